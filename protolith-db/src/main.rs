@@ -56,7 +56,7 @@ async fn main() {
     let shutdown_grace_period = config.shutdown_grace_period;
     // let databases = config.databases;
     let app = match config
-        .build(trace).await
+        .build(trace.clone()).await
     {
         Ok(app) => app,
         Err(e) => {
@@ -82,6 +82,7 @@ async fn main() {
             "Graceful shutdown did not complete in {shutdown_grace_period:?}, terminating now"
         ),
     }
+    trace.shutdown();
 
     println!("Bye Bye :)");
 }
@@ -7,13 +7,24 @@ use std::{
 use protolith_api::service::MetadataSvc;
 pub use protolith_api::{
     pbjson_types::Empty,
-    protolith::services::v1::{
-        admin_service_client::AdminServiceClient, CreateDatabaseRequest, CreateDatabaseResponse,
-        ListDatabasesResponse,
+    protolith::{
+        services::v1::{
+            admin_service_client::AdminServiceClient, BackgroundWorkRequest, BackgroundWorkResponse,
+            ChangePasswordRequest, CompactRequest, FlushRequest, GetMaintenanceOperationRequest,
+            GetPropertiesRequest, GetPropertiesResponse, ListMaintenanceOperationsResponse,
+            MaintenanceOperation, MaintenanceOperationResponse, CreateApiKeyRequest, CreateApiKeyResponse, CreateDatabaseRequest, CreateDatabaseResponse, CreateUserRequest, DeleteUserRequest,
+            GrantPermissionRequest, ListApiKeysRequest, ListApiKeysResponse,
+            ListAuditEventsRequest, ListAuditEventsResponse,
+            ListDatabasesResponse, ListPermissionsRequest,
+            ListUsersResponse, PermissionsResponse, RevokePermissionRequest, RevokeSessionsRequest,
+            RevokeApiKeyRequest, RevokeApiKeyResponse, RevokeSessionsResponse,
+            SetUserDisabledRequest, UserResponse,
+        },
+        types::v1::{Grant, Role},
     },
 };
 use protolith_error::{Error, Result};
-use tonic::{transport::Channel, IntoRequest};
+use tonic::{codegen::http::HeaderValue, transport::Channel, IntoRequest};
 use tracing::debug;
 
 #[derive(Debug, Clone)]
@@ -36,6 +47,21 @@ impl Client {
         }
     }
 
+    /// Creates a client authenticating with an API key rather than a session.
+    pub fn with_api_key(channel: Channel, api_key: &str) -> Result<Self> {
+        const VERSION: Option<&str> = option_env!("CARGO_PKG_VERSION");
+
+        let api_key: HeaderValue = api_key.parse()?;
+        let channel = tower::ServiceBuilder::new()
+            .layer_fn(|service| MetadataSvc::new(service, VERSION.unwrap().to_owned()).with_api_key(api_key.clone()))
+            .service(channel);
+        let admin_client = AdminServiceClient::new(channel);
+        Ok(Self {
+            admin_client,
+            session: String::new(),
+        })
+    }
+
     pub async fn create_database(
         &mut self,
         name: &str,
@@ -52,8 +78,7 @@ impl Client {
         .into_request();
         request
             .metadata_mut()
-            .insert("protolith-session", self.session.parse().unwrap())
-            .unwrap();
+            .insert("protolith-session", self.session.parse().unwrap());
         let response = self.admin_client.create_database(request).await?;
         let md = response.metadata();
         debug!(metadata = ?md, "Incoming metadata");
@@ -64,10 +89,295 @@ impl Client {
         let mut request = Empty::default().into_request();
         request
             .metadata_mut()
-            .insert("protolith-session", self.session.parse().unwrap())
-            .unwrap();
+            .insert("protolith-session", self.session.parse().unwrap());
 
         let response = self.admin_client.list_databases(request).await?;
         Ok(response.into_inner())
     }
+
+    pub async fn grant_permission(
+        &mut self,
+        username: &str,
+        role: Role,
+        database: &str,
+        collection: &str,
+        decrypt: bool,
+    ) -> Result<PermissionsResponse, Error> {
+        let mut request = GrantPermissionRequest {
+            username: username.to_owned(),
+            grant: Some(Grant {
+                role: role.into(),
+                database: database.to_owned(),
+                collection: collection.to_owned(),
+                decrypt,
+            }),
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.grant_permission(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn revoke_permission(
+        &mut self,
+        username: &str,
+        role: Role,
+        database: &str,
+        collection: &str,
+        decrypt: bool,
+    ) -> Result<PermissionsResponse, Error> {
+        let mut request = RevokePermissionRequest {
+            username: username.to_owned(),
+            grant: Some(Grant {
+                role: role.into(),
+                database: database.to_owned(),
+                collection: collection.to_owned(),
+                decrypt,
+            }),
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.revoke_permission(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn list_permissions(&mut self, username: &str) -> Result<PermissionsResponse, Error> {
+        let mut request = ListPermissionsRequest {
+            username: username.to_owned(),
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.list_permissions(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn create_user(
+        &mut self,
+        username: &str,
+        password: &str,
+        grants: Vec<Grant>,
+    ) -> Result<UserResponse, Error> {
+        let mut request = CreateUserRequest {
+            username: username.to_owned(),
+            password: password.to_owned(),
+            grants,
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.create_user(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn delete_user(&mut self, username: &str) -> Result<UserResponse, Error> {
+        let mut request = DeleteUserRequest {
+            username: username.to_owned(),
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.delete_user(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn change_password(
+        &mut self,
+        username: &str,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<UserResponse, Error> {
+        let mut request = ChangePasswordRequest {
+            username: username.to_owned(),
+            old_password: old_password.to_owned(),
+            new_password: new_password.to_owned(),
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.change_password(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn set_user_disabled(
+        &mut self,
+        username: &str,
+        disabled: bool,
+    ) -> Result<UserResponse, Error> {
+        let mut request = SetUserDisabledRequest {
+            username: username.to_owned(),
+            disabled,
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.set_user_disabled(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn list_users(&mut self) -> Result<ListUsersResponse, Error> {
+        let mut request = Empty::default().into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.list_users(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn create_api_key(
+        &mut self,
+        username: &str,
+        name: &str,
+        databases: Vec<String>,
+    ) -> Result<CreateApiKeyResponse, Error> {
+        let mut request = CreateApiKeyRequest {
+            username: username.to_owned(),
+            name: name.to_owned(),
+            databases,
+            ..Default::default()
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.create_api_key(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn list_api_keys(&mut self, username: &str) -> Result<ListApiKeysResponse, Error> {
+        let mut request = ListApiKeysRequest {
+            username: username.to_owned(),
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.list_api_keys(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn revoke_api_key(&mut self, id: &str) -> Result<RevokeApiKeyResponse, Error> {
+        let mut request = RevokeApiKeyRequest { id: id.to_owned() }.into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.revoke_api_key(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn revoke_sessions(&mut self, username: &str) -> Result<RevokeSessionsResponse, Error> {
+        let mut request = RevokeSessionsRequest {
+            username: username.to_owned(),
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.revoke_sessions(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn list_audit_events(
+        &mut self,
+        request: ListAuditEventsRequest,
+    ) -> Result<ListAuditEventsResponse, Error> {
+        let mut request = request.into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.list_audit_events(request).await?;
+        Ok(response.into_inner())
+    }
+
+    /// Starts compacting `collection`, or the whole database when empty.
+    pub async fn compact(&mut self, database: &str, collection: &str) -> Result<MaintenanceOperationResponse, Error> {
+        let mut request = CompactRequest {
+            database: database.to_owned(),
+            collection: collection.to_owned(),
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.compact(request).await?;
+        Ok(response.into_inner())
+    }
+
+    /// Starts flushing `collection`, or the whole database when empty.
+    pub async fn flush(&mut self, database: &str, collection: &str) -> Result<MaintenanceOperationResponse, Error> {
+        let mut request = FlushRequest {
+            database: database.to_owned(),
+            collection: collection.to_owned(),
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.flush(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn get_maintenance_operation(&mut self, id: &str) -> Result<MaintenanceOperation, Error> {
+        let mut request = GetMaintenanceOperationRequest { id: id.to_owned() }.into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.get_maintenance_operation(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn list_maintenance_operations(&mut self) -> Result<ListMaintenanceOperationsResponse, Error> {
+        let mut request = Empty::default().into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.list_maintenance_operations(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn pause_background_work(&mut self, database: &str) -> Result<BackgroundWorkResponse, Error> {
+        let mut request = BackgroundWorkRequest { database: database.to_owned() }.into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.pause_background_work(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn resume_background_work(&mut self, database: &str) -> Result<BackgroundWorkResponse, Error> {
+        let mut request = BackgroundWorkRequest { database: database.to_owned() }.into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.resume_background_work(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn get_properties(
+        &mut self,
+        database: &str,
+        collection: &str,
+        properties: &[&str],
+    ) -> Result<GetPropertiesResponse, Error> {
+        let mut request = GetPropertiesRequest {
+            database: database.to_owned(),
+            collection: collection.to_owned(),
+            properties: properties.iter().map(|property| property.to_string()).collect(),
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let response = self.admin_client.get_properties(request).await?;
+        Ok(response.into_inner())
+    }
 }
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 mod client;
+pub mod password;
 mod service;
 
 use protolith_engine::Engine;
@@ -11,13 +12,13 @@ type AdminServiceType<E> = admin_service_server::AdminServiceServer<ProtolithAdm
 
 #[derive(Debug, Clone)]
 pub struct Config {
-
+    pub password_policy: password::PasswordPolicy,
 }
 
 impl Config {
     pub fn build<E: Engine>(self, engine: Arc<E>, drain: drain::Watch ) -> Result<Admin<E>, Error> {
         Ok(Admin {
-            admin: ProtolithAdminService::new(engine),
+            admin: ProtolithAdminService::new(engine).with_password_policy(self.password_policy),
             drain
         })
     }
@@ -29,6 +30,13 @@ pub struct Admin<E: Engine> {
     pub drain: drain::Watch,
 }
 
+impl<E: Engine + Clone> Admin<E> {
+    /// The service handling the requests, for those served over HTTP.
+    pub fn handler(&self) -> ProtolithAdminService<E> {
+        self.admin.clone()
+    }
+}
+
 impl<E: Engine> Admin<E> {
     pub fn service(self, max_message_size: usize) -> AdminServiceType<E> {
         admin_service_server::AdminServiceServer::new(self.admin)
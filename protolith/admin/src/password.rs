@@ -0,0 +1,64 @@
+//! The rules the passwords of created users and changed passwords follow.
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    /// The minimum number of characters.
+    pub min_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    /// Requires a character which is neither a letter nor a digit.
+    pub require_symbol: bool,
+}
+
+impl PasswordPolicy {
+    /// Checks `password`, failing with the rules it breaks.
+    pub fn check(&self, password: &str) -> Result<(), String> {
+        let mut broken = Vec::new();
+        if password.chars().count() < self.min_length {
+            broken.push(format!("at least {} characters", self.min_length));
+        }
+        let rules = [
+            (self.require_lowercase, "a lowercase letter", char::is_lowercase as fn(char) -> bool),
+            (self.require_uppercase, "an uppercase letter", char::is_uppercase),
+            (self.require_digit, "a digit", |c| c.is_ascii_digit()),
+            (self.require_symbol, "a symbol", |c| !c.is_alphanumeric()),
+        ];
+        for (required, rule, matches) in rules {
+            if required && !password.chars().any(matches) {
+                broken.push(rule.to_string());
+            }
+        }
+        if broken.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("password must contain {}", broken.join(", ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules() {
+        let policy = PasswordPolicy {
+            min_length: 8,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+        };
+        assert!(policy.check("Passw0rd!").is_ok());
+        assert_eq!(
+            policy.check("passw0rd").unwrap_err(),
+            "password must contain an uppercase letter, a symbol",
+        );
+        assert_eq!(
+            policy.check("Pa0!").unwrap_err(),
+            "password must contain at least 8 characters",
+        );
+        assert!(PasswordPolicy::default().check("x").is_ok());
+    }
+}
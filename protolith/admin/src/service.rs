@@ -1,16 +1,38 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use protolith_api::{
     pbjson_types::Empty,
-    protolith::services::v1::{
-        admin_service_server::AdminService, CreateCollectionRequest, CreateCollectionResponse,
-        CreateDatabaseRequest, CreateDatabaseResponse, ListDatabasesResponse,
+    protolith::{
+        services::v1::{
+            admin_service_server::AdminService, ApiKeyInfo, BackgroundWorkRequest,
+            BackgroundWorkResponse, ChangePasswordRequest, CompactRequest, FlushRequest,
+            GetMaintenanceOperationRequest, GetPropertiesRequest, GetPropertiesResponse,
+            ListMaintenanceOperationsResponse, MaintenanceOperation, MaintenanceOperationResponse,
+            CreateApiKeyRequest, CreateApiKeyResponse, CreateCollectionRequest,
+            CreateCollectionResponse, CreateDatabaseRequest, CreateDatabaseResponse,
+            CreateUserRequest, DeleteUserRequest, GrantPermissionRequest, ListDatabasesResponse,
+            ListApiKeysRequest, ListApiKeysResponse, ListAuditEventsRequest, ListAuditEventsResponse, ListPermissionsRequest, ListUsersResponse,
+            PermissionsResponse, RevokeApiKeyRequest, RevokeApiKeyResponse, RevokePermissionRequest, RevokeSessionsRequest, RevokeSessionsResponse, SetUserDisabledRequest, UserInfo, UserResponse,
+        },
+        metastore::v1::{ApiKey, User},
+        types::v1::{ApiOp, Grant, Op, OpStatus},
     },
 };
 use tracing::{info_span, Instrument, Span};
 
-use protolith_engine::Engine;
+use protolith_engine::{
+    audit,
+    rbac::{self, Permission, Principal},
+    Engine,
+};
 use tonic::{Request, Response, Status};
+use crate::password::PasswordPolicy;
+
+const DEFAULT_AUDIT_EVENTS_LIMIT: usize = 100;
+const MAX_AUDIT_EVENTS_LIMIT: usize = 1000;
 
 pub enum AdminRequest {
     ListDatabase,
@@ -20,6 +42,7 @@ pub enum AdminRequest {
 #[derive(Debug, Clone)]
 pub struct ProtolithAdminService<E: Engine> {
     engine: Arc<E>,
+    password_policy: PasswordPolicy,
 }
 
 impl<E: Engine> ProtolithAdminService<E> {
@@ -33,7 +56,15 @@ impl<E: Engine> ProtolithAdminService<E> {
 
 impl<E: Engine> ProtolithAdminService<E> {
     pub fn new(engine: Arc<E>) -> Self {
-        Self { engine }
+        Self {
+            engine,
+            password_policy: PasswordPolicy::default(),
+        }
+    }
+
+    pub fn with_password_policy(mut self, password_policy: PasswordPolicy) -> Self {
+        self.password_policy = password_policy;
+        self
     }
 }
 
@@ -43,7 +74,10 @@ impl<E: Engine> AdminService for ProtolithAdminService<E> {
         &self,
         request: Request<CreateDatabaseRequest>,
     ) -> Result<Response<CreateDatabaseResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, "", "").await?;
         let req = request.into_inner();
+        audit::record_collection(&req.name, "");
         let span = self.build_client_request_span(AdminRequest::CreateDatabase(req.clone()));
         let db_response = self
             .engine
@@ -72,20 +106,31 @@ impl<E: Engine> AdminService for ProtolithAdminService<E> {
         &self,
         request: Request<CreateDatabaseRequest>,
     ) -> Result<Response<CreateDatabaseResponse>, Status> {
-        let _req = request.into_inner();
+        let principal = request.extensions().get::<Principal>().cloned();
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, "", "").await?;
+        let req = request.into_inner();
+        audit::record_collection(&req.name, "");
 
         Ok(Response::new(CreateDatabaseResponse::default()))
     }
 
     async fn list_databases(
         &self,
-        _: Request<Empty>,
+        request: Request<Empty>,
     ) -> Result<Response<ListDatabasesResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let grants = rbac::grants(self.engine.as_ref(), principal.as_ref()).await?;
         let span = self.build_client_request_span(AdminRequest::ListDatabase);
         let resp = self.engine.list_databases().instrument(span).await;
         match resp {
             Err(e) => Err(Status::internal(e.to_string())),
-            Ok(databases) => Ok(Response::new(databases)),
+            Ok(mut databases) => {
+                // Users only see the databases they were granted something on.
+                databases
+                    .databases
+                    .retain(|db| rbac::can_access(&grants, &db.name));
+                Ok(Response::new(databases))
+            }
         }
     }
 
@@ -93,7 +138,10 @@ impl<E: Engine> AdminService for ProtolithAdminService<E> {
         &self,
         request: Request<CreateCollectionRequest>,
     ) -> Result<Response<CreateCollectionResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
         let req = request.into_inner();
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, &req.database, "").await?;
+        audit::record_collection(&req.database, &req.collection);
         let rep = self
             .engine
             .create_collection(req.database, req.collection, req.key, 1)
@@ -112,4 +160,579 @@ impl<E: Engine> AdminService for ProtolithAdminService<E> {
             })?;
         Ok(Response::new(rep))
     }
+
+    async fn grant_permission(
+        &self,
+        request: Request<GrantPermissionRequest>,
+    ) -> Result<Response<PermissionsResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        audit::record_key(&req.username);
+        let grant = req.grant.unwrap_or_default();
+        // Admins of a scope may grant roles within it.
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, &grant.database, &grant.collection).await?;
+        let grants = self
+            .engine
+            .grant_permission(req.username.clone(), grant.clone())
+            .await
+            .map_err(user_status)?;
+        Ok(Response::new(PermissionsResponse {
+            op: Some(ApiOp {
+                description: format!("granted {} to {}", describe(&grant), req.username),
+                r#type: Op::Update.into(),
+                status: OpStatus::Success.into(),
+                ..Default::default()
+            }),
+            username: req.username,
+            grants,
+        }))
+    }
+
+    async fn revoke_permission(
+        &self,
+        request: Request<RevokePermissionRequest>,
+    ) -> Result<Response<PermissionsResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        audit::record_key(&req.username);
+        let grant = req.grant.unwrap_or_default();
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, &grant.database, &grant.collection).await?;
+        let grants = self
+            .engine
+            .revoke_permission(req.username.clone(), grant.clone())
+            .await
+            .map_err(user_status)?;
+        Ok(Response::new(PermissionsResponse {
+            op: Some(ApiOp {
+                description: format!("revoked {} from {}", describe(&grant), req.username),
+                r#type: Op::Update.into(),
+                status: OpStatus::Success.into(),
+                ..Default::default()
+            }),
+            username: req.username,
+            grants,
+        }))
+    }
+
+    async fn list_permissions(
+        &self,
+        request: Request<ListPermissionsRequest>,
+    ) -> Result<Response<PermissionsResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        audit::record_key(&req.username);
+        // Everyone can list their own permissions.
+        let own = principal.as_ref().is_some_and(|p| p.username == req.username);
+        if !own {
+            rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, "", "").await?;
+        }
+        let grants = self
+            .engine
+            .list_permissions(req.username.clone())
+            .await
+            .map_err(user_status)?;
+        Ok(Response::new(PermissionsResponse {
+            username: req.username,
+            grants,
+            ..Default::default()
+        }))
+    }
+
+    async fn create_user(
+        &self,
+        request: Request<CreateUserRequest>,
+    ) -> Result<Response<UserResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, "", "").await?;
+        let req = request.into_inner();
+        audit::record_key(&req.username);
+        if req.username.is_empty() || req.password.is_empty() {
+            return Err(Status::invalid_argument("username and password are required"));
+        }
+        self.password_policy.check(&req.password).map_err(Status::invalid_argument)?;
+        let user = self
+            .engine
+            .create_user(req.username, req.password, req.grants)
+            .await
+            .map_err(user_status)?;
+        let description = format!("created user {}", user.username);
+        Ok(user_response(user, Op::Create, description))
+    }
+
+    async fn delete_user(
+        &self,
+        request: Request<DeleteUserRequest>,
+    ) -> Result<Response<UserResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, "", "").await?;
+        let req = request.into_inner();
+        audit::record_key(&req.username);
+        if principal.is_some_and(|p| p.username == req.username) {
+            return Err(Status::failed_precondition("users can not delete themselves"));
+        }
+        let user = self
+            .engine
+            .delete_user(req.username)
+            .await
+            .map_err(user_status)?;
+        self.engine
+            .revoke_sessions(user.username.clone())
+            .await
+            .map_err(user_status)?;
+        self.engine
+            .revoke_api_keys(user.username.clone())
+            .await
+            .map_err(user_status)?;
+        let description = format!("deleted user {}", user.username);
+        Ok(user_response(user, Op::Delete, description))
+    }
+
+    async fn change_password(
+        &self,
+        request: Request<ChangePasswordRequest>,
+    ) -> Result<Response<UserResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        audit::record_key(&req.username);
+        if req.new_password.is_empty() {
+            return Err(Status::invalid_argument("new_password is required"));
+        }
+        self.password_policy.check(&req.new_password).map_err(Status::invalid_argument)?;
+        // Users change their own password by proving they know the current
+        // one, admins can reset anyone's.
+        let own = principal.as_ref().is_some_and(|p| p.username == req.username);
+        if own {
+            let verified = self
+                .engine
+                .verify_password(req.username.clone(), req.old_password)
+                .await
+                .map_err(user_status)?;
+            if !verified {
+                return Err(Status::permission_denied("old_password does not match"));
+            }
+        } else {
+            rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, "", "").await?;
+        }
+        let user = self
+            .engine
+            .change_password(req.username, req.new_password)
+            .await
+            .map_err(user_status)?;
+        let description = format!("changed the password of {}", user.username);
+        Ok(user_response(user, Op::Update, description))
+    }
+
+    async fn set_user_disabled(
+        &self,
+        request: Request<SetUserDisabledRequest>,
+    ) -> Result<Response<UserResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, "", "").await?;
+        let req = request.into_inner();
+        audit::record_key(&req.username);
+        if principal.is_some_and(|p| p.username == req.username) {
+            return Err(Status::failed_precondition("users can not disable themselves"));
+        }
+        let user = self
+            .engine
+            .set_user_disabled(req.username, req.disabled)
+            .await
+            .map_err(user_status)?;
+        if user.disabled {
+            self.engine
+                .revoke_sessions(user.username.clone())
+                .await
+                .map_err(user_status)?;
+        }
+        let state = if user.disabled { "disabled" } else { "enabled" };
+        let description = format!("{} user {}", state, user.username);
+        Ok(user_response(user, Op::Update, description))
+    }
+
+    async fn list_users(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<ListUsersResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, "", "").await?;
+        let users = self
+            .engine
+            .list_users()
+            .await
+            .map_err(user_status)?;
+        Ok(Response::new(ListUsersResponse {
+            users: users.into_iter().map(user_info).collect(),
+        }))
+    }
+
+    async fn revoke_sessions(
+        &self,
+        request: Request<RevokeSessionsRequest>,
+    ) -> Result<Response<RevokeSessionsResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        audit::record_key(&req.username);
+        // Everyone can end their own sessions.
+        let own = principal.as_ref().is_some_and(|p| p.username == req.username);
+        if !own {
+            rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, "", "").await?;
+        }
+        let revoked = self
+            .engine
+            .revoke_sessions(req.username.clone())
+            .await
+            .map_err(user_status)?;
+        Ok(Response::new(RevokeSessionsResponse {
+            op: Some(ApiOp {
+                description: format!("revoked {} sessions of {}", revoked, req.username),
+                r#type: Op::Delete.into(),
+                status: OpStatus::Success.into(),
+                ..Default::default()
+            }),
+            username: req.username,
+            revoked: revoked as u64,
+        }))
+    }
+
+    async fn create_api_key(
+        &self,
+        request: Request<CreateApiKeyRequest>,
+    ) -> Result<Response<CreateApiKeyResponse>, Status> {
+        let principal = request
+            .extensions()
+            .get::<Principal>()
+            .cloned()
+            .ok_or_else(|| Status::unauthenticated("request is not authenticated"))?;
+        let mut req = request.into_inner();
+        if req.username.is_empty() {
+            req.username = principal.username.clone();
+        }
+        audit::record_key(&req.username);
+        // Everyone can create keys for themselves.
+        if principal.username != req.username {
+            rbac::authorize(self.engine.as_ref(), Some(&principal), Permission::Admin, "", "").await?;
+        }
+        // A restricted credential only creates keys within its own scope.
+        if !principal.databases.is_empty() {
+            if req.databases.is_empty() {
+                req.databases = principal.databases.clone();
+            } else if let Some(database) = req.databases.iter().find(|db| !principal.databases.contains(db)) {
+                return Err(Status::permission_denied(format!(
+                    "credential of {} is not allowed on database {}",
+                    principal.username, database
+                )));
+            }
+        }
+        if let Some(expires_at) = &req.expires_at {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            if expires_at.seconds <= now.as_secs() as i64 {
+                return Err(Status::invalid_argument("expires_at must be in the future"));
+            }
+        }
+        let (api_key, key) = self
+            .engine
+            .create_api_key(req.username, req.name, req.expires_at, req.databases)
+            .await
+            .map_err(user_status)?;
+        Ok(Response::new(CreateApiKeyResponse {
+            op: Some(ApiOp {
+                description: format!("created api key {} of {}", api_key.id, api_key.username),
+                r#type: Op::Create.into(),
+                status: OpStatus::Success.into(),
+                ..Default::default()
+            }),
+            api_key: Some(api_key_info(api_key)),
+            key,
+        }))
+    }
+
+    async fn list_api_keys(
+        &self,
+        request: Request<ListApiKeysRequest>,
+    ) -> Result<Response<ListApiKeysResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let mut req = request.into_inner();
+        if req.username.is_empty() {
+            req.username = principal.as_ref().map(|p| p.username.clone()).unwrap_or_default();
+        }
+        audit::record_key(&req.username);
+        // Everyone can list their own keys.
+        let own = principal.as_ref().is_some_and(|p| p.username == req.username);
+        if !own {
+            rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, "", "").await?;
+        }
+        let api_keys = self
+            .engine
+            .list_api_keys(Some(req.username))
+            .await
+            .map_err(user_status)?;
+        Ok(Response::new(ListApiKeysResponse {
+            api_keys: api_keys.into_iter().map(api_key_info).collect(),
+        }))
+    }
+
+    async fn revoke_api_key(
+        &self,
+        request: Request<RevokeApiKeyRequest>,
+    ) -> Result<Response<RevokeApiKeyResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        audit::record_key(&req.id);
+        // Everyone can revoke their own keys.
+        let own = match &principal {
+            Some(principal) => self
+                .engine
+                .list_api_keys(Some(principal.username.clone()))
+                .await
+                .map_err(user_status)?
+                .iter()
+                .any(|api_key| api_key.id == req.id),
+            None => false,
+        };
+        if !own {
+            rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, "", "").await?;
+        }
+        let api_key = self.engine.revoke_api_key(req.id).await.map_err(user_status)?;
+        Ok(Response::new(RevokeApiKeyResponse {
+            op: Some(ApiOp {
+                description: format!("revoked api key {} of {}", api_key.id, api_key.username),
+                r#type: Op::Delete.into(),
+                status: OpStatus::Success.into(),
+                ..Default::default()
+            }),
+            api_key: Some(api_key_info(api_key)),
+        }))
+    }
+
+    async fn list_audit_events(
+        &self,
+        request: Request<ListAuditEventsRequest>,
+    ) -> Result<Response<ListAuditEventsResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, "", "").await?;
+        let req = request.into_inner();
+        let username = Some(req.username).filter(|username| !username.is_empty());
+        let limit = match req.limit {
+            0 => DEFAULT_AUDIT_EVENTS_LIMIT,
+            limit => (limit as usize).min(MAX_AUDIT_EVENTS_LIMIT),
+        };
+        let events = self
+            .engine
+            .list_audit_events(req.since, req.until, username, limit)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(ListAuditEventsResponse { events }))
+    }
+
+    async fn compact(
+        &self,
+        request: Request<CompactRequest>,
+    ) -> Result<Response<MaintenanceOperationResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, &req.database, &req.collection).await?;
+        audit::record_collection(&req.database, &req.collection);
+        let operation = self
+            .engine
+            .compact(req.database, Some(req.collection).filter(|c| !c.is_empty()))
+            .await
+            .map_err(maintenance_status)?;
+        Ok(maintenance_response(operation, "compacting"))
+    }
+
+    async fn flush(
+        &self,
+        request: Request<FlushRequest>,
+    ) -> Result<Response<MaintenanceOperationResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, &req.database, &req.collection).await?;
+        audit::record_collection(&req.database, &req.collection);
+        let operation = self
+            .engine
+            .flush(req.database, Some(req.collection).filter(|c| !c.is_empty()))
+            .await
+            .map_err(maintenance_status)?;
+        Ok(maintenance_response(operation, "flushing"))
+    }
+
+    async fn get_maintenance_operation(
+        &self,
+        request: Request<GetMaintenanceOperationRequest>,
+    ) -> Result<Response<MaintenanceOperation>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        let operation = self
+            .engine
+            .get_maintenance_operation(req.id)
+            .await
+            .map_err(maintenance_status)?;
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, &operation.database, &operation.collection).await?;
+        Ok(Response::new(operation))
+    }
+
+    async fn list_maintenance_operations(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<ListMaintenanceOperationsResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let grants = rbac::grants(self.engine.as_ref(), principal.as_ref()).await?;
+        let mut operations = self
+            .engine
+            .list_maintenance_operations()
+            .await
+            .map_err(maintenance_status)?;
+        // Users only see the operations on the databases they administer.
+        operations.retain(|operation| {
+            rbac::permits(&grants, Permission::Admin, &operation.database, &operation.collection)
+        });
+        Ok(Response::new(ListMaintenanceOperationsResponse { operations }))
+    }
+
+    async fn pause_background_work(
+        &self,
+        request: Request<BackgroundWorkRequest>,
+    ) -> Result<Response<BackgroundWorkResponse>, Status> {
+        self.set_background_work(request, true).await
+    }
+
+    async fn resume_background_work(
+        &self,
+        request: Request<BackgroundWorkRequest>,
+    ) -> Result<Response<BackgroundWorkResponse>, Status> {
+        self.set_background_work(request, false).await
+    }
+
+    async fn get_properties(
+        &self,
+        request: Request<GetPropertiesRequest>,
+    ) -> Result<Response<GetPropertiesResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, &req.database, &req.collection).await?;
+        if req.properties.is_empty() {
+            return Err(Status::invalid_argument("properties are required"));
+        }
+        let column_families = self
+            .engine
+            .get_properties(req.database.clone(), Some(req.collection).filter(|c| !c.is_empty()), req.properties)
+            .await
+            .map_err(maintenance_status)?;
+        Ok(Response::new(GetPropertiesResponse {
+            database: req.database,
+            column_families,
+        }))
+    }
+}
+
+impl<E: Engine> ProtolithAdminService<E> {
+    async fn set_background_work(
+        &self,
+        request: Request<BackgroundWorkRequest>,
+        paused: bool,
+    ) -> Result<Response<BackgroundWorkResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        rbac::authorize(self.engine.as_ref(), principal.as_ref(), Permission::Admin, &req.database, "").await?;
+        audit::record_collection(&req.database, "");
+        self.engine
+            .pause_background_work(req.database.clone(), paused)
+            .await
+            .map_err(maintenance_status)?;
+        let state = if paused { "paused" } else { "resumed" };
+        Ok(Response::new(BackgroundWorkResponse {
+            op: Some(ApiOp {
+                description: format!("{} background work of database {}", state, req.database),
+                r#type: Op::Update.into(),
+                status: OpStatus::Success.into(),
+                ..Default::default()
+            }),
+            database: req.database,
+            paused,
+        }))
+    }
+}
+
+fn user_status(err: protolith_engine::EngineError) -> Status {
+    match err {
+        protolith_engine::EngineError::OpError(protolith_engine::OpError::UserNotFound(user)) => {
+            Status::not_found(format!("user {} not found", user))
+        }
+        protolith_engine::EngineError::OpError(protolith_engine::OpError::UserAlreadyExists(user)) => {
+            Status::already_exists(format!("user {} already exists", user))
+        }
+        protolith_engine::EngineError::OpError(protolith_engine::OpError::ApiKeyNotFound(id)) => {
+            Status::not_found(format!("api key {} not found", id))
+        }
+        err => Status::internal(err.to_string()),
+    }
+}
+
+fn maintenance_status(err: protolith_engine::EngineError) -> Status {
+    match err {
+        protolith_engine::EngineError::OpError(err @ protolith_engine::OpError::DatabaseNotFound(_))
+        | protolith_engine::EngineError::OpError(err @ protolith_engine::OpError::CollectionNotFound(..))
+        | protolith_engine::EngineError::OpError(err @ protolith_engine::OpError::OperationNotFound(_)) => {
+            Status::not_found(err.to_string())
+        }
+        protolith_engine::EngineError::OpError(err @ protolith_engine::OpError::BackgroundWorkPaused(_)) => {
+            Status::failed_precondition(err.to_string())
+        }
+        err => Status::internal(err.to_string()),
+    }
+}
+
+fn maintenance_response(operation: MaintenanceOperation, action: &str) -> Response<MaintenanceOperationResponse> {
+    let target = if operation.collection.is_empty() {
+        format!("database {}", operation.database)
+    } else {
+        format!("collection {} of database {}", operation.collection, operation.database)
+    };
+    Response::new(MaintenanceOperationResponse {
+        op: Some(ApiOp {
+            description: format!("{} {} as operation {}", action, target, operation.id),
+            r#type: Op::Update.into(),
+            status: OpStatus::Pending.into(),
+            ..Default::default()
+        }),
+        operation: Some(operation),
+    })
+}
+
+fn user_info(user: User) -> UserInfo {
+    UserInfo {
+        username: user.username,
+        grants: user.grants,
+        created_at: user.created_at,
+        disabled: user.disabled,
+    }
+}
+
+fn api_key_info(api_key: ApiKey) -> ApiKeyInfo {
+    ApiKeyInfo {
+        id: api_key.id,
+        username: api_key.username,
+        name: api_key.name,
+        created_at: api_key.created_at,
+        expires_at: api_key.expires_at,
+        databases: api_key.databases,
+    }
+}
+
+fn user_response(user: User, r#type: Op, description: String) -> Response<UserResponse> {
+    Response::new(UserResponse {
+        user: Some(user_info(user)),
+        op: Some(ApiOp {
+            description,
+            r#type: r#type.into(),
+            status: OpStatus::Success.into(),
+            ..Default::default()
+        }),
+    })
+}
+
+fn describe(grant: &Grant) -> String {
+    let database = if grant.database.is_empty() { "*" } else { &grant.database };
+    let collection = if grant.collection.is_empty() { "*" } else { &grant.collection };
+    format!("{:?} on {}/{}", grant.role(), database, collection)
 }
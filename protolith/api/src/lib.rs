@@ -7,8 +7,13 @@ pub use prost_wkt_types;
 pub use api::protolith;
 pub use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor};
 
+/// The encoded `FileDescriptorSet` of the ProtolithDB API.
+pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/descriptor.bin"));
+
 pub mod service {
     pub const HEADER_USER_AGENT: &str = "protolith-user-agent";
+    /// The header carrying the keys created with `AdminService.CreateApiKey`.
+    pub const HEADER_PROTOLITH_API_KEY: &str = "x-protolith-api-key";
     // use hyper::http::{Request, Response};
     use hyper::header::HeaderValue;
     use std::future::Future;
@@ -24,11 +29,25 @@ pub mod service {
     pub struct MetadataSvc {
         inner: Channel,
         version: String,
+        api_key: Option<HeaderValue>,
     }
 
     impl MetadataSvc {
         pub fn new(inner: Channel, version: String) -> Self {
-            MetadataSvc { inner, version }
+            // Clients which do not export spans would otherwise inject
+            // nothing, with the no-op propagator installed by default.
+            #[cfg(feature = "otel")]
+            {
+                static PROPAGATOR: std::sync::Once = std::sync::Once::new();
+                PROPAGATOR.call_once(protolith_tracing::otel::install_propagator);
+            }
+            MetadataSvc { inner, version, api_key: None }
+        }
+
+        /// Authenticates every request with `api_key`.
+        pub fn with_api_key(mut self, api_key: HeaderValue) -> Self {
+            self.api_key = Some(api_key);
+            self
         }
     }
 
@@ -49,6 +68,11 @@ pub mod service {
             let clone = self.inner.clone();
             let mut inner = std::mem::replace(&mut self.inner, clone);
             req.headers_mut().insert(HEADER_USER_AGENT, HeaderValue::from_str(&format!("protolith@rust/{}", self.version)).unwrap());
+            if let Some(api_key) = &self.api_key {
+                req.headers_mut().insert(HEADER_PROTOLITH_API_KEY, api_key.clone());
+            }
+            // Continues the trace of the caller on the server.
+            protolith_tracing::otel::inject(req.headers_mut());
             Box::pin(async move {
                 // Do extra async work here...
                 let response = inner.call(req).await?;
@@ -0,0 +1,217 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    body::{self, Bytes},
+    header,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use percent_encoding::percent_decode_str;
+use protolith_auth::Auth;
+use protolith_core::{error::Error, trace};
+use protolith_engine::ProtolithDbEngine;
+use tracing::{debug, error, info};
+
+use crate::metrics::Metrics;
+
+/// The content type of the Prometheus text format.
+const CONTENT_TYPE_METRICS: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// The content type of log streams, a JSON object per line.
+const CONTENT_TYPE_LOGS: &str = "application/x-ndjson";
+
+/// Serves the operational endpoints on their own address, apart from the API
+/// served to clients.
+///
+/// | Route           | Description                                          |
+/// |-----------------|------------------------------------------------------|
+/// | `GET /metrics`  | The metrics in the Prometheus format                 |
+/// | `GET /logging`  | The current log filter                               |
+/// | `PUT /logging`  | Sets the log filter to the request body              |
+/// | `GET /logs`     | Streams the logs matching the `filter` query as JSON |
+/// | `POST /logs`    | Streams the logs matching the request body as JSON   |
+///
+/// The log routes answer `404 Not Found` unless `PROTOLITH_ADMIN_LOGS` is
+/// set: the logs carry usernames, keys and documents, and changing the filter
+/// can flood the process logs.
+///
+/// Filters use the `EnvFilter` syntax of `PROTOLITH_LOG`, such as
+/// `info,protolith_engine=debug`. Log streams are separate from the process
+/// logs and their filter, they end when the client disconnects or the server
+/// shuts down.
+///
+/// The admin server does not authenticate requests, it is meant to be bound
+/// to an address only reachable by the operators and their scrapers.
+#[derive(Clone)]
+pub struct AdminServer {
+    engine: ProtolithDbEngine,
+    auth: Arc<Auth<ProtolithDbEngine>>,
+    trace: trace::Handle,
+    metrics: Metrics,
+    logs: bool,
+}
+
+impl AdminServer {
+    pub fn new(
+        engine: ProtolithDbEngine,
+        auth: Arc<Auth<ProtolithDbEngine>>,
+        trace: trace::Handle,
+        metrics: Metrics,
+        logs: bool,
+    ) -> Self {
+        Self {
+            engine,
+            auth,
+            trace,
+            metrics,
+            logs,
+        }
+    }
+
+    pub async fn serve(self, addr: SocketAddr, drain: drain::Watch) -> Result<(), Error> {
+        let shutdown = drain.clone();
+        let make_service = make_service_fn(move |_| {
+            let server = self.clone();
+            let drain = drain.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| server.clone().call(req, drain.clone())))
+            }
+        });
+        info!(?addr, "Serving admin endpoints at");
+        Server::bind(&addr)
+            .serve(make_service)
+            .with_graceful_shutdown(async move {
+                let release = shutdown.signaled().await;
+                drop(release)
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn call(self, req: Request<Body>, drain: drain::Watch) -> Result<Response<Body>, Infallible> {
+        let rep = match (req.method(), req.uri().path()) {
+            (&Method::GET, "/metrics") => self.metrics().await,
+            (_, "/logging" | "/logs") if !self.logs => {
+                text_response(StatusCode::NOT_FOUND, "log routes are disabled\n".to_owned())
+            }
+            (&Method::GET, "/logging") => self.current_filter(),
+            (&Method::PUT, "/logging") => self.set_filter(req).await,
+            (&Method::GET | &Method::POST, "/logs") => self.stream_logs(req, drain).await,
+            (_, "/metrics" | "/logging" | "/logs") => {
+                text_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed\n".to_owned())
+            }
+            _ => text_response(StatusCode::NOT_FOUND, "not found\n".to_owned()),
+        };
+        Ok(rep)
+    }
+
+    async fn metrics(&self) -> Response<Body> {
+        match self.metrics.encode(&self.engine, &self.auth, &self.trace).await {
+            Ok(metrics) => {
+                let mut rep = Response::new(Body::from(metrics));
+                rep.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    header::HeaderValue::from_static(CONTENT_TYPE_METRICS),
+                );
+                rep
+            }
+            Err(e) => {
+                error!(error = ?e, "failed to encode metrics");
+                text_response(StatusCode::INTERNAL_SERVER_ERROR, format!("{}\n", e))
+            }
+        }
+    }
+
+    fn current_filter(&self) -> Response<Body> {
+        let Some(level) = self.trace.level() else {
+            return text_response(StatusCode::NOT_FOUND, "logging is disabled\n".to_owned());
+        };
+        match level.current() {
+            Ok(filter) => text_response(StatusCode::OK, format!("{}\n", filter)),
+            Err(e) => text_response(StatusCode::INTERNAL_SERVER_ERROR, format!("{}\n", e)),
+        }
+    }
+
+    async fn set_filter(&self, req: Request<Body>) -> Response<Body> {
+        let Some(level) = self.trace.level() else {
+            return text_response(StatusCode::NOT_FOUND, "logging is disabled\n".to_owned());
+        };
+        let filter = match body::to_bytes(req.into_body()).await {
+            Ok(filter) => filter,
+            Err(e) => return text_response(StatusCode::BAD_REQUEST, format!("{}\n", e)),
+        };
+        match level.set_from(filter.trim_ascii()) {
+            Ok(()) => text_response(StatusCode::NO_CONTENT, String::new()),
+            Err(e) => text_response(StatusCode::BAD_REQUEST, format!("{}\n", e)),
+        }
+    }
+
+    async fn stream_logs(&self, req: Request<Body>, drain: drain::Watch) -> Response<Body> {
+        let filter = if req.method() == Method::GET {
+            let query = req.uri().query().unwrap_or_default();
+            let filter = query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("filter="))
+                .map(|filter| percent_decode_str(&filter.replace('+', " ")).decode_utf8_lossy().into_owned());
+            match filter {
+                Some(filter) => filter,
+                None => return text_response(StatusCode::BAD_REQUEST, "missing filter query\n".to_owned()),
+            }
+        } else {
+            match body::to_bytes(req.into_body()).await {
+                Ok(filter) => String::from_utf8_lossy(&filter).into_owned(),
+                Err(e) => return text_response(StatusCode::BAD_REQUEST, format!("{}\n", e)),
+            }
+        };
+        let filter = match trace::EnvFilter::builder().with_regex(false).parse(filter.trim()) {
+            Ok(filter) => filter,
+            Err(e) => return text_response(StatusCode::BAD_REQUEST, format!("{}\n", e)),
+        };
+        let reader = match self.trace.stream().add_stream(filter) {
+            Ok(reader) => reader,
+            Err(e) => return text_response(StatusCode::INTERNAL_SERVER_ERROR, format!("{}\n", e)),
+        };
+
+        let (mut tx, body) = Body::channel();
+        tokio::spawn(async move {
+            debug!("starting log stream");
+            let signaled = drain.signaled();
+            tokio::pin!(signaled);
+            loop {
+                let line = tokio::select! {
+                    release = &mut signaled => {
+                        drop(release);
+                        break;
+                    }
+                    line = reader.next_line() => match line {
+                        Some(line) => Bytes::copy_from_slice(&line),
+                        None => break,
+                    },
+                };
+                let dropped = reader.take_dropped_count();
+                if dropped > 0 {
+                    let notice = format!("{{\"dropped_logs\":{}}}\n", dropped);
+                    if tx.send_data(notice.into()).await.is_err() {
+                        break;
+                    }
+                }
+                if tx.send_data(line).await.is_err() {
+                    break;
+                }
+            }
+            debug!("log stream ended");
+        });
+        let mut rep = Response::new(body);
+        rep.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static(CONTENT_TYPE_LOGS),
+        );
+        rep
+    }
+}
+
+fn text_response(status: StatusCode, body: String) -> Response<Body> {
+    let mut rep = Response::new(Body::from(body));
+    *rep.status_mut() = status;
+    rep
+}
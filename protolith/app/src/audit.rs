@@ -0,0 +1,87 @@
+//! The audit log sinks.
+//!
+//! Events are recorded by the `AuditLayer` of the gRPC server and the HTTP
+//! gateway, and written in order by a single task to the audit column family
+//! of the default database and to a file of JSON lines.
+//!
+//! The lines of the file are not in the format of the JSON access log: each
+//! is an `AuditEvent` in the protobuf JSON mapping, the same objects
+//! `ListAuditEvents` answers with, so one parser reads both sinks and the
+//! file keeps the fields the access log has no column for: the credential,
+//! database, collection and key of the request.
+use std::path::PathBuf;
+
+use protolith_core::{api::protolith::types::v1::AuditEvent, error::Error};
+use protolith_engine::{Admin as _, ProtolithDbEngine};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::mpsc};
+use tracing::{debug, warn};
+
+/// How many events may wait to be written before requests wait for the
+/// writer.
+const AUDIT_LOG_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Whether events are stored in the audit column family, where
+    /// `ListAuditEvents` reads them from.
+    pub store: bool,
+    /// The file events are appended to, one `AuditEvent` per line in the
+    /// protobuf JSON mapping.
+    pub file: Option<PathBuf>,
+}
+
+/// Records audit events, cloned into every audited service.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    tx: mpsc::Sender<AuditEvent>,
+}
+
+impl Config {
+    /// Spawns the writer of the audit log, `None` when no sink is enabled.
+    pub async fn build(self, engine: ProtolithDbEngine) -> Result<Option<AuditLog>, Error> {
+        if !self.store && self.file.is_none() {
+            return Ok(None);
+        }
+        let file = match &self.file {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path).await?),
+            None => None,
+        };
+        let (tx, rx) = mpsc::channel(AUDIT_LOG_CAPACITY);
+        tokio::spawn(write(rx, self.store.then_some(engine), file));
+        Ok(Some(AuditLog { tx }))
+    }
+}
+
+impl AuditLog {
+    pub async fn record(&self, event: AuditEvent) {
+        if self.tx.send(event).await.is_err() {
+            warn!("audit log writer stopped, dropping event");
+        }
+    }
+}
+
+async fn write(
+    mut rx: mpsc::Receiver<AuditEvent>,
+    engine: Option<ProtolithDbEngine>,
+    mut file: Option<tokio::fs::File>,
+) {
+    while let Some(event) = rx.recv().await {
+        debug!(path = ?event.path, username = ?event.username, code = ?event.code, "audit");
+        if let Some(engine) = &engine {
+            if let Err(e) = engine.append_audit_event(event.clone()).await {
+                warn!(error = ?e, id = ?event.id, "failed to store audit event");
+            }
+        }
+        if let Some(f) = &mut file {
+            let mut line = serde_json::to_vec(&event).unwrap_or_default();
+            line.push(b'\n');
+            let written = match f.write_all(&line).await {
+                Ok(()) => f.flush().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = written {
+                warn!(error = ?e, id = ?event.id, "failed to write audit event");
+            }
+        }
+    }
+}
@@ -9,9 +9,12 @@ use thiserror::Error;
 use protolith_auth as auth;
 use tracing::error;
 use protolith_core::{
-    db, meta_store, schema
+    api::protolith::annotation::v1::{CompactionStyle, Compression, Tuning},
+    db, encryption, meta_store, schema, tuning
 };
 use  protolith_admin as admin;
+use protolith_engine::limit;
+use crate::{audit, tls};
 /// The strings used to build a configuration.
 pub trait Strings {
     /// Retrieves the value for the key `key`.
@@ -76,23 +79,84 @@ pub enum ParseError {
     InvalidTokenSource,
     #[error("invalid trust anchors")]
     InvalidTrustAnchors,
+    #[error("invalid encryption key")]
+    InvalidEncryptionKey,
     #[error("not a valid port policy: {0}")]
     InvalidPortPolicy(String),
+    #[error("not a valid compression: {0}")]
+    InvalidCompression(String),
+    #[error("not a valid compaction style: {0}")]
+    InvalidCompactionStyle(String),
+    #[error("invalid tuning file")]
+    InvalidTuningFile,
 }
 
 // Environment variables to look at when loading the configuration
 pub const ENV_DB_PATH: &str = "PROTOLITH_DB_PATH";
 pub const ENV_DB_MAX_OPEN_FILES: &str = "PROTOLITH_DB_MAX_OPEN_FILES";
 pub const ENV_DB_CACHE_SIZE: &str = "PROTOLITH_DB_CACHE_SIZE";
+pub const ENV_DB_WRITE_BUFFER_BUDGET: &str = "PROTOLITH_DB_WRITE_BUFFER_BUDGET";
+pub const ENV_DB_COMPRESSION: &str = "PROTOLITH_DB_COMPRESSION";
+pub const ENV_DB_COMPRESSION_PER_LEVEL: &str = "PROTOLITH_DB_COMPRESSION_PER_LEVEL";
+pub const ENV_DB_BLOOM_FILTER_BITS: &str = "PROTOLITH_DB_BLOOM_FILTER_BITS";
+pub const ENV_DB_BLOCK_SIZE: &str = "PROTOLITH_DB_BLOCK_SIZE";
+pub const ENV_DB_WRITE_BUFFER_SIZE: &str = "PROTOLITH_DB_WRITE_BUFFER_SIZE";
+pub const ENV_DB_COMPACTION_STYLE: &str = "PROTOLITH_DB_COMPACTION_STYLE";
+pub const ENV_DB_PREFIX_LENGTH: &str = "PROTOLITH_DB_PREFIX_LENGTH";
+pub const ENV_DB_TUNING_FILE: &str = "PROTOLITH_DB_TUNING_FILE";
 pub const ENV_METASTORE_INDEX_NAME: &str = "PROTOLITH_METASTORE_INDEX_NAME";
 pub const ENV_METASTORE_SCHEMA_NAME: &str = "PROTOLITH_METASTORE_SCHEMA_NAME";
 pub const ENV_METASTORE_VERSION_NAME: &str = "PROTOLITH_METASTORE_VERSION_NAME";
 pub const ENV_METASTORE_USER: &str = "PROTOLITH_METASTORE_USER";
+pub const ENV_METASTORE_SESSION: &str = "PROTOLITH_METASTORE_SESSION";
+pub const ENV_METASTORE_API_KEY: &str = "PROTOLITH_METASTORE_API_KEY";
+pub const ENV_METASTORE_AUDIT: &str = "PROTOLITH_METASTORE_AUDIT";
 pub const ENV_SCHEMA_DEFAULT_VERSION: &str = "PROTOLITH_SCHEMA_DEFAULT_VERSION";
 pub const ENV_SCHEMA_ENABLE_VERSIONING: &str = "PROTOLITH_SCHEMA_VERSIONING";
 pub const ENV_ADDR: &str = "PROTOLITH_ADDR";
+pub const ENV_HTTP_ADDR: &str = "PROTOLITH_HTTP_ADDR";
+pub const ENV_ADMIN_ADDR: &str = "PROTOLITH_ADMIN_ADDR";
+pub const ENV_ADMIN_LOGS: &str = "PROTOLITH_ADMIN_LOGS";
 pub const ENV_USER: &str = "PROTOLITH_USER";
 pub const ENV_PASS: &str = "PROTOLITH_PASS";
+pub const ENV_SESSION_IDLE_TTL: &str = "PROTOLITH_SESSION_IDLE_TTL";
+pub const ENV_SESSION_TTL: &str = "PROTOLITH_SESSION_TTL";
+pub const ENV_SESSION_SWEEP_INTERVAL: &str = "PROTOLITH_SESSION_SWEEP_INTERVAL";
+pub const ENV_JWT_HMAC_SECRETS: &str = "PROTOLITH_JWT_HMAC_SECRETS";
+pub const ENV_JWT_JWKS_PATH: &str = "PROTOLITH_JWT_JWKS_PATH";
+pub const ENV_JWT_ISSUER: &str = "PROTOLITH_JWT_ISSUER";
+pub const ENV_JWT_AUDIENCE: &str = "PROTOLITH_JWT_AUDIENCE";
+pub const ENV_JWT_USERNAME_CLAIM: &str = "PROTOLITH_JWT_USERNAME_CLAIM";
+pub const ENV_JWT_ROLES_CLAIM: &str = "PROTOLITH_JWT_ROLES_CLAIM";
+pub const ENV_JWT_TTL: &str = "PROTOLITH_JWT_TTL";
+pub const ENV_TLS_CERT_PATH: &str = "PROTOLITH_TLS_CERT_PATH";
+pub const ENV_TLS_KEY_PATH: &str = "PROTOLITH_TLS_KEY_PATH";
+pub const ENV_TLS_CLIENT_CA_PATH: &str = "PROTOLITH_TLS_CLIENT_CA_PATH";
+pub const ENV_TLS_CLIENT_AUTH_REQUIRED: &str = "PROTOLITH_TLS_CLIENT_AUTH_REQUIRED";
+pub const ENV_TLS_CLIENT_USERS: &str = "PROTOLITH_TLS_CLIENT_USERS";
+pub const ENV_TLS_CLIENT_CN_AS_USERNAME: &str = "PROTOLITH_TLS_CLIENT_CN_AS_USERNAME";
+pub const ENV_AUDIT_LOG: &str = "PROTOLITH_AUDIT_LOG";
+pub const ENV_AUDIT_LOG_FILE: &str = "PROTOLITH_AUDIT_LOG_FILE";
+pub const ENV_ENCRYPTION_KEY: &str = "PROTOLITH_ENCRYPTION_KEY";
+pub const ENV_ENCRYPTION_KEY_FILE: &str = "PROTOLITH_ENCRYPTION_KEY_FILE";
+pub const ENV_LOGIN_MAX_FAILURES: &str = "PROTOLITH_LOGIN_MAX_FAILURES";
+pub const ENV_LOGIN_MAX_PEER_FAILURES: &str = "PROTOLITH_LOGIN_MAX_PEER_FAILURES";
+pub const ENV_LOGIN_LOCKOUT: &str = "PROTOLITH_LOGIN_LOCKOUT";
+pub const ENV_LOGIN_MAX_LOCKOUT: &str = "PROTOLITH_LOGIN_MAX_LOCKOUT";
+pub const ENV_PASSWORD_MIN_LENGTH: &str = "PROTOLITH_PASSWORD_MIN_LENGTH";
+pub const ENV_PASSWORD_REQUIRE_LOWERCASE: &str = "PROTOLITH_PASSWORD_REQUIRE_LOWERCASE";
+pub const ENV_PASSWORD_REQUIRE_UPPERCASE: &str = "PROTOLITH_PASSWORD_REQUIRE_UPPERCASE";
+pub const ENV_PASSWORD_REQUIRE_DIGIT: &str = "PROTOLITH_PASSWORD_REQUIRE_DIGIT";
+pub const ENV_PASSWORD_REQUIRE_SYMBOL: &str = "PROTOLITH_PASSWORD_REQUIRE_SYMBOL";
+pub const ENV_USER_RATE_LIMIT: &str = "PROTOLITH_USER_RATE_LIMIT";
+pub const ENV_USER_RATE_BURST: &str = "PROTOLITH_USER_RATE_BURST";
+pub const ENV_USER_MAX_CONCURRENT_REQUESTS: &str = "PROTOLITH_USER_MAX_CONCURRENT_REQUESTS";
+pub const ENV_DATABASE_RATE_LIMIT: &str = "PROTOLITH_DATABASE_RATE_LIMIT";
+pub const ENV_DATABASE_RATE_BURST: &str = "PROTOLITH_DATABASE_RATE_BURST";
+pub const ENV_DATABASE_MAX_CONCURRENT_REQUESTS: &str = "PROTOLITH_DATABASE_MAX_CONCURRENT_REQUESTS";
+pub const ENV_DATABASE_MAX_DOCUMENTS: &str = "PROTOLITH_DATABASE_MAX_DOCUMENTS";
+pub const ENV_DATABASE_MAX_BYTES: &str = "PROTOLITH_DATABASE_MAX_BYTES";
+pub const ENV_SLOW_QUERY_THRESHOLD: &str = "PROTOLITH_SLOW_QUERY_THRESHOLD";
 const ENV_SHUTDOWN_GRACE_PERIOD: &str = "PROTOLITH_SHUTDOWN_GRACE_PERIOD";
 const ENV_DATABASE: &str = "PROTOLITH_DATABASE";
 const ENV_DB_DROP_ON_SHUTDOWN: &str = "PROTOLITH_DESTROY_ON_SHUTDOWN";
@@ -101,11 +165,15 @@ const ENV_DEFAULT_DB_DESCRIPTOR_PATH: &str = "PROTOLITH_DEFAULT_DB_DESCRIPTOR";
 
 // Default values for various configuration fields
 const DEFAULT_DB_MAX_OPEN_FILES: i32 = 1000;
-const DEFAULT_DB_CACHE_SIZE: usize = 50 * 1024 * 1024 * 1024; // 1GB in bytes
+const DEFAULT_DB_CACHE_SIZE: usize = 1024 * 1024 * 1024; // 1GB in bytes, shared by every database
+const DEFAULT_DB_WRITE_BUFFER_BUDGET: usize = 256 * 1024 * 1024; // 256MB in bytes, shared by every database
 const DEFAULT_INDEX_CF_NAME: &str = "index";
 const DEFAULT_SCHEMA_CF_NAME: &str = "schema";
 const DEFAULT_SCHEMA_VERSIONS_CF_NAME: &str = "schema_versions";
 const DEFAULT_USER_CF_NAME: &str = "user";
+const DEFAULT_SESSION_CF_NAME: &str = "session";
+const DEFAULT_API_KEY_CF_NAME: &str = "api_key";
+const DEFAULT_AUDIT_CF_NAME: &str = "audit";
 const DEFAULT_ADDR: &str = "0.0.0.0:5678";
 const DEFAULT_DB_DESCRIPTOR: &str = "/usr/src/bin/protolith-db/descriptor.bin";
 const DEFAULT_USER: &str = "protolith";
@@ -113,6 +181,18 @@ const DEFAULT_PASS: &str = "protolith";
 
 // 2 minutes seems like a reasonable amount of time to wait for connections to close...
 const DEFAULT_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(2 * 60);
+const DEFAULT_SESSION_IDLE_TTL: Duration = Duration::from_secs(30 * 60);
+const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(12 * 60 * 60);
+const DEFAULT_SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_JWT_USERNAME_CLAIM: &str = "sub";
+const DEFAULT_JWT_ROLES_CLAIM: &str = "roles";
+const DEFAULT_JWT_TTL: Duration = Duration::from_secs(15 * 60);
+const DEFAULT_LOGIN_MAX_FAILURES: u32 = 5;
+const DEFAULT_LOGIN_MAX_PEER_FAILURES: u32 = 20;
+const DEFAULT_LOGIN_LOCKOUT: Duration = Duration::from_secs(5);
+const DEFAULT_LOGIN_MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+const DEFAULT_PASSWORD_MIN_LENGTH: usize = 8;
+const DEFAULT_SLOW_QUERY_THRESHOLD: Duration = Duration::from_secs(1);
 const DEFAULT_SCHEMA_VERSION: u64 = 1;
 const DEFAULT_DATABASE: &str = "protolith";
 const DEFAULT_DESCRIPTOR_NAME: &str = "DESCRIPTOR";
@@ -122,20 +202,73 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
     // defer returning any errors until all of them have been parsed.
     let shutdown_grace_period = parse(strings, ENV_SHUTDOWN_GRACE_PERIOD, parse_duration);
     let cache_size = parse(strings, ENV_DB_CACHE_SIZE, parse_number);
+    let write_buffer_budget = parse(strings, ENV_DB_WRITE_BUFFER_BUDGET, parse_number);
     let max_open_files = parse(strings, ENV_DB_MAX_OPEN_FILES, parse_number);
+    let compression = parse(strings, ENV_DB_COMPRESSION, parse_compression);
+    let compression_per_level = parse(strings, ENV_DB_COMPRESSION_PER_LEVEL, parse_compression_per_level);
+    let bloom_filter_bits = parse(strings, ENV_DB_BLOOM_FILTER_BITS, parse_number);
+    let block_size = parse(strings, ENV_DB_BLOCK_SIZE, parse_number);
+    let write_buffer_size = parse(strings, ENV_DB_WRITE_BUFFER_SIZE, parse_number);
+    let compaction_style = parse(strings, ENV_DB_COMPACTION_STYLE, parse_compaction_style);
+    let prefix_length = parse(strings, ENV_DB_PREFIX_LENGTH, parse_number);
+    let database_tuning = parse(strings, ENV_DB_TUNING_FILE, parse_tuning_file);
     let index_cf_name = parse(strings, ENV_METASTORE_INDEX_NAME, parse_string);
     let schema_cf_name = parse(strings, ENV_METASTORE_SCHEMA_NAME, parse_string);
     let schema_versions_cf_name = parse(strings, ENV_METASTORE_VERSION_NAME, parse_string);
     let user_cf_name = parse(strings, ENV_METASTORE_USER, parse_string);
+    let session_cf_name = parse(strings, ENV_METASTORE_SESSION, parse_string);
+    let api_key_cf_name = parse(strings, ENV_METASTORE_API_KEY, parse_string);
+    let audit_cf_name = parse(strings, ENV_METASTORE_AUDIT, parse_string);
     let default_version = parse(strings, ENV_SCHEMA_DEFAULT_VERSION, parse_number);
     let schema_versioning = parse(strings, ENV_SCHEMA_ENABLE_VERSIONING, parse_bool);
     let database = parse(strings, ENV_DATABASE, parse_string);
     let addr = parse(strings, ENV_ADDR, parse_socket_addr);
+    let http_addr = parse(strings, ENV_HTTP_ADDR, parse_socket_addr);
+    let admin_addr = parse(strings, ENV_ADMIN_ADDR, parse_socket_addr);
+    let admin_logs = parse(strings, ENV_ADMIN_LOGS, parse_bool);
     let drop_on_shutdown = parse(strings, ENV_DB_DROP_ON_SHUTDOWN, parse_bool);
     let descriptor_file_name = parse(strings, ENV_DB_DESCRIPTOR_FILE_NAME, parse_string);
     let database_descriptor_path = parse(strings, ENV_DEFAULT_DB_DESCRIPTOR_PATH, parse_pathbuf);
     let user = parse(strings, ENV_USER, parse_string);
     let password = parse(strings, ENV_PASS, parse_string);
+    let session_idle_ttl = parse(strings, ENV_SESSION_IDLE_TTL, parse_duration);
+    let session_ttl = parse(strings, ENV_SESSION_TTL, parse_duration);
+    let session_sweep_interval = parse(strings, ENV_SESSION_SWEEP_INTERVAL, parse_duration);
+    let jwt_hmac_secrets = parse(strings, ENV_JWT_HMAC_SECRETS, parse_list);
+    let jwt_jwks_path = parse(strings, ENV_JWT_JWKS_PATH, parse_pathbuf);
+    let jwt_issuer = parse(strings, ENV_JWT_ISSUER, parse_string);
+    let jwt_audience = parse(strings, ENV_JWT_AUDIENCE, parse_string);
+    let jwt_username_claim = parse(strings, ENV_JWT_USERNAME_CLAIM, parse_string);
+    let jwt_roles_claim = parse(strings, ENV_JWT_ROLES_CLAIM, parse_string);
+    let jwt_ttl = parse(strings, ENV_JWT_TTL, parse_duration);
+    let tls_cert_path = parse(strings, ENV_TLS_CERT_PATH, parse_pathbuf);
+    let tls_key_path = parse(strings, ENV_TLS_KEY_PATH, parse_pathbuf);
+    let tls_client_ca_path = parse(strings, ENV_TLS_CLIENT_CA_PATH, parse_trust_anchors);
+    let tls_client_auth_required = parse(strings, ENV_TLS_CLIENT_AUTH_REQUIRED, parse_bool);
+    let tls_client_users = parse(strings, ENV_TLS_CLIENT_USERS, parse_client_users);
+    let tls_client_cn_as_username = parse(strings, ENV_TLS_CLIENT_CN_AS_USERNAME, parse_bool);
+    let audit_log = parse(strings, ENV_AUDIT_LOG, parse_bool);
+    let audit_log_file = parse(strings, ENV_AUDIT_LOG_FILE, parse_pathbuf);
+    let encryption_key = parse(strings, ENV_ENCRYPTION_KEY, parse_encryption_key);
+    let encryption_key_file = parse(strings, ENV_ENCRYPTION_KEY_FILE, parse_encryption_key_file);
+    let login_max_failures = parse(strings, ENV_LOGIN_MAX_FAILURES, parse_number);
+    let login_max_peer_failures = parse(strings, ENV_LOGIN_MAX_PEER_FAILURES, parse_number);
+    let login_lockout = parse(strings, ENV_LOGIN_LOCKOUT, parse_duration);
+    let login_max_lockout = parse(strings, ENV_LOGIN_MAX_LOCKOUT, parse_duration);
+    let password_min_length = parse(strings, ENV_PASSWORD_MIN_LENGTH, parse_number);
+    let password_require_lowercase = parse(strings, ENV_PASSWORD_REQUIRE_LOWERCASE, parse_bool);
+    let password_require_uppercase = parse(strings, ENV_PASSWORD_REQUIRE_UPPERCASE, parse_bool);
+    let password_require_digit = parse(strings, ENV_PASSWORD_REQUIRE_DIGIT, parse_bool);
+    let password_require_symbol = parse(strings, ENV_PASSWORD_REQUIRE_SYMBOL, parse_bool);
+    let user_rate_limit = parse(strings, ENV_USER_RATE_LIMIT, parse_number);
+    let user_rate_burst = parse(strings, ENV_USER_RATE_BURST, parse_number);
+    let user_max_concurrent_requests = parse(strings, ENV_USER_MAX_CONCURRENT_REQUESTS, parse_number);
+    let database_rate_limit = parse(strings, ENV_DATABASE_RATE_LIMIT, parse_number);
+    let database_rate_burst = parse(strings, ENV_DATABASE_RATE_BURST, parse_number);
+    let database_max_concurrent_requests = parse(strings, ENV_DATABASE_MAX_CONCURRENT_REQUESTS, parse_number);
+    let database_max_documents = parse(strings, ENV_DATABASE_MAX_DOCUMENTS, parse_number);
+    let database_max_bytes = parse(strings, ENV_DATABASE_MAX_BYTES, parse_number);
+    let slow_query_threshold = parse(strings, ENV_SLOW_QUERY_THRESHOLD, parse_duration);
     
     let drop_on_shutdown =  drop_on_shutdown?.unwrap_or(false);
     let user = user?.unwrap_or(DEFAULT_USER.to_owned());
@@ -145,12 +278,39 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         let descriptor_file_name = descriptor_file_name?.unwrap_or(DEFAULT_DESCRIPTOR_NAME.to_string());
         let db_path = parse_rocks_db_path(strings, ENV_DB_PATH)?;
         let cache_size = cache_size?.unwrap_or(DEFAULT_DB_CACHE_SIZE);
+        let write_buffer_budget = write_buffer_budget?.unwrap_or(DEFAULT_DB_WRITE_BUFFER_BUDGET);
         let max_open_files = max_open_files?.unwrap_or(DEFAULT_DB_MAX_OPEN_FILES);
+        let encryption_key = match (encryption_key?, encryption_key_file?) {
+            (Some(_), Some(_)) => {
+                error!("only one of {ENV_ENCRYPTION_KEY} and {ENV_ENCRYPTION_KEY_FILE} may be set");
+                return Err(EnvError::InvalidEnvVar);
+            }
+            (key, file) => key.or(file),
+        };
+        let quota = db::Quota {
+            max_documents: database_max_documents?,
+            max_bytes: database_max_bytes?,
+        };
+        let tuning = Tuning {
+            compression: compression?.map(Into::into),
+            compression_per_level: compression_per_level?.unwrap_or_default(),
+            bloom_filter_bits: bloom_filter_bits?,
+            block_size: block_size?,
+            write_buffer_size: write_buffer_size?,
+            compaction_style: compaction_style?.map(Into::into),
+            prefix_length: prefix_length?,
+        };
         db::Config {
             db_path,
             cache_size,
+            write_buffer_budget,
             max_open_files,
             descriptor_file_name,
+            encryption_key,
+            quota,
+            slow_query_threshold: slow_query_threshold?.unwrap_or(DEFAULT_SLOW_QUERY_THRESHOLD),
+            tuning,
+            database_tuning: database_tuning?.unwrap_or_default(),
         }
     };
 
@@ -161,12 +321,18 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         let schema_cf_name = schema_cf_name?.unwrap_or(DEFAULT_SCHEMA_CF_NAME.to_string());
         let schema_versions_cf_name = schema_versions_cf_name?.unwrap_or(DEFAULT_SCHEMA_VERSIONS_CF_NAME.to_string());
         let user_cf_name = user_cf_name?.unwrap_or(DEFAULT_USER_CF_NAME.to_string());
+        let session_cf_name = session_cf_name?.unwrap_or(DEFAULT_SESSION_CF_NAME.to_string());
+        let api_key_cf_name = api_key_cf_name?.unwrap_or(DEFAULT_API_KEY_CF_NAME.to_string());
+        let audit_cf_name = audit_cf_name?.unwrap_or(DEFAULT_AUDIT_CF_NAME.to_string());
 
         meta_store::Config {
             index_cf_name,
             schema_cf_name,
             schema_versions_cf_name,
             user_cf_name,
+            session_cf_name,
+            api_key_cf_name,
+            audit_cf_name,
             default_db: database.clone(),
         }
     };
@@ -181,22 +347,109 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
     };
 
     let admin = {
+        let password_policy = admin::password::PasswordPolicy {
+            min_length: password_min_length?.unwrap_or(DEFAULT_PASSWORD_MIN_LENGTH),
+            require_lowercase: password_require_lowercase?.unwrap_or(false),
+            require_uppercase: password_require_uppercase?.unwrap_or(false),
+            require_digit: password_require_digit?.unwrap_or(false),
+            require_symbol: password_require_symbol?.unwrap_or(false),
+        };
         admin::Config {
+            password_policy,
+        }
+    };
+
+    let tls = match (tls_cert_path?, tls_key_path?) {
+        (Some(cert_path), Some(key_path)) => Some(tls::Config {
+            cert_path,
+            key_path,
+            client_ca_path: tls_client_ca_path?,
+            client_auth_required: tls_client_auth_required?.unwrap_or(false),
+        }),
+        (None, None) => {
+            if tls_client_ca_path?.is_some() {
+                error!("{ENV_TLS_CLIENT_CA_PATH} requires {ENV_TLS_CERT_PATH} and {ENV_TLS_KEY_PATH}");
+                return Err(EnvError::InvalidEnvVar);
+            }
+            None
+        }
+        _ => {
+            error!("{ENV_TLS_CERT_PATH} and {ENV_TLS_KEY_PATH} must be set together");
+            return Err(EnvError::InvalidEnvVar);
+        }
+    };
+    // Verified client certificates authenticate as the users their subjects
+    // map to, or only as the user named by their common name when opted in.
+    let tls_client_users = tls_client_users?.unwrap_or_default();
+    let tls_client_cn_as_username = tls_client_cn_as_username?.unwrap_or(false);
+    let client_certificates = match &tls {
+        Some(tls::Config { client_ca_path: Some(_), .. }) => {
+            Some(auth::tls::ClientCertificates::new(tls_client_users, tls_client_cn_as_username))
+        }
+        _ => None,
+    };
+
+    let audit = audit::Config {
+        store: audit_log?.unwrap_or(true),
+        file: audit_log_file?,
+    };
 
+    // Rates default to a burst of one second worth of requests.
+    let limits = {
+        let user_rate = user_rate_limit?.unwrap_or(0.0);
+        let database_rate = database_rate_limit?.unwrap_or(0.0);
+        limit::Config {
+            user: limit::Limits {
+                rate: user_rate,
+                burst: user_rate_burst?.unwrap_or(user_rate.ceil() as u32),
+                max_concurrent: user_max_concurrent_requests?.unwrap_or(0),
+            },
+            database: limit::Limits {
+                rate: database_rate,
+                burst: database_rate_burst?.unwrap_or(database_rate.ceil() as u32),
+                max_concurrent: database_max_concurrent_requests?.unwrap_or(0),
+            },
         }
     };
 
     let addr = addr?.unwrap_or(DEFAULT_ADDR.parse().unwrap());
     let database_descriptor_path = database_descriptor_path?.unwrap_or(PathBuf::from(DEFAULT_DB_DESCRIPTOR));
     let auth = {
+        let jwt = auth::jwt::Config {
+            hmac_secrets: jwt_hmac_secrets?.unwrap_or_default(),
+            jwks_path: jwt_jwks_path?,
+            issuer: jwt_issuer?,
+            audience: jwt_audience?,
+            username_claim: jwt_username_claim?.unwrap_or(DEFAULT_JWT_USERNAME_CLAIM.to_string()),
+            roles_claim: jwt_roles_claim?.unwrap_or(DEFAULT_JWT_ROLES_CLAIM.to_string()),
+            ttl: jwt_ttl?.unwrap_or(DEFAULT_JWT_TTL),
+        };
+        let lockout = auth::lockout::Config {
+            max_failures: login_max_failures?.unwrap_or(DEFAULT_LOGIN_MAX_FAILURES),
+            max_peer_failures: login_max_peer_failures?.unwrap_or(DEFAULT_LOGIN_MAX_PEER_FAILURES),
+            lockout: login_lockout?.unwrap_or(DEFAULT_LOGIN_LOCKOUT),
+            max_lockout: login_max_lockout?.unwrap_or(DEFAULT_LOGIN_MAX_LOCKOUT),
+        };
         auth::Config {
             password,
             user,
-            meta_store: meta_store.clone()
+            meta_store: meta_store.clone(),
+            session_idle_ttl: session_idle_ttl?.unwrap_or(DEFAULT_SESSION_IDLE_TTL),
+            session_ttl: session_ttl?.unwrap_or(DEFAULT_SESSION_TTL),
+            session_sweep_interval: session_sweep_interval?.unwrap_or(DEFAULT_SESSION_SWEEP_INTERVAL),
+            jwt,
+            lockout,
+            client_certificates,
         }
     };
     Ok(super::Config {
         addr,
+        http_addr: http_addr?,
+        admin_addr: admin_addr?,
+        admin_logs: admin_logs?.unwrap_or(false),
+        tls,
+        audit,
+        limits,
         db,
         admin,
         auth,
@@ -261,6 +514,99 @@ fn parse_string(s: &str) -> Result<String, ParseError> {
     Ok(s.to_owned())
 }
 
+/// Parses a comma separated list, ignoring empty entries.
+fn parse_list(s: &str) -> Result<Vec<String>, ParseError> {
+    Ok(s.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Parses a comma separated list of `<subject common name>:<username>`.
+fn parse_client_users(s: &str) -> Result<HashMap<String, String>, ParseError> {
+    parse_list(s)?
+        .into_iter()
+        .map(|entry| match entry.split_once(':') {
+            Some((subject, username)) if !subject.is_empty() && !username.is_empty() => {
+                Ok((subject.to_owned(), username.to_owned()))
+            }
+            _ => Err(ParseError::NameError),
+        })
+        .collect()
+}
+
+fn parse_trust_anchors(s: &str) -> Result<PathBuf, ParseError> {
+    let path = PathBuf::from(s);
+    tls::read_trust_anchors(&path).map_err(|e| {
+        error!(error = %e, "failed to read trust anchors");
+        ParseError::InvalidTrustAnchors
+    })?;
+    Ok(path)
+}
+
+/// Parses a 32 byte key, hex or base64 encoded.
+fn parse_encryption_key(s: &str) -> Result<encryption::Key, ParseError> {
+    encryption::Key::parse(s.as_bytes()).map_err(|e| {
+        error!(error = %e, "failed to parse encryption key");
+        ParseError::InvalidEncryptionKey
+    })
+}
+
+/// Reads a 32 byte key from a file, raw, hex or base64 encoded.
+fn parse_encryption_key_file(s: &str) -> Result<encryption::Key, ParseError> {
+    let bytes = std::fs::read(s).map_err(|e| {
+        error!(error = %e, "failed to read encryption key file");
+        ParseError::InvalidEncryptionKey
+    })?;
+    encryption::Key::parse(&bytes).map_err(|e| {
+        error!(error = %e, "failed to parse encryption key file");
+        ParseError::InvalidEncryptionKey
+    })
+}
+
+/// Parses the name of a compression, such as `lz4` or `ZSTD`.
+fn parse_compression(s: &str) -> Result<Compression, ParseError> {
+    Compression::from_str_name(&s.trim().to_uppercase())
+        .ok_or_else(|| ParseError::InvalidCompression(s.to_owned()))
+}
+
+/// Parses a comma separated list of compressions, the first for level 0.
+fn parse_compression_per_level(s: &str) -> Result<Vec<i32>, ParseError> {
+    parse_list(s)?
+        .iter()
+        .map(|level| parse_compression(level).map(Into::into))
+        .collect()
+}
+
+fn parse_compaction_style(s: &str) -> Result<CompactionStyle, ParseError> {
+    CompactionStyle::from_str_name(&s.trim().to_uppercase())
+        .ok_or_else(|| ParseError::InvalidCompactionStyle(s.to_owned()))
+}
+
+/// Reads a JSON object of the tunings of databases by name, such as
+/// `{"archive": {"compression": "ZSTD", "compactionStyle": "UNIVERSAL"}}`.
+fn parse_tuning_file(s: &str) -> Result<HashMap<String, Tuning>, ParseError> {
+    let bytes = std::fs::read(s).map_err(|e| {
+        error!(error = %e, "failed to read tuning file");
+        ParseError::InvalidTuningFile
+    })?;
+    let databases: HashMap<String, serde_json::Value> = serde_json::from_slice(&bytes).map_err(|e| {
+        error!(error = %e, "failed to parse tuning file");
+        ParseError::InvalidTuningFile
+    })?;
+    databases
+        .into_iter()
+        .map(|(database, json)| {
+            let tuning = tuning::from_json(json).map_err(|e| {
+                error!(error = %e, database, "invalid tuning of database");
+                ParseError::InvalidTuningFile
+            })?;
+            Ok((database, tuning))
+        })
+        .collect()
+}
+
 fn parse_rocks_db_path<S: Strings>(s: &S, base: &str) -> Result<PathBuf, EnvError> {
     let path_str = parse(s, base, parse_string)?;
 
@@ -0,0 +1,482 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+
+use hyper::{
+    header,
+    server::{accept, conn::AddrStream},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use percent_encoding::percent_decode_str;
+use protolith_admin::ProtolithAdminService;
+use protolith_auth::Auth;
+use protolith_core::api::{
+    pbjson_types::{value::Kind, Empty, Value},
+    prost::Message,
+    protolith::{
+        services::v1::{
+            admin_service_server::AdminService, engine_service_server::EngineService,
+            CreateCollectionRequest, CreateDatabaseRequest, GetRequest, InsertRequest,
+            ListRequest,
+        },
+        types::v1::DocumentEncoding,
+    },
+    DescriptorPool, DynamicMessage, FILE_DESCRIPTOR_SET,
+};
+use protolith_core::error::Error;
+use protolith_engine::{
+    audit,
+    limit::{self, Limiter},
+    rbac::Principal,
+    service::ProtolithEngineService,
+    ProtolithDbEngine,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tonic::{Code, Status};
+use tracing::{debug, info};
+
+use crate::{
+    audit::AuditLog,
+    layer::{AuditLayer, MetricsLayer, TracingLayer, HEADER_PROTOLITH_SESSION},
+    metrics::{Metrics, UNKNOWN_ROUTE},
+};
+
+/// How long a client has to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves the `EngineService` and `AdminService` over HTTP/JSON.
+///
+/// Requests are handled by the same services backing the gRPC server, the
+/// service messages are translated with the API descriptors and documents
+/// with the `DescriptorPool` of their database.
+///
+/// | Route                                          | RPC                             |
+/// |------------------------------------------------|---------------------------------|
+/// | `POST /auth/v1/login`                          | `AuthService/Login`             |
+/// | `POST /auth/v1/logout`                         | `AuthService/Logout`            |
+/// | `GET /admin/v1/databases`                      | `AdminService/ListDatabases`    |
+/// | `POST /admin/v1/databases`                     | `AdminService/CreateDatabase`   |
+/// | `POST /admin/v1/databases/{db}/collections`    | `AdminService/CreateCollection` |
+/// | `GET /v1/{db}/{collection}`                    | `EngineService/List`            |
+/// | `POST /v1/{db}/{collection}`                   | `EngineService/Insert`          |
+/// | `GET /v1/{db}/{collection}/{key}`              | `EngineService/Get`             |
+///
+/// The `{key}` is percent-decoded, keys holding `/`, `%` or spaces are given
+/// percent-encoded.
+///
+/// All routes but login expect the `protolith-session` header or another
+/// credential accepted by `Auth::authenticate_peer`, including the client
+/// certificate when served over TLS. Authenticated requests are subject to
+/// the same limits as gRPC requests, refused ones are answered with
+/// `429 Too Many Requests` and a `Retry-After` header.
+#[derive(Clone)]
+pub struct Gateway {
+    api: DescriptorPool,
+    engine: ProtolithDbEngine,
+    engine_service: Arc<ProtolithEngineService<ProtolithDbEngine>>,
+    admin_service: Arc<ProtolithAdminService<ProtolithDbEngine>>,
+    auth: Arc<Auth<ProtolithDbEngine>>,
+    audit_log: Option<AuditLog>,
+    limiter: Option<Arc<Limiter>>,
+    metrics: Metrics,
+}
+
+// `tonic::Status` is what the wrapped services fail with.
+#[allow(clippy::result_large_err)]
+impl Gateway {
+    pub fn new(
+        engine: ProtolithDbEngine,
+        admin: ProtolithAdminService<ProtolithDbEngine>,
+        auth: Arc<Auth<ProtolithDbEngine>>,
+        audit_log: Option<AuditLog>,
+        limiter: Option<Arc<Limiter>>,
+    ) -> Self {
+        let api = DescriptorPool::decode(FILE_DESCRIPTOR_SET).expect("API file descriptor set");
+        Self {
+            api,
+            engine_service: Arc::new(ProtolithEngineService::new(engine.clone())),
+            admin_service: Arc::new(admin),
+            engine,
+            auth,
+            audit_log,
+            limiter,
+            metrics: Metrics::new(),
+        }
+    }
+
+    /// Records the requests in `metrics`, by route template.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub async fn serve(
+        self,
+        addr: SocketAddr,
+        tls: Option<TlsAcceptor>,
+        drain: drain::Watch,
+    ) -> Result<(), Error> {
+        let shutdown = async move {
+            let release = drain.signaled().await;
+            drop(release)
+        };
+        let Some(acceptor) = tls else {
+            let make_service = make_service_fn(move |conn: &AddrStream| {
+                let peer = Some(conn.remote_addr());
+                let audit = self.audit_layer(peer, false);
+                let metrics = self.metrics_layer();
+                let gateway = self.clone();
+                async move {
+                    let service = tower::ServiceBuilder::new()
+                        .layer(metrics)
+                        .layer(TracingLayer::new().with_peer(peer))
+                        .option_layer(audit)
+                        .service(service_fn(move |req| gateway.clone().call(req, peer, None)));
+                    Ok::<_, Infallible>(service)
+                }
+            });
+            info!(?addr, "Serving HTTP gateway at");
+            Server::bind(&addr).serve(make_service).with_graceful_shutdown(shutdown).await?;
+            return Ok(());
+        };
+        let make_service = make_service_fn(move |conn: &TlsStream<TcpStream>| {
+            // The handshake verified the certificate against the client CA bundle.
+            let certificate = conn
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|cert| cert.0.clone());
+            let peer = conn.get_ref().0.peer_addr().ok();
+            let audit = self.audit_layer(peer, certificate.is_some());
+            let metrics = self.metrics_layer();
+            let gateway = self.clone();
+            async move {
+                let service = tower::ServiceBuilder::new()
+                    .layer(metrics)
+                    .layer(TracingLayer::new().with_peer(peer))
+                    .option_layer(audit)
+                    .service(service_fn(move |req| gateway.clone().call(req, peer, certificate.clone())));
+                Ok::<_, Infallible>(service)
+            }
+        });
+        let incoming = tls_incoming(TcpListener::bind(addr).await?, acceptor);
+        info!(?addr, "Serving HTTPS gateway at");
+        Server::builder(incoming).serve(make_service).with_graceful_shutdown(shutdown).await?;
+        Ok(())
+    }
+
+    fn metrics_layer(&self) -> MetricsLayer {
+        MetricsLayer::new(self.metrics.clone()).with_route(route_template)
+    }
+
+    fn audit_layer(&self, peer: Option<SocketAddr>, client_certificate: bool) -> Option<AuditLayer> {
+        self.audit_log
+            .clone()
+            .map(|log| AuditLayer::new(log).with_connection(peer, client_certificate))
+    }
+
+    async fn call(
+        self,
+        req: Request<Body>,
+        peer: Option<SocketAddr>,
+        certificate: Option<Vec<u8>>,
+    ) -> Result<Response<Body>, Infallible> {
+        let rep = match self.route(req, peer, certificate).await {
+            Ok(body) => json_response(StatusCode::OK, &body),
+            Err(status) => {
+                audit::record_outcome(status.code(), status.message());
+                let body = serde_json::json!({
+                    "code": status.code() as i32,
+                    "message": status.message(),
+                });
+                let mut rep = json_response(http_status(status.code()), &body);
+                if let Some(retry_after) = status.metadata().get(limit::HEADER_RETRY_AFTER) {
+                    if let Ok(retry_after) = header::HeaderValue::from_bytes(retry_after.as_bytes()) {
+                        rep.headers_mut().insert(header::RETRY_AFTER, retry_after);
+                    }
+                }
+                rep
+            }
+        };
+        Ok(rep)
+    }
+
+    async fn route(
+        &self,
+        req: Request<Body>,
+        peer: Option<SocketAddr>,
+        certificate: Option<Vec<u8>>,
+    ) -> Result<serde_json::Value, Status> {
+        let method = req.method().clone();
+        let path = req.uri().path().trim_matches('/').to_owned();
+        let segments: Vec<&str> = path.split('/').collect();
+        let headers = req.headers().clone();
+        let body = hyper::body::to_bytes(req.into_body())
+            .await
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        debug!(method = ?method, path = ?path, bytes = ?body.len(), "gateway request");
+
+        if method == Method::POST && segments == ["auth", "v1", "login"] {
+            return self.login(&body, peer).await;
+        }
+        let principal = match self.auth.authenticate_peer(&headers, certificate.as_deref()).await {
+            Ok(session) => {
+                audit::record_user(session.username());
+                Principal::new(session.username())
+                    .with_grants(session.grants().to_vec())
+                    .with_databases(session.databases().to_vec())
+            }
+            Err(e) => return Err(Status::unauthenticated(e.to_string())),
+        };
+        let Some(limiter) = &self.limiter else {
+            return self.dispatch(&method, &segments, &headers, &body, &principal).await;
+        };
+        let scope = limit::Scope::new(limiter.clone());
+        scope.admit_user(&principal.username)?;
+        scope
+            .run(self.dispatch(&method, &segments, &headers, &body, &principal))
+            .await
+    }
+
+    /// Serves the authenticated request of `principal`.
+    async fn dispatch(
+        &self,
+        method: &Method,
+        segments: &[&str],
+        headers: &header::HeaderMap,
+        body: &[u8],
+        principal: &Principal,
+    ) -> Result<serde_json::Value, Status> {
+        match (method, segments) {
+            (&Method::POST, ["auth", "v1", "logout"]) => {
+                let session = headers
+                    .get(HEADER_PROTOLITH_SESSION)
+                    .and_then(|session| session.to_str().ok())
+                    .unwrap_or_default();
+                self.auth
+                    .logout(session.to_owned())
+                    .await
+                    .map_err(|e| Status::internal(e.to_string()))?;
+                Ok(serde_json::json!({}))
+            }
+            (&Method::GET, ["admin", "v1", "databases"]) => {
+                let rep = self
+                    .admin_service
+                    .list_databases(request(principal, Empty {}))
+                    .await?;
+                self.encode_json("protolith.services.v1.ListDatabasesResponse", rep.get_ref())
+            }
+            (&Method::POST, ["admin", "v1", "databases"]) => {
+                let req: CreateDatabaseRequest =
+                    self.decode_json("protolith.services.v1.CreateDatabaseRequest", body)?;
+                let rep = self
+                    .admin_service
+                    .create_database(request(principal, req))
+                    .await?;
+                self.encode_json("protolith.services.v1.CreateDatabaseResponse", rep.get_ref())
+            }
+            (&Method::POST, ["admin", "v1", "databases", database, "collections"]) => {
+                let mut req: CreateCollectionRequest =
+                    self.decode_json("protolith.services.v1.CreateCollectionRequest", body)?;
+                req.database = database.to_string();
+                let rep = self
+                    .admin_service
+                    .create_collection(request(principal, req))
+                    .await?;
+                self.encode_json("protolith.services.v1.CreateCollectionResponse", rep.get_ref())
+            }
+            (&Method::GET, ["v1", database, collection]) => {
+                self.list(principal, database, collection).await
+            }
+            (&Method::POST, ["v1", database, collection]) => {
+                let json_data = String::from_utf8(body.to_vec())
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?;
+                let req = InsertRequest {
+                    database: database.to_string(),
+                    collection: collection.to_string(),
+                    json_data,
+                    ..Default::default()
+                };
+                let rep = self.engine_service.insert(request(principal, req)).await?;
+                self.encode_json("protolith.services.v1.InsertResponse", rep.get_ref())
+            }
+            (&Method::GET, ["v1", database, collection, key]) => {
+                let key = percent_decode_str(key)
+                    .decode_utf8()
+                    .map_err(|e| Status::invalid_argument(format!("invalid key: {}", e)))?;
+                let req = GetRequest {
+                    database: database.to_string(),
+                    collection: collection.to_string(),
+                    key: Some(Value {
+                        kind: Some(Kind::StringValue(key.into_owned())),
+                    }),
+                    encoding: DocumentEncoding::Json.into(),
+                    ..Default::default()
+                };
+                let rep = self.engine_service.get(request(principal, req)).await?;
+                serde_json::from_str(&rep.into_inner().json_data)
+                    .map_err(|e| Status::internal(e.to_string()))
+            }
+            _ => Err(Status::not_found(format!("no route for {} /{}", method, segments.join("/")))),
+        }
+    }
+
+    async fn login(&self, body: &[u8], peer: Option<SocketAddr>) -> Result<serde_json::Value, Status> {
+        let req = self.decode_json("protolith.services.v1.LoginRequest", body)?;
+        let rep = self.auth.login(req, peer).await?;
+        self.encode_json("protolith.services.v1.LoginResponse", &rep)
+    }
+
+    async fn list(
+        &self,
+        principal: &Principal,
+        database: &str,
+        collection: &str,
+    ) -> Result<serde_json::Value, Status> {
+        let req = ListRequest {
+            database: database.to_string(),
+            collection: collection.to_string(),
+            ..Default::default()
+        };
+        let rep = self.engine_service.list(request(principal, req)).await?;
+        let pool = self
+            .engine
+            .descriptor_pool(database)
+            .await
+            .ok_or_else(|| Status::not_found(format!("database {} not exists", database)))?;
+        let message_desc = pool
+            .get_message_by_name(collection)
+            .ok_or_else(|| Status::not_found(format!("collection {} not exists", collection)))?;
+        let rep = rep.into_inner();
+        let mut data = Vec::with_capacity(rep.data.len());
+        for any in rep.data {
+            let document = DynamicMessage::decode(message_desc.clone(), any.value.as_slice())
+                .map_err(|e| Status::internal(e.to_string()))?;
+            data.push(serde_json::to_value(&document).map_err(|e| Status::internal(e.to_string()))?);
+        }
+        Ok(serde_json::json!({
+            "collection": rep.collection,
+            "data": data,
+        }))
+    }
+
+    /// Decodes the JSON `body` as the API message `name`.
+    fn decode_json<M: Message + Default>(&self, name: &str, body: &[u8]) -> Result<M, Status> {
+        let message_desc = self
+            .api
+            .get_message_by_name(name)
+            .ok_or_else(|| Status::internal(format!("unknown message {}", name)))?;
+        let body = if body.is_empty() { b"{}".as_slice() } else { body };
+        let mut deserializer = serde_json::Deserializer::from_slice(body);
+        let message = DynamicMessage::deserialize(message_desc, &mut deserializer)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        deserializer
+            .end()
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        message
+            .transcode_to()
+            .map_err(|e| Status::internal(e.to_string()))
+    }
+
+    /// Encodes the API message `name` as JSON.
+    fn encode_json<M: Message>(&self, name: &str, message: &M) -> Result<serde_json::Value, Status> {
+        let message_desc = self
+            .api
+            .get_message_by_name(name)
+            .ok_or_else(|| Status::internal(format!("unknown message {}", name)))?;
+        let message = DynamicMessage::decode(message_desc, message.encode_to_vec().as_slice())
+            .map_err(|e| Status::internal(e.to_string()))?;
+        serde_json::to_value(&message).map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+/// Accepts the TLS connections of `listener`, handshakes run concurrently so
+/// a slow client does not hold back the others.
+fn tls_incoming(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+) -> impl accept::Accept<Conn = TlsStream<TcpStream>, Error = std::io::Error> {
+    let (tx, rx) = mpsc::channel(64);
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    debug!(error = ?e, "failed to accept connection");
+                    continue;
+                }
+            };
+            // The server stopped accepting connections.
+            if tx.is_closed() {
+                return;
+            }
+            let (acceptor, tx) = (acceptor.clone(), tx.clone());
+            tokio::spawn(async move {
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(stream).await;
+                    }
+                    Ok(Err(e)) => debug!(?peer, error = ?e, "TLS handshake failed"),
+                    Err(_) => debug!(?peer, "TLS handshake timed out"),
+                }
+            });
+        }
+    });
+    accept::from_stream(futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|stream| (Ok(stream), rx))
+    }))
+}
+
+/// The route of a request as in the table of `Gateway`, so that the keys and
+/// names in paths do not become metric labels.
+fn route_template(method: &Method, path: &str) -> String {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let route = match (method, segments.as_slice()) {
+        (&Method::POST, ["auth", "v1", "login"]) => "/auth/v1/login",
+        (&Method::POST, ["auth", "v1", "logout"]) => "/auth/v1/logout",
+        (&Method::GET | &Method::POST, ["admin", "v1", "databases"]) => "/admin/v1/databases",
+        (&Method::POST, ["admin", "v1", "databases", _, "collections"]) => "/admin/v1/databases/{db}/collections",
+        (&Method::GET | &Method::POST, ["v1", _, _]) => "/v1/{db}/{collection}",
+        (&Method::GET, ["v1", _, _, _]) => "/v1/{db}/{collection}/{key}",
+        _ => return UNKNOWN_ROUTE.to_owned(),
+    };
+    format!("{} {}", method, route)
+}
+
+/// Wraps `message` in a request made on behalf of `principal`.
+fn request<T>(principal: &Principal, message: T) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request.extensions_mut().insert(principal.clone());
+    request
+}
+
+fn json_response(status: StatusCode, body: &serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("valid response")
+}
+
+/// Maps a gRPC status code to its HTTP counterpart.
+fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
@@ -0,0 +1,70 @@
+use std::{collections::HashSet, time::Duration};
+
+use engine::{Admin as _, ProtolithDbEngine};
+use tonic_health::{server::HealthReporter, ServingStatus};
+use tracing::{debug, info, warn};
+
+use crate::engine;
+
+/// How often the per-database statuses are refreshed.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The `grpc.health.v1` service name reporting the status of `database`.
+pub fn database_service_name(database: &str) -> String {
+    format!("protolith.db.{}", database)
+}
+
+/// Keeps the `grpc.health.v1` statuses in sync with the engine.
+///
+/// `services` and every open database (as `protolith.db.<name>`) are reported
+/// SERVING until `drain` is signaled, then everything, including the overall
+/// server status, is reported NOT_SERVING.
+pub async fn report(
+    mut reporter: HealthReporter,
+    services: Vec<&'static str>,
+    engine: ProtolithDbEngine,
+    drain: drain::Watch,
+) {
+    for service in &services {
+        reporter.set_service_status(*service, ServingStatus::Serving).await;
+    }
+    let mut databases = HashSet::new();
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    let signaled = drain.signaled();
+    tokio::pin!(signaled);
+    loop {
+        tokio::select! {
+            release = &mut signaled => {
+                info!("reporting NOT_SERVING health status");
+                reporter.set_service_status("", ServingStatus::NotServing).await;
+                for service in services.iter().copied().chain(databases.iter().map(String::as_str)) {
+                    reporter.set_service_status(service, ServingStatus::NotServing).await;
+                }
+                drop(release);
+                return;
+            }
+            _ = interval.tick() => {
+                let current = match engine.list_databases().await {
+                    Ok(rep) => rep
+                        .databases
+                        .into_iter()
+                        .map(|db| database_service_name(&db.name))
+                        .collect::<HashSet<_>>(),
+                    Err(e) => {
+                        warn!(error = ?e, "failed to list databases for health status");
+                        continue;
+                    }
+                };
+                for removed in databases.difference(&current) {
+                    debug!(service = ?removed, "clearing health status");
+                    reporter.clear_service_status(removed).await;
+                }
+                for added in current.difference(&databases) {
+                    debug!(service = ?added, "reporting SERVING health status");
+                    reporter.set_service_status(added, ServingStatus::Serving).await;
+                }
+                databases = current;
+            }
+        }
+    }
+}
@@ -1,16 +1,27 @@
 use protolith_auth::Auth;
-use protolith_core::api::service::HEADER_USER_AGENT;
-use protolith_engine::ProtolithDbEngine;
+use protolith_core::api::{
+    pbjson_types::Timestamp,
+    protolith::types::v1::AuditEvent,
+    service::{HEADER_PROTOLITH_API_KEY, HEADER_USER_AGENT},
+};
+use protolith_core::trace::{self, access_log};
+use protolith_engine::{audit, dynamic::{DynamicService, DYNAMIC_PREFIX}, limit::{self, Limiter}, rbac::Principal, ProtolithDbEngine};
+use sha2::{Digest, Sha256};
 use tower::{Layer, Service};
-use tracing::{error, trace};
-use std::{task::{Context, Poll}, fmt::Debug};
-use tonic::{body::BoxBody, Status};
-use hyper::header::HeaderValue;
+use tracing::{error, info_span, trace, Instrument, Level};
+use std::{pin::Pin, task::{ready, Context, Poll}, fmt::{Debug, Display}, net::SocketAddr, sync::atomic::{AtomicU64, Ordering}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use tonic::{
+    body::BoxBody,
+    transport::server::{TcpConnectInfo, TlsConnectInfo},
+    Code,
+    Status,
+};
+use hyper::{body::{Buf, HttpBody}, header::{self, HeaderMap, HeaderValue}, Method, Version};
 use std::sync::Arc;
-use futures_util::future::BoxFuture;
+use futures_util::{future::BoxFuture, TryStreamExt};
 use hyper::Body;
 
-use crate::BUILD_INFO;
+use crate::{audit::AuditLog, metrics::Metrics, BUILD_INFO};
 
 
 #[derive(Debug, Clone, Default)]
@@ -62,31 +73,48 @@ where
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct TracingLayer;
+/// Traces every request and records it in the access log once its response
+/// completed.
+#[derive(Debug, Clone, Default)]
+pub struct TracingLayer {
+    peer: Option<SocketAddr>,
+}
+
+impl TracingLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the peer of the connection, for servers which do not record it
+    /// in the request extensions like the HTTP gateway.
+    pub fn with_peer(mut self, peer: Option<SocketAddr>) -> Self {
+        self.peer = peer;
+        self
+    }
+}
 
 impl<S> Layer<S> for TracingLayer {
     type Service = TracingService<S>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        TracingService { inner }
+        TracingService { inner, peer: self.peer }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct TracingService<S> {
     inner: S,
+    peer: Option<SocketAddr>,
 }
 
-impl<S, ReqBody, ResBody> Service<hyper::Request<ReqBody>> for TracingService<S>
+impl<S, ResBody> Service<hyper::Request<Body>> for TracingService<S>
 where
-    S: Service<hyper::Request<ReqBody>, Response = hyper::Response<ResBody>> + Clone + Send + 'static,
+    S: Service<hyper::Request<Body>, Response = hyper::Response<ResBody>> + Clone + Send + 'static,
     S::Future: Send + 'static,
-    ReqBody: Send + 'static,
-    ResBody: Send + 'static,
-    <S as Service<hyper::Request<ReqBody>>>::Error: std::fmt::Debug,
+    ResBody: HttpBody + Unpin + Send + 'static,
+    <S as Service<hyper::Request<Body>>>::Error: std::fmt::Debug,
 {
-    type Response = S::Response;
+    type Response = hyper::Response<AccessLogBody<ResBody>>;
     type Error = S::Error;
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
@@ -94,24 +122,476 @@ where
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, req: hyper::Request<ReqBody>) -> Self::Future {
+    fn call(&mut self, mut req: hyper::Request<Body>) -> Self::Future {
         let uri = req.uri().clone();
         let clone = self.inner.clone();
         let headers = req.headers().clone();
         let ua = headers.get(HEADER_USER_AGENT).unwrap_or(&hyper::header::HeaderValue::from_static("unknown")).clone();
         let session = headers.get("protolith-session").unwrap_or(&hyper::header::HeaderValue::from_static("unknown")).clone();
         let mut inner = std::mem::replace(&mut self.inner, clone);
+        // Continues the trace of the caller, if any.
+        let span = info_span!("request", otel.name = %uri.path(), otel.kind = "server");
+        trace::otel::set_parent(&span, &headers);
+
+        let mut access = tracing::enabled!(target: access_log::TRACE_TARGET, Level::INFO).then(|| {
+            let request_bytes = Arc::new(AtomicU64::new(0));
+            let counted = request_bytes.clone();
+            let body = std::mem::take(req.body_mut()).inspect_ok(move |chunk| {
+                counted.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            });
+            *req.body_mut() = Body::wrap_stream(body);
+            Access {
+                peer: self.peer.or_else(|| remote_addr(&req)),
+                timestamp: SystemTime::now(),
+                start: Instant::now(),
+                method: req.method().clone(),
+                path: uri.path().to_owned(),
+                version: req.version(),
+                user_agent: headers.get(header::USER_AGENT).cloned(),
+                request_bytes,
+                processing: Duration::ZERO,
+                status: None,
+                grpc_status: None,
+                response_bytes: 0,
+                username: String::new(),
+            }
+        });
         Box::pin(async move {
             trace!(path = ?uri.path(), ua = ?ua, session = ?session, "request:");
-            let response = inner.call(req).await;
-            if let Err(e) = &response {
-                error!(path = ?uri.path(), ua = ?ua, error = ?e,"Error processing request:");
+            // The services record the user of the request in the scope.
+            let scope = audit::Scope::default();
+            let response = scope.run(inner.call(req)).await;
+            if let Some(access) = &mut access {
+                access.processing = access.start.elapsed();
+                access.username = scope.target().username;
+            }
+            match response {
+                Ok(response) => {
+                    if let Some(access) = &mut access {
+                        access.status = Some(response.status().as_u16());
+                        access.grpc_status = grpc_code(response.headers());
+                    }
+                    Ok(response.map(|inner| AccessLogBody { inner, access }))
+                }
+                Err(e) => {
+                    error!(path = ?uri.path(), ua = ?ua, error = ?e,"Error processing request:");
+                    Err(e)
+                }
             }
+        }.instrument(span))
+    }
+}
+
+/// A request recorded in the access log once its response completed.
+struct Access {
+    peer: Option<SocketAddr>,
+    timestamp: SystemTime,
+    start: Instant,
+    method: Method,
+    path: String,
+    version: Version,
+    user_agent: Option<HeaderValue>,
+    request_bytes: Arc<AtomicU64>,
+    /// How long until the response headers.
+    processing: Duration,
+    username: String,
+    status: Option<u16>,
+    grpc_status: Option<Code>,
+    response_bytes: u64,
+}
+
+impl Drop for Access {
+    fn drop(&mut self) {
+        let peer = self.peer.map(|peer| peer.to_string());
+        let username = if self.username.is_empty() { "-" } else { self.username.as_str() };
+        // The span closes right away, the access log writes its fields in
+        // the order they are declared.
+        let _span = info_span!(
+            target: access_log::TRACE_TARGET,
+            "access",
+            client.addr = peer.as_deref().unwrap_or("-"),
+            client.id = username,
+            timestamp = %httpdate::fmt_http_date(self.timestamp),
+            method = %self.method,
+            uri = %self.path,
+            version = ?self.version,
+            status = self.status,
+            response_bytes = self.response_bytes,
+            grpc_status = self.grpc_status.map(|code| code as i32),
+            request_bytes = self.request_bytes.load(Ordering::Relaxed),
+            processing_ns = self.processing.as_nanos() as u64,
+            total_ns = self.start.elapsed().as_nanos() as u64,
+            user_agent = self.user_agent.as_ref().and_then(|ua| ua.to_str().ok()),
+        );
+    }
+}
+
+/// The body of a response counting its bytes and recording the gRPC status
+/// sent in the trailers for the access log.
+pub struct AccessLogBody<B> {
+    inner: B,
+    access: Option<Access>,
+}
+
+impl<B: HttpBody + Unpin> HttpBody for AccessLogBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let data = ready!(Pin::new(&mut self.inner).poll_data(cx));
+        if let (Some(access), Some(Ok(data))) = (&mut self.access, &data) {
+            access.response_bytes += data.remaining() as u64;
+        }
+        Poll::Ready(data)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let trailers = ready!(Pin::new(&mut self.inner).poll_trailers(cx));
+        if let (Some(access), Ok(Some(trailers))) = (&mut self.access, &trailers) {
+            access.grpc_status = grpc_code(trailers).or(access.grpc_status);
+        }
+        Poll::Ready(trailers)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// The remote address of a request served by tonic, over TLS or not.
+fn remote_addr<B>(req: &hyper::Request<B>) -> Option<SocketAddr> {
+    req.extensions()
+        .get::<TlsConnectInfo<TcpConnectInfo>>()
+        .and_then(|info| info.get_ref().remote_addr())
+        .or_else(|| req.extensions().get::<TcpConnectInfo>().and_then(|info| info.remote_addr()))
+}
+
+/// Names the route of a request in the request metrics.
+pub type RouteFn = fn(&Method, &str) -> String;
+
+#[derive(Clone)]
+pub struct MetricsLayer {
+    metrics: Metrics,
+    route: RouteFn,
+}
+
+impl MetricsLayer {
+    /// Records the requests to gRPC services, routed by their path.
+    pub fn new(metrics: Metrics) -> Self {
+        MetricsLayer { metrics, route: |_, path| path.to_owned() }
+    }
+
+    /// Sets how the route of requests is named, such as the route templates
+    /// of the HTTP gateway, whose paths hold documents keys.
+    pub fn with_route(mut self, route: RouteFn) -> Self {
+        self.route = route;
+        self
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsSvc<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsSvc { inner, layer: self.clone() }
+    }
+}
+
+/// Counts the requests and how long they took by route and status, gRPC
+/// statuses sent in the trailers are recorded once the body completes.
+#[derive(Clone)]
+pub struct MetricsSvc<S> {
+    inner: S,
+    layer: MetricsLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<hyper::Request<ReqBody>> for MetricsSvc<S>
+where
+    S: Service<hyper::Request<ReqBody>, Response = hyper::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: HttpBody + Unpin + Send + 'static,
+{
+    type Response = hyper::Response<MetricsBody<ResBody>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<ReqBody>) -> Self::Future {
+        // This is necessary because tonic internally uses `tower::buffer::Buffer`.
+        // See https://github.com/tower-rs/tower/issues/547#issuecomment-767629149
+        // for details on why this is necessary
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let mut pending = Pending {
+            metrics: self.layer.metrics.clone(),
+            route: (self.layer.route)(req.method(), req.uri().path()),
+            start: Instant::now(),
+        };
+        Box::pin(async move {
+            let response = match inner.call(req).await {
+                Ok(response) => response,
+                Err(e) => {
+                    pending.record("Error");
+                    return Err(e);
+                }
+            };
+            let grpc = response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/grpc"));
+            let pending = match grpc_code(response.headers()) {
+                Some(code) => {
+                    pending.record(&format!("{:?}", code));
+                    None
+                }
+                // The status follows the body.
+                None if grpc => Some(pending),
+                None => {
+                    pending.record(response.status().as_str());
+                    None
+                }
+            };
+            Ok(response.map(|inner| MetricsBody { inner, pending }))
+        })
+    }
+}
+
+/// A request whose completion is not recorded yet.
+struct Pending {
+    metrics: Metrics,
+    route: String,
+    start: Instant,
+}
+
+impl Pending {
+    fn record(&mut self, code: &str) {
+        self.metrics.record(&self.route, code, self.start.elapsed());
+    }
+}
+
+/// The body of a gRPC response recording its status from the trailers.
+pub struct MetricsBody<B> {
+    inner: B,
+    pending: Option<Pending>,
+}
+
+impl<B: HttpBody + Unpin> HttpBody for MetricsBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let trailers = ready!(Pin::new(&mut self.inner).poll_trailers(cx));
+        if let Some(mut pending) = self.pending.take() {
+            let code = match &trailers {
+                Ok(trailers) => trailers.as_ref().and_then(grpc_code).unwrap_or(Code::Unknown),
+                Err(_) => Code::Internal,
+            };
+            pending.record(&format!("{:?}", code));
+        }
+        Poll::Ready(trailers)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B> Drop for MetricsBody<B> {
+    fn drop(&mut self) {
+        // The client went away before the trailers were sent.
+        if let Some(mut pending) = self.pending.take() {
+            pending.record(&format!("{:?}", Code::Cancelled));
+        }
+    }
+}
+
+fn grpc_code(headers: &HeaderMap) -> Option<Code> {
+    headers.get("grpc-status").map(|code| Code::from_bytes(code.as_bytes()))
+}
+
+#[derive(Clone)]
+pub struct AuditLayer {
+    log: AuditLog,
+    peer: Option<SocketAddr>,
+    client_certificate: bool,
+}
+
+impl AuditLayer {
+    pub fn new(log: AuditLog) -> Self {
+        AuditLayer { log, peer: None, client_certificate: false }
+    }
+
+    /// Sets the connection details of services not served by tonic, which
+    /// records them in the request extensions.
+    pub fn with_connection(mut self, peer: Option<SocketAddr>, client_certificate: bool) -> Self {
+        self.peer = peer;
+        self.client_certificate = client_certificate;
+        self
+    }
+}
+
+impl<S> Layer<S> for AuditLayer {
+    type Service = AuditSvc<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuditSvc { inner, layer: self.clone() }
+    }
+}
+
+/// Records an `AuditEvent` for every request but health checks and
+/// reflection, once the request completed.
+#[derive(Clone)]
+pub struct AuditSvc<S> {
+    inner: S,
+    layer: AuditLayer,
+}
+
+/// Paths the audit log skips, polled by probes and tooling.
+const UNAUDITED_PATHS: &[&str] = &[
+    "/grpc.health.v1.Health/",
+    "/grpc.reflection.v1alpha.ServerReflection/",
+];
+
+impl<S, ReqBody, ResBody> Service<hyper::Request<ReqBody>> for AuditSvc<S>
+where
+    S: Service<hyper::Request<ReqBody>, Response = hyper::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Display + Send,
+    ReqBody: Send + 'static,
+    ResBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<ReqBody>) -> Self::Future {
+        // This is necessary because tonic internally uses `tower::buffer::Buffer`.
+        // See https://github.com/tower-rs/tower/issues/547#issuecomment-767629149
+        // for details on why this is necessary
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let path = req.uri().path().to_owned();
+        if UNAUDITED_PATHS.iter().any(|p| path.starts_with(p)) {
+            return Box::pin(inner.call(req));
+        }
+        let grpc = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/grpc"));
+        let path = if grpc { path } else { format!("{} {}", req.method(), path) };
+        let tls = req.extensions().get::<TlsConnectInfo<TcpConnectInfo>>();
+        let peer = self.layer.peer.or_else(|| remote_addr(&req));
+        let client_certificate = self.layer.client_certificate
+            || tls.and_then(|info| info.peer_certs()).is_some_and(|certs| !certs.is_empty());
+        let session = credential(req.headers(), client_certificate);
+        let log = self.layer.log.clone();
+        Box::pin(async move {
+            // Shares the scope of the tracing layer, when it runs first.
+            let scope = audit::Scope::current().unwrap_or_default();
+            let response = scope.run(inner.call(req)).await;
+            let target = scope.target();
+            let (code, message) = match (&response, target.outcome) {
+                (_, Some(outcome)) => outcome,
+                (Ok(response), None) => match Status::from_header_map(response.headers()) {
+                    Some(status) => (status.code(), status.message().to_owned()),
+                    None => (Code::Ok, String::new()),
+                },
+                (Err(e), None) => (Code::Unknown, e.to_string()),
+            };
+            let event = AuditEvent {
+                id: uuid::Uuid::new_v4().simple().to_string(),
+                time: Some(now()),
+                username: target.username,
+                session,
+                path,
+                database: target.database,
+                collection: target.collection,
+                key: target.key,
+                code: code as i32,
+                message,
+                peer: peer.map(|peer| peer.to_string()).unwrap_or_default(),
+            };
+            log.record(event).await;
             response
         })
     }
 }
 
+/// Describes the credential of a request without revealing it, sessions
+/// are identified by a digest of their token and API keys by their id.
+fn credential(headers: &HeaderMap, client_certificate: bool) -> String {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+    };
+    if let Some(api_key) = header(HEADER_PROTOLITH_API_KEY) {
+        let id = api_key
+            .strip_prefix("pk_")
+            .and_then(|key| key.split_once('.'))
+            .map(|(id, _)| id)
+            .unwrap_or("invalid");
+        format!("api_key:{}", id)
+    } else if header(header::AUTHORIZATION.as_str()).is_some_and(|value| value.starts_with("Bearer ")) {
+        "jwt".to_string()
+    } else if let Some(session) = header(HEADER_PROTOLITH_SESSION) {
+        let digest: String = Sha256::digest(session.as_bytes())
+            .iter()
+            .take(8)
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        format!("session:{}", digest)
+    } else if client_certificate {
+        "certificate".to_string()
+    } else {
+        String::new()
+    }
+}
+
+fn now() -> Timestamp {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Timestamp {
+        seconds: since_epoch.as_secs() as i64,
+        nanos: since_epoch.subsec_nanos() as i32,
+    }
+}
 
 #[derive(Clone)]
 pub struct SessionLayer {
@@ -140,13 +620,20 @@ pub struct SessionSvc<S> {
     auth: Arc<Auth<ProtolithDbEngine>>, 
 }
 
-pub const HEADER_PROTOLITH_SESSION: &str = "protolith-session";
+pub use protolith_auth::HEADER_PROTOLITH_SESSION;
+
+/// Paths served without a session: login itself, and the health checking
+/// and reflection services used by probes and tooling.
+const UNAUTHENTICATED_PATHS: &[&str] = &[
+    "/protolith.services.v1.AuthService/Login",
+    "/grpc.health.v1.Health/",
+    "/grpc.reflection.v1alpha.ServerReflection/",
+];
 
 impl<S> Service<hyper::Request<Body>> for SessionSvc<S>
 where
     S: Service<hyper::Request<Body>, Response = hyper::Response<BoxBody>> + Clone + Send + 'static,
     S::Future: Send + 'static,
-    <S as Service<hyper::Request<hyper::Body>>>::Error: From<Status>,
 {
     type Response = S::Response;
     type Error = S::Error;
@@ -156,41 +643,231 @@ where
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, req: hyper::Request<Body>) -> Self::Future {
+    fn call(&mut self, mut req: hyper::Request<Body>) -> Self::Future {
         // This is necessary because tonic internally uses `tower::buffer::Buffer`.
         // See https://github.com/tower-rs/tower/issues/547#issuecomment-767629149
         // for details on why this is necessary
         let clone = self.inner.clone();
         let mut inner = std::mem::replace(&mut self.inner, clone);
         
-        let session_token = extract_session_token_from_metadata(&req);
         let auth = self.auth.clone();
         Box::pin(async move {
-            if req.uri().path() == "/protolith.services.v1.AuthService/Login" {
+            let path = req.uri().path();
+            if UNAUTHENTICATED_PATHS.iter().any(|p| path.starts_with(p)) {
                 let fut = inner.call(req)
                     .await?;
                 Ok(fut)
             } else {
-                let session = auth.get_session(session_token.clone());
-                let sessions = auth.sessions().await;
-                println!("interceptor: {:?}", sessions);
-                if let Some(_session) = session {
-                    let fut = inner.call(req)
-                        .await?;
-                    Ok(fut)
-                } else {
-                    Err(Status::unauthenticated(format!("session {:?} is not exists", session_token)).into())
+                // A client certificate verified by the TLS handshake.
+                let certificate = req
+                    .extensions()
+                    .get::<TlsConnectInfo<TcpConnectInfo>>()
+                    .and_then(|info| info.peer_certs())
+                    .and_then(|certs| certs.first().map(|cert| cert.get_ref().to_vec()));
+                match auth.authenticate_peer(req.headers(), certificate.as_deref()).await {
+                    Ok(session) => {
+                        // The services authorize the request against the grants of its user.
+                        let principal = Principal::new(session.username())
+                            .with_grants(session.grants().to_vec())
+                            .with_databases(session.databases().to_vec());
+                        audit::record_user(&principal.username);
+                        req.extensions_mut().insert(principal);
+                        let fut = inner.call(req)
+                            .await?;
+                        Ok(fut)
+                    }
+                    Err(e) => Ok(Status::unauthenticated(e.to_string()).to_http()),
                 }
             }
         })
     }
 }
 
+#[derive(Clone)]
+pub struct LimitLayer {
+    limiter: Arc<Limiter>,
+}
 
-fn extract_session_token_from_metadata(req: &hyper::Request<Body>) -> String {
-    if let Some(token) = req.headers().get(HEADER_PROTOLITH_SESSION) {
-        token.to_str().unwrap().to_owned()
-    } else {
-        "unknown".to_string()
+impl LimitLayer {
+    pub fn new(limiter: Arc<Limiter>) -> Self {
+        LimitLayer { limiter }
+    }
+}
+
+impl<S> Layer<S> for LimitLayer {
+    type Service = LimitSvc<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        LimitSvc { inner: service, limiter: self.limiter.clone() }
+    }
+}
+
+/// Enforces the request limits of the session user, and runs the request
+/// within a `limit::Scope` so that the services enforce those of the
+/// databases they reach.
+#[derive(Clone)]
+pub struct LimitSvc<S> {
+    inner: S,
+    limiter: Arc<Limiter>,
+}
+
+impl<S> Service<hyper::Request<Body>> for LimitSvc<S>
+where
+    S: Service<hyper::Request<Body>, Response = hyper::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<Body>) -> Self::Future {
+        // This is necessary because tonic internally uses `tower::buffer::Buffer`.
+        // See https://github.com/tower-rs/tower/issues/547#issuecomment-767629149
+        // for details on why this is necessary
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let scope = limit::Scope::new(self.limiter.clone());
+        // Unauthenticated requests carry no principal and are not limited.
+        if let Some(principal) = req.extensions().get::<Principal>() {
+            if let Err(status) = scope.admit_user(&principal.username) {
+                trace!(user = ?principal.username, status = ?status, "request refused by limits");
+                return Box::pin(async move { Ok(status.to_http()) });
+            }
+        }
+        Box::pin(async move { scope.run(inner.call(req)).await })
+    }
+}
+
+#[derive(Clone)]
+pub struct DynamicLayer {
+    engine: ProtolithDbEngine,
+}
+
+impl DynamicLayer {
+    pub fn new(engine: ProtolithDbEngine) -> Self {
+        DynamicLayer { engine }
+    }
+}
+
+impl<S> Layer<S> for DynamicLayer {
+    type Service = Dynamic<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Dynamic { inner: service, dynamic: DynamicService::new(self.engine.clone()) }
+    }
+}
+
+/// Routes the `protolith.dyn.*` services to the `DynamicService`, these are
+/// not known when building the tonic router.
+#[derive(Clone)]
+pub struct Dynamic<S> {
+    inner: S,
+    dynamic: DynamicService,
+}
+
+impl<S> Service<hyper::Request<Body>> for Dynamic<S>
+where
+    S: Service<hyper::Request<Body>, Response = hyper::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<Body>) -> Self::Future {
+        if req.uri().path().starts_with(DYNAMIC_PREFIX) {
+            let mut dynamic = self.dynamic.clone();
+            return Box::pin(async move {
+                match dynamic.call(req).await {
+                    Ok(response) => Ok(response),
+                    Err(never) => match never {},
+                }
+            });
+        }
+        // This is necessary because tonic internally uses `tower::buffer::Buffer`.
+        // See https://github.com/tower-rs/tower/issues/547#issuecomment-767629149
+        // for details on why this is necessary
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(inner.call(req))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{convert::Infallible, io, sync::Mutex};
+
+    /// What the access log wrote.
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Serves a request through the `TracingLayer` and returns what the
+    /// access log in `format` wrote for it.
+    async fn access_log(format: &str) -> String {
+        let lines = Lines::default();
+        let writer = lines.clone();
+        let subscriber = access_log::subscriber(format.parse().unwrap(), move || writer.clone());
+        let _subscriber = tracing::subscriber::set_default(subscriber);
+        let service = tower::service_fn(|req: hyper::Request<Body>| async move {
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            Ok::<_, Infallible>(hyper::Response::new(Body::from(format!("received {} bytes", body.len()))))
+        });
+        let mut service = TracingLayer::new()
+            .with_peer(Some("127.0.0.1:4242".parse().unwrap()))
+            .layer(service);
+        let req = hyper::Request::post("/v1/db/collection")
+            .header(header::USER_AGENT, "test-agent")
+            .body(Body::from("hello"))
+            .unwrap();
+
+        let response = service.call(req).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "received 5 bytes");
+        let written = lines.0.lock().unwrap().clone();
+        String::from_utf8(written).unwrap()
+    }
+
+    #[tokio::test]
+    async fn logs_requests_once_their_response_completed() {
+        let line = access_log("apache").await;
+        let (client, rest) = line.split_once(" [").unwrap();
+        assert_eq!(client, "127.0.0.1:4242 - -");
+        let (_timestamp, request) = rest.split_once("] ").unwrap();
+        assert_eq!(request, "\"POST /v1/db/collection HTTP/1.1\" 200 16\n");
+
+        let line = access_log("json").await;
+        let fields: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(fields["client.addr"], "127.0.0.1:4242");
+        assert_eq!(fields["client.id"], "-");
+        assert_eq!(fields["method"], "POST");
+        assert_eq!(fields["uri"], "/v1/db/collection");
+        assert_eq!(fields["version"], "HTTP/1.1");
+        assert_eq!(fields["status"], 200);
+        assert_eq!(fields["request_bytes"], 5);
+        assert_eq!(fields["response_bytes"], 16);
+        assert_eq!(fields["user_agent"], "test-agent");
+        assert!(fields["timestamp"].is_string());
+        assert!(fields.get("grpc_status").is_none());
+    }
+}
@@ -5,12 +5,18 @@ pub mod env;
 mod build_info;
 pub mod signals;
 mod layer;
-use layer::{MetadataLayer, TracingLayer, SessionLayer};
+mod metrics;
+mod admin_server;
+pub mod audit;
+mod gateway;
+mod health;
+pub mod tls;
+use layer::{AuditLayer, DynamicLayer, LimitLayer, MetadataLayer, MetricsLayer, TracingLayer, SessionLayer};
 pub use build_info::BUILD_INFO;
 use engine::{ProtolithDbEngine, service::ProtolithEngineService, Admin as _};
-use protolith_core::{error::Error, api::{DescriptorPool, prost::bytes::Bytes}};
+use protolith_core::{error::Error, memory::Memory, api::{DescriptorPool, FILE_DESCRIPTOR_SET, prost::bytes::Bytes}};
 use tracing::{debug, error, info, warn};
-use std::{time::{Duration, Instant}, collections::{HashMap, HashSet}, sync::{Arc, Mutex}, net::SocketAddr, path::{PathBuf, Path}, fs::{self, File}, io::{BufReader, Read, Write}};
+use std::{time::Duration, collections::{HashMap, HashSet}, sync::Arc, net::SocketAddr, path::{PathBuf, Path}, fs::{self, File}, io::{BufReader, Read}};
 use drain;
 pub use protolith_core::{
     db,
@@ -21,28 +27,27 @@ pub use protolith_core::{
 use protolith_admin as admin;
 use protolith_engine as engine;
 use protolith_auth as auth;
-use tonic::transport::Server;
+use tonic::{server::NamedService, transport::Server};
 pub const EX_USAGE: i32 = 64;
 
 
 pub struct App {
     addr: SocketAddr,
+    http_addr: Option<SocketAddr>,
+    admin_addr: Option<SocketAddr>,
+    admin_logs: bool,
+    server: Server,
+    gateway_tls: Option<tokio_rustls::TlsAcceptor>,
+    audit_log: Option<audit::AuditLog>,
+    limiter: Option<Arc<engine::limit::Limiter>>,
     admin: admin::Admin<ProtolithDbEngine>,
     auth: auth::Auth<ProtolithDbEngine>,
+    trace: trace::Handle,
     drain: drain::Signal,
     engine: ProtolithDbEngine,
-    sessions: Arc<Mutex<HashMap<String, Session>>>,
     destroy_on_shutdown: bool,
 }
 
-const SESSIONS_FILE: &str = "/Users/amitshmulevitch/rusty-land/protolith-db/sessions";
-
-#[derive(Debug, Clone)]
-pub struct Session {
-    user_id: String,
-    last_accessed: Instant,  // Use this to expire sessions
-}
-
 #[derive(Debug, Clone)]
 pub struct Config {
     pub db: db::Config,
@@ -51,6 +56,12 @@ pub struct Config {
     meta_store: meta_store::Config,
     schema: schema::Config,
     addr: SocketAddr,
+    http_addr: Option<SocketAddr>,
+    admin_addr: Option<SocketAddr>,
+    admin_logs: bool,
+    tls: Option<tls::Config>,
+    audit: audit::Config,
+    limits: engine::limit::Config,
     pub default_database: (String, PathBuf),
     pub destroy_on_shutdown: bool,
     pub shutdown_grace_period: Duration,
@@ -65,7 +76,7 @@ impl Config {
 impl Config {
     pub async fn build(
         self,
-        _trace: trace::Handle,
+        trace: trace::Handle,
     ) -> Result<App, Error> {
         let Config {
             db,
@@ -75,6 +86,12 @@ impl Config {
             schema,
             default_database,
             addr,
+            http_addr,
+            admin_addr,
+            admin_logs,
+            tls,
+            audit,
+            limits,
             destroy_on_shutdown,
             ..
         } = self;
@@ -85,6 +102,7 @@ impl Config {
 
         debug!(config = ?db, "Building RocksDB Embedded Server");
         let mut dbs = HashMap::new();
+        let memory = Memory::new(db.cache_size, db.write_buffer_budget);
         
         let existing_databases = find_rocksdb_databases(db.db_path.as_path());
         let mut folder_set = HashSet::new();
@@ -116,31 +134,47 @@ impl Config {
                 }
             };
             let pool = DescriptorPool::decode(f).unwrap();
-            let rocksdb = db.clone().build(db_name.clone(), meta_store.clone(), schema.clone(), pool)?;
+            let rocksdb = db.clone().build(db_name.clone(), meta_store.clone(), schema.clone(), pool, &memory)?;
             dbs.insert(db_name,  rocksdb);
         }
         
-        let engine = engine::ProtolithDbEngine::new(db, meta_store.clone(), schema.clone(), dbs.clone());
+        let engine = engine::ProtolithDbEngine::new(db, memory, meta_store.clone(), schema.clone(), dbs.clone());
         // let meta_store = meta_store.build(dbs.clone());
         debug!(config = ?schema, "Building Schema");
 
         debug!(config = ?admin, "Building Admin Service");
         let engine_arc = Arc::new(engine.clone());
         let admin = admin.build(engine_arc.clone(), drain_rx.clone())?;
-        let mut auth = auth.build(engine_arc).await?;
-        match load_sessions(SESSIONS_FILE) {
-            Ok(sess) => auth.set_sessions(sess).await,
-            Err(err) => warn!(error = ?err, "not loaded any sessions")
-        }
-        info!(?addr, "Serving protolith db instance at");
+        let auth = auth.build(engine_arc).await?;
+        debug!(config = ?audit, "Building Audit Log");
+        let audit_log = audit.build(engine.clone()).await?;
+        debug!(config = ?limits, "Building Limits");
+        let limiter = limits.build();
+
+        let (server, gateway_tls) = match tls {
+            Some(tls) => {
+                debug!(config = ?tls, "Building TLS");
+                let server = Server::builder().tls_config(tls.server_tls_config()?)?;
+                (server, Some(tls.acceptor()?))
+            }
+            None => (Server::builder(), None),
+        };
+        info!(?addr, tls = ?gateway_tls.is_some(), "Serving protolith db instance at");
         Ok(App {
             admin,
             addr,
+            http_addr,
+            admin_addr,
+            admin_logs,
+            server,
+            gateway_tls,
+            audit_log,
+            limiter,
             engine,
             auth,
+            trace,
             destroy_on_shutdown,
             drain: drain_tx,
-            sessions: Arc::new(Mutex::new(HashMap::new())),
         })
     }
 }
@@ -151,11 +185,18 @@ impl App {
         let Self { 
             admin,
             addr,
+            http_addr,
+            admin_addr,
+            admin_logs,
+            server,
+            gateway_tls,
+            audit_log,
+            limiter,
             drain,
             engine,
             destroy_on_shutdown,
-            sessions,
             auth,
+            trace,
             ..
         } = self;
         
@@ -164,19 +205,57 @@ impl App {
         let mut engine = engine.clone();
         let engine_service = ProtolithEngineService::new(engine.clone()).service();
         let auth_arc = Arc::new(auth);
+        let metrics = metrics::Metrics::new();
+        if let Some(admin_addr) = admin_addr {
+            let admin_server = admin_server::AdminServer::new(engine.clone(), auth_arc.clone(), trace, metrics.clone(), admin_logs);
+            let drain = admin.drain.clone();
+            tokio::spawn(async move {
+                if let Err(e) = admin_server.serve(admin_addr, drain).await {
+                    error!(error = ?e, "admin server error");
+                }
+            });
+        }
+        if let Some(http_addr) = http_addr {
+            let gateway = gateway::Gateway::new(engine.clone(), admin.handler(), auth_arc.clone(), audit_log.clone(), limiter.clone())
+                .with_metrics(metrics.clone());
+            let drain = admin.drain.clone();
+            tokio::spawn(async move {
+                if let Err(e) = gateway.serve(http_addr, gateway_tls, drain).await {
+                    error!(error = ?e, "HTTP gateway error");
+                }
+            });
+        }
+        tokio::spawn(sweep_expired(auth_arc.clone(), admin.drain.clone()));
         let session_layer = SessionLayer::new(auth_arc.clone());
         let auth_service = auth_arc.service(max_message_size);
+        let reflection_service = tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+            .build()
+            .expect("building reflection service");
+        let (health_reporter, health_service) = tonic_health::server::health_reporter();
+        let services = vec![
+            service_name(&admin_service),
+            service_name(&engine_service),
+            service_name(&auth_service),
+        ];
+        tokio::spawn(health::report(health_reporter, services, engine.clone(), admin.drain.clone()));
         let layer = tower::ServiceBuilder::new()
             // .timeout(Duration::from_secs(30))
-            .layer(TracingLayer)
+            .layer(MetricsLayer::new(metrics))
+            .layer(TracingLayer::new())
             .layer(MetadataLayer)
+            .option_layer(audit_log.map(AuditLayer::new))
             .layer(session_layer)
+            .option_layer(limiter.map(LimitLayer::new))
+            .layer(DynamicLayer::new(engine.clone()))
             .into_inner();
-        let server = Server::builder()
+        let server = server
             .layer(layer)
             .add_service(admin_service)
             .add_service(engine_service)
             .add_service(auth_service)
+            .add_service(reflection_service)
+            .add_service(health_service)
             .serve_with_shutdown(addr, async move {
                 let release = admin.drain.clone().signaled().await;
                 info!("starting RocksDB shutdown");
@@ -188,12 +267,10 @@ impl App {
                     }
                     for db in to_destroy {
                         warn!(db = ?db, "Destroying");
-                        engine.destroy_db(&db).await.expect("destroying database");
+                        if let Err(e) = engine.destroy_db(&db).await {
+                            error!(db = ?db, error = ?e, "failed to destroy database");
+                        }
                     }
-                } else {
-                    let sessions = auth_arc.sessions().await;
-                    println!("{:?}", sessions);
-                    save_sessions(&sessions, SESSIONS_FILE).expect("presisting sessions");
                 }
                 drop(release)
             });
@@ -207,6 +284,10 @@ impl App {
     }
 }
 
+fn service_name<S: NamedService>(_service: &S) -> &'static str {
+    S::NAME
+}
+
 // Function to check if a directory contains a RocksDB database
 fn contains_rocksdb<P: AsRef<Path>>(dir: P) -> bool {
     let rocksdb_files = ["IDENTITY", "CURRENT"];
@@ -243,25 +324,30 @@ fn find_rocksdb_databases<P: AsRef<Path>>(path: P) -> Vec<String> {
     databases
 }
 
-
-
-fn save_sessions(sessions: &HashMap<String, auth::Session>, file_path: &str) -> Result<(), std::io::Error> {
-    // Serialize the session map to a JSON string
-    let serialized = serde_json::to_string(sessions)?;
-
-    // Write the JSON string to a file
-    let mut file = std::fs::File::create(file_path)?;
-    file.write_all(serialized.as_bytes())?;
-    Ok(())
+/// Deletes the expired sessions and API keys every
+/// `auth::Auth::sweep_interval` until `drain` is signaled.
+async fn sweep_expired(auth: Arc<auth::Auth<ProtolithDbEngine>>, drain: drain::Watch) {
+    let mut interval = tokio::time::interval(auth.sweep_interval());
+    let signaled = drain.signaled();
+    tokio::pin!(signaled);
+    loop {
+        tokio::select! {
+            release = &mut signaled => {
+                drop(release);
+                return;
+            }
+            _ = interval.tick() => {
+                match auth.sweep_sessions().await {
+                    Ok(0) => {}
+                    Ok(swept) => info!(swept = ?swept, "deleted expired sessions"),
+                    Err(e) => warn!(error = ?e, "failed to sweep sessions"),
+                }
+                match auth.sweep_api_keys().await {
+                    Ok(0) => {}
+                    Ok(swept) => info!(swept = ?swept, "deleted expired api keys"),
+                    Err(e) => warn!(error = ?e, "failed to sweep api keys"),
+                }
+            }
+        }
+    }
 }
-
-fn load_sessions(file_path: &str) -> Result<HashMap<String, auth::Session>, std::io::Error> {
-    // Read the entire file content
-    let mut file = std::fs::File::open(file_path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-
-    // Deserialize the JSON string to a SessionMap
-    let sessions = serde_json::from_str(&contents)?;
-    Ok(sessions)
-}
\ No newline at end of file
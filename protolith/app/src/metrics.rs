@@ -0,0 +1,235 @@
+//! The Prometheus metrics served by the admin server.
+//!
+//! Requests are counted as they complete by the `MetricsLayer`, the database,
+//! session and process metrics are read on every scrape.
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use prometheus_client::{
+    collector::Collector,
+    encoding::{text, DescriptorEncoder, EncodeLabelSet, EncodeMetric},
+    metrics::{
+        counter::{ConstCounter, Counter},
+        family::Family,
+        gauge::ConstGauge,
+        histogram::{exponential_buckets, Histogram},
+        MetricType,
+    },
+    registry::{Registry, Unit},
+};
+use protolith_core::{db, memory, trace};
+use tracing::warn;
+
+use crate::{auth::Auth, engine::ProtolithDbEngine};
+
+/// The route of the requests for which no service exists, so that their
+/// paths do not become labels.
+pub const UNKNOWN_ROUTE: &str = "unknown";
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RequestLabels {
+    route: String,
+    code: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RouteLabels {
+    route: String,
+}
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Arc<Registry>,
+    requests: Family<RequestLabels, Counter>,
+    request_duration: Family<RouteLabels, Histogram>,
+    snapshot: Snapshot,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let mut registry = Registry::with_prefix("protolith");
+        let requests = Family::<RequestLabels, Counter>::default();
+        registry.register(
+            "requests",
+            "Requests completed, by route and gRPC status or HTTP status code",
+            requests.clone(),
+        );
+        let request_duration = Family::<RouteLabels, Histogram>::new_with_constructor(|| {
+            // 1ms up to about 16s.
+            Histogram::new(exponential_buckets(0.001, 2.0, 15))
+        });
+        registry.register_with_unit(
+            "request_duration",
+            "How long requests took until their response completed",
+            Unit::Seconds,
+            request_duration.clone(),
+        );
+        let snapshot = Snapshot::default();
+        registry.register_collector(Box::new(snapshot.clone()));
+        Self {
+            registry: Arc::new(registry),
+            requests,
+            request_duration,
+            snapshot,
+        }
+    }
+
+    /// Records a request to `route` completed with `code` after `elapsed`.
+    pub fn record(&self, route: &str, code: &str, elapsed: Duration) {
+        // Unknown gRPC methods may be named anything.
+        let route = if code == "Unimplemented" { UNKNOWN_ROUTE } else { route };
+        self.requests
+            .get_or_create(&RequestLabels {
+                route: route.to_owned(),
+                code: code.to_owned(),
+            })
+            .inc();
+        self.request_duration
+            .get_or_create(&RouteLabels { route: route.to_owned() })
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Reads the current database, session and process metrics and encodes
+    /// every metric in the Prometheus text format.
+    pub async fn encode(
+        &self,
+        engine: &ProtolithDbEngine,
+        auth: &Auth<ProtolithDbEngine>,
+        trace: &trace::Handle,
+    ) -> Result<String, std::fmt::Error> {
+        let databases = engine.database_stats().await;
+        let sessions = match auth.count_sessions().await {
+            Ok(sessions) => Some(sessions),
+            Err(e) => {
+                warn!(error = ?e, "failed to count sessions");
+                None
+            }
+        };
+        *self.snapshot.0.lock().unwrap() = Current {
+            uptime: trace.uptime(),
+            sessions,
+            databases,
+            memory: engine.memory_usage(),
+        };
+        let mut buf = String::new();
+        text::encode(&mut buf, &self.registry)?;
+        Ok(buf)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Current {
+    uptime: Duration,
+    sessions: Option<usize>,
+    databases: Vec<(String, db::Stats)>,
+    memory: memory::Usage,
+}
+
+/// The metrics read on every scrape, encoded as they were last read.
+#[derive(Debug, Clone, Default)]
+struct Snapshot(Arc<Mutex<Current>>);
+
+impl Collector for Snapshot {
+    fn encode(&self, mut encoder: DescriptorEncoder<'_>) -> Result<(), std::fmt::Error> {
+        let current = self.0.lock().unwrap();
+
+        let uptime = encoder.encode_descriptor(
+            "process_uptime",
+            "How long the process has been running",
+            Some(&Unit::Seconds),
+            MetricType::Gauge,
+        )?;
+        ConstGauge::new(current.uptime.as_secs_f64()).encode(uptime)?;
+
+        if let Some(sessions) = current.sessions {
+            let metric = encoder.encode_descriptor("sessions", "Sessions which have not expired", None, MetricType::Gauge)?;
+            ConstGauge::new(sessions as i64).encode(metric)?;
+        }
+
+        type Read = fn(&db::Stats) -> u64;
+        let counters: [(&str, &str, Read); 2] = [
+            ("rocksdb_block_cache_hits", "Reads served by the block cache", |s| s.block_cache_hits),
+            ("rocksdb_block_cache_misses", "Reads missing the block cache", |s| s.block_cache_misses),
+        ];
+        for (name, help, read) in counters {
+            let mut metric = encoder.encode_descriptor(name, help, None, MetricType::Counter)?;
+            for (database, stats) in &current.databases {
+                ConstCounter::new(read(stats)).encode(metric.encode_family(&[("database", database.as_str())])?)?;
+            }
+        }
+        let gauges: [(&str, &str, Option<Unit>, Read); 5] = [
+            ("rocksdb_memtable", "Size of the memtables", Some(Unit::Bytes), |s| s.memtable_bytes),
+            (
+                "rocksdb_table_readers",
+                "Memory of the SST indexes and filters outside of the block cache",
+                Some(Unit::Bytes),
+                |s| s.table_readers_bytes,
+            ),
+            (
+                "rocksdb_pending_compaction",
+                "Estimated bytes compaction has to rewrite",
+                Some(Unit::Bytes),
+                |s| s.pending_compaction_bytes,
+            ),
+            ("rocksdb_sst_files", "Live SST files", None, |s| s.sst_files),
+            ("rocksdb_sst", "Size of the live SST files", Some(Unit::Bytes), |s| s.sst_bytes),
+        ];
+        for (name, help, unit, read) in gauges {
+            let mut metric = encoder.encode_descriptor(name, help, unit.as_ref(), MetricType::Gauge)?;
+            for (database, stats) in &current.databases {
+                ConstGauge::new(read(stats) as i64).encode(metric.encode_family(&[("database", database.as_str())])?)?;
+            }
+        }
+
+        let mut hit_ratio = encoder.encode_descriptor(
+            "rocksdb_block_cache_hit_ratio",
+            "Share of the reads served by the block cache since the database was opened",
+            None,
+            MetricType::Gauge,
+        )?;
+        for (database, stats) in &current.databases {
+            let reads = stats.block_cache_hits + stats.block_cache_misses;
+            if reads > 0 {
+                let ratio = stats.block_cache_hits as f64 / reads as f64;
+                ConstGauge::new(ratio).encode(hit_ratio.encode_family(&[("database", database.as_str())])?)?;
+            }
+        }
+
+        // The memory shared by every database.
+        let memory = &current.memory;
+        let shared: [(&str, &str, u64); 5] = [
+            ("rocksdb_block_cache_capacity", "Capacity of the block cache", memory.block_cache_capacity),
+            ("rocksdb_block_cache_usage", "Size of the blocks in the block cache", memory.block_cache_bytes),
+            (
+                "rocksdb_block_cache_pinned",
+                "Size of the blocks held by readers in the block cache",
+                memory.block_cache_pinned_bytes,
+            ),
+            (
+                "rocksdb_write_buffer_budget",
+                "Size the memtables of the databases may grow to together, zero when unlimited",
+                memory.write_buffer_budget,
+            ),
+            ("rocksdb_write_buffer_usage", "Size of the memtables of the databases", memory.write_buffer_bytes),
+        ];
+        for (name, help, value) in shared {
+            let metric = encoder.encode_descriptor(name, help, Some(&Unit::Bytes), MetricType::Gauge)?;
+            ConstGauge::new(value as i64).encode(metric)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+//! TLS termination of the gRPC server and the HTTP gateway.
+use std::{fs, io, path::{Path, PathBuf}, sync::Arc};
+
+use protolith_core::error::Error;
+use tokio_rustls::{
+    rustls::{
+        server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient},
+        Certificate, PrivateKey, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tonic::transport::{self, Identity, ServerTlsConfig};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The PEM encoded certificate chain of the server.
+    pub cert_path: PathBuf,
+
+    /// The PEM encoded private key of the server.
+    pub key_path: PathBuf,
+
+    /// The PEM encoded CA bundle client certificates are verified against,
+    /// clients are not asked for a certificate when unset.
+    pub client_ca_path: Option<PathBuf>,
+
+    /// Whether clients without a verified certificate are rejected.
+    pub client_auth_required: bool,
+}
+
+impl Config {
+    /// The TLS configuration of the gRPC server.
+    pub fn server_tls_config(&self) -> Result<ServerTlsConfig, Error> {
+        let identity = Identity::from_pem(fs::read(&self.cert_path)?, fs::read(&self.key_path)?);
+        let mut config = ServerTlsConfig::new().identity(identity);
+        if let Some(path) = &self.client_ca_path {
+            config = config
+                .client_ca_root(transport::Certificate::from_pem(fs::read(path)?))
+                .client_auth_optional(!self.client_auth_required);
+        }
+        Ok(config)
+    }
+
+    /// The TLS acceptor of the HTTP gateway.
+    pub fn acceptor(&self) -> Result<TlsAcceptor, Error> {
+        let certs = rustls_pemfile::certs(&mut io::BufReader::new(fs::File::open(&self.cert_path)?))?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+        let key = read_private_key(&self.key_path)?;
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &self.client_ca_path {
+            None => builder.with_no_client_auth(),
+            Some(path) if self.client_auth_required => builder
+                .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(read_trust_anchors(path)?).boxed()),
+            Some(path) => builder.with_client_cert_verifier(
+                AllowAnyAnonymousOrAuthenticatedClient::new(read_trust_anchors(path)?).boxed(),
+            ),
+        };
+        let mut config = builder.with_single_cert(certs, key)?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// Reads the PEM encoded CA certificates of `path`, failing when it holds
+/// none.
+pub fn read_trust_anchors(path: &Path) -> Result<RootCertStore, Error> {
+    let certs = rustls_pemfile::certs(&mut io::BufReader::new(fs::File::open(path)?))?;
+    let mut roots = RootCertStore::empty();
+    let (added, ignored) = roots.add_parsable_certificates(&certs);
+    if added == 0 || ignored > 0 {
+        return Err(format!("{} holds {} valid and {} invalid certificates", path.display(), added, ignored).into());
+    }
+    Ok(roots)
+}
+
+fn read_private_key(path: &Path) -> Result<PrivateKey, Error> {
+    let mut reader = io::BufReader::new(fs::File::open(path)?);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => continue,
+        }
+    }
+    Err(format!("no private key found in {}", path.display()).into())
+}
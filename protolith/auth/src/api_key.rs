@@ -0,0 +1,43 @@
+//! `x-protolith-api-key` authentication.
+//!
+//! API keys are created with `AdminService.CreateApiKey` and act on behalf of
+//! their user, restricted to the databases they were created for.
+use std::sync::Arc;
+
+pub use protolith_api::service::HEADER_PROTOLITH_API_KEY;
+use protolith_engine::Engine;
+use tonic::codegen::http::HeaderMap;
+use tracing::debug;
+
+use crate::{internal, AuthError, Authenticator, Session};
+
+#[derive(Debug, Clone)]
+pub struct ApiKeys<E: Engine> {
+    engine: Arc<E>,
+}
+
+impl<E: Engine> ApiKeys<E> {
+    pub fn new(engine: Arc<E>) -> Self {
+        Self { engine }
+    }
+}
+
+#[tonic::async_trait]
+impl<E: Engine> Authenticator for ApiKeys<E> {
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Session>, AuthError> {
+        let Some(key) = headers.get(HEADER_PROTOLITH_API_KEY) else {
+            return Ok(None);
+        };
+        let key = key.to_str().map_err(|_| AuthError::InvalidApiKey)?;
+        let api_key = self
+            .engine
+            .verify_api_key(key.trim().to_owned())
+            .await
+            .map_err(internal)?
+            .ok_or(AuthError::InvalidApiKey)?;
+        debug!(username = ?api_key.username, id = ?api_key.id, "authenticated api key");
+        let mut session = Session::new(api_key.username);
+        session.databases = api_key.databases;
+        Ok(Some(session))
+    }
+}
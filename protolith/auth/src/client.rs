@@ -1,5 +1,6 @@
 pub use protolith_api::protolith::services::v1::auth_service_client::AuthServiceClient;
 use protolith_api::{
+    pbjson_types::Empty,
     protolith::services::v1::{LoginRequest, LoginResponse},
     service::MetadataSvc,
 };
@@ -24,12 +25,22 @@ impl Client {
 
     pub async fn login(&mut self, username: &str, password: &str) -> Result<LoginResponse, Error> {
         let request = LoginRequest {
-            password: username.to_owned(),
-            username: password.to_owned(),
+            username: username.to_owned(),
+            password: password.to_owned(),
+            ..Default::default()
         }
         .into_request();
         // request.metadata_mut().insert("protolith-session", self.session.parse().unwrap()).unwrap();
         let response = self.auth_client.login(request).await?;
         Ok(response.into_inner())
     }
+
+    pub async fn logout(&mut self, session: &str) -> Result<(), Error> {
+        let mut request = Empty::default().into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", session.parse()?);
+        self.auth_client.logout(request).await?;
+        Ok(())
+    }
 }
@@ -1,21 +1,61 @@
+pub mod api_key;
 mod client;
+pub mod jwt;
+pub mod lockout;
 mod service;
+pub mod tls;
 pub use client::Client;
-use protolith_api::protolith::services::v1::auth_service_server;
+use protolith_api::{
+    pbjson_types::Timestamp,
+    protolith::{
+        metastore::v1::Session as StoredSession,
+        services::v1::{auth_service_server, LoginRequest, LoginResponse},
+        types::v1::{Grant, Role},
+    },
+};
 use protolith_core::{error::Error, meta_store};
-use protolith_engine::Engine;
+use protolith_engine::{Engine, EngineError, OpError};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use thiserror::Error as thisError;
-use tracing::info;
+use tonic::{codegen::http::HeaderMap, transport::server::TcpConnectInfo};
+use tracing::{debug, error, info};
 
 #[derive(Debug, Clone, PartialEq, Eq, thisError)]
 pub enum AuthError {
-    #[error("user {0} not exists")]
-    UserNotExists(String),
+    /// Unknown users and wrong passwords fail alike, so that logins do not
+    /// tell which usernames exist.
+    #[error("invalid username or password")]
+    InvalidCredentials,
+    #[error("user {0} is disabled")]
+    UserDisabled(String),
+    #[error("too many failed logins, retry in {}s", retry_secs(.0))]
+    LockedOut(Duration),
+    #[error("session {0:?} is not exists or expired")]
+    SessionNotFound(String),
+    #[error("invalid or expired api key")]
+    InvalidApiKey,
+    #[error("invalid client certificate: {0}")]
+    InvalidCertificate(String),
+    #[error("invalid token: {0}")]
+    InvalidToken(String),
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+/// The header carrying the opaque session tokens returned by `Login`.
+pub const HEADER_PROTOLITH_SESSION: &str = "protolith-session";
+
+/// Authenticates requests from their headers.
+#[tonic::async_trait]
+pub trait Authenticator: Send + Sync + 'static {
+    /// Returns `Ok(None)` when the request carries no credentials of this
+    /// authenticator, so the next one is tried.
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Session>, AuthError>;
 }
 
 #[derive(Debug, Clone)]
@@ -23,20 +63,74 @@ pub struct Config {
     pub meta_store: meta_store::Config,
     pub user: String,
     pub password: String,
+
+    /// How long a session stays valid without being used, zero disables it.
+    pub session_idle_ttl: Duration,
+
+    /// How long a session stays valid after login, zero disables it.
+    pub session_ttl: Duration,
+
+    /// How often expired sessions and API keys are deleted.
+    pub session_sweep_interval: Duration,
+
+    pub jwt: jwt::Config,
+
+    /// Failed logins before a lockout, zero `max_failures` disables it.
+    pub lockout: lockout::Config,
+
+    /// Authenticates the requests of verified client certificates carrying
+    /// no other credentials, set when the server verifies them.
+    pub client_certificates: Option<tls::ClientCertificates>,
 }
 
 impl Config {
     pub async fn build<E: Engine>(self, engine: Arc<E>) -> Result<Auth<E>, Error> {
-        engine.create_user(self.user, self.password).await?;
+        // The configured user bootstraps the instance, so it administers everything.
+        let admin = Grant {
+            role: Role::Admin.into(),
+            ..Default::default()
+        };
+        match engine.create_user(self.user.clone(), self.password, vec![admin.clone()]).await {
+            Ok(_) => {}
+            // Keep the existing user and its password, only make sure it
+            // still administers the instance.
+            Err(EngineError::OpError(OpError::UserAlreadyExists(_))) => {
+                info!(username = ?self.user, "bootstrap user already exists");
+                engine.grant_permission(self.user, admin).await?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let ttl = SessionTtl {
+            idle: Some(self.session_idle_ttl).filter(|ttl| !ttl.is_zero()),
+            absolute: Some(self.session_ttl).filter(|ttl| !ttl.is_zero()),
+        };
+        let mut authenticators: Vec<Arc<dyn Authenticator>> =
+            vec![Arc::new(api_key::ApiKeys::new(engine.clone()))];
+        let mut auth = ProtolithAuth::new(engine, self.meta_store.clone(), ttl);
+        if self.jwt.is_enabled() {
+            let jwt = Arc::new(self.jwt.build()?);
+            authenticators.push(jwt.clone());
+            auth.jwt = Some(jwt);
+        }
+        if self.lockout.max_failures > 0 {
+            auth.lockout = Some(Arc::new(lockout::Lockout::new(self.lockout)));
+        }
         Ok(Auth {
-            auth: ProtolithAuth::new(engine, self.meta_store.clone()),
+            auth,
+            authenticators,
+            client_certificates: self.client_certificates,
+            sweep_interval: self.session_sweep_interval,
         })
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Auth<E: Engine> {
     auth: ProtolithAuth<E>,
+    /// Tried in order before falling back to the `protolith-session` header.
+    authenticators: Vec<Arc<dyn Authenticator>>,
+    client_certificates: Option<tls::ClientCertificates>,
+    sweep_interval: Duration,
 }
 
 type AuthServiceType<E> = auth_service_server::AuthServiceServer<ProtolithAuth<E>>;
@@ -45,18 +139,84 @@ impl<E: Engine> Auth<E>
 where
     E: Clone,
 {
-    pub fn get_session(&self, session: String) -> Option<Session> {
-        self.auth.get_session(session)
+    /// Authenticates a request with the first authenticator recognizing its
+    /// credentials, or its `protolith-session` header.
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Result<Session, AuthError> {
+        self.authenticate_peer(headers, None).await
+    }
+
+    /// Like `authenticate`, falling back to the DER encoded client
+    /// `certificate` verified by the TLS handshake when the request carries
+    /// no other credentials.
+    pub async fn authenticate_peer(
+        &self,
+        headers: &HeaderMap,
+        certificate: Option<&[u8]>,
+    ) -> Result<Session, AuthError> {
+        for authenticator in &self.authenticators {
+            if let Some(session) = authenticator.authenticate(headers).await? {
+                return Ok(session);
+            }
+        }
+        let session = headers
+            .get(HEADER_PROTOLITH_SESSION)
+            .and_then(|session| session.to_str().ok())
+            .filter(|session| !session.is_empty());
+        if let (None, Some(certificate), Some(client_certificates)) =
+            (session, certificate, &self.client_certificates)
+        {
+            return client_certificates.session(certificate);
+        }
+        let session = session.unwrap_or("unknown");
+        self.auth
+            .get_session(session.to_owned())
+            .await
+            .ok_or_else(|| AuthError::SessionNotFound(session.to_owned()))
+    }
+
+    /// Returns the session `session` refers to, unless it expired.
+    pub async fn get_session(&self, session: String) -> Option<Session> {
+        self.auth.get_session(session).await
     }
 
-    pub async fn sessions(&self) -> HashMap<String, Session> {
-        let sessions = self.auth.sessions();
-        sessions
+    pub async fn login_user(&self, username: String, password: String) -> Result<String, AuthError> {
+        self.auth.clone().login_user(username, password).await
     }
 
-    pub async fn set_sessions(&mut self, sessions: HashMap<String, Session>) {
-        info!(sessions = ?sessions, "loading sessions");
-        self.auth.sessions = Arc::new(Mutex::new(sessions));
+    /// Handles a `Login` request from `peer` like `AuthService` does.
+    pub async fn login(&self, request: LoginRequest, peer: Option<SocketAddr>) -> Result<LoginResponse, tonic::Status> {
+        use auth_service_server::AuthService;
+        let mut request = tonic::Request::new(request);
+        request.extensions_mut().insert(TcpConnectInfo {
+            local_addr: None,
+            remote_addr: peer,
+        });
+        let response = self.auth.login(request).await?;
+        Ok(response.into_inner())
+    }
+
+    /// Ends the session `session`, returns whether it existed.
+    pub async fn logout(&self, session: String) -> Result<bool, AuthError> {
+        self.auth.delete_session(session).await
+    }
+
+    pub fn sweep_interval(&self) -> Duration {
+        self.sweep_interval
+    }
+
+    /// Deletes the expired sessions, returns how many were deleted.
+    pub async fn sweep_sessions(&self) -> Result<usize, AuthError> {
+        self.auth.sweep_sessions().await
+    }
+
+    /// Deletes the expired API keys, returns how many were deleted.
+    pub async fn sweep_api_keys(&self) -> Result<usize, AuthError> {
+        self.auth.sweep_api_keys().await
+    }
+
+    /// Returns how many sessions have not expired.
+    pub async fn count_sessions(&self) -> Result<usize, AuthError> {
+        self.auth.count_sessions().await
     }
 
     pub fn service(&self, max_message_size: usize) -> AuthServiceType<E> {
@@ -66,35 +226,135 @@ where
     }
 }
 
+/// The longest a session is used without its access being recorded again.
+const MAX_ACCESS_RESOLUTION: Duration = Duration::from_secs(60);
+
+/// The lifetime of the sessions, `None` never expires.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionTtl {
+    pub idle: Option<Duration>,
+    pub absolute: Option<Duration>,
+}
+
+impl SessionTtl {
+    /// Whether a session created at `created_at` and last used at
+    /// `last_accessed_at` is expired at `now`.
+    pub fn is_expired(&self, created_at: SystemTime, last_accessed_at: SystemTime, now: SystemTime) -> bool {
+        let elapsed = |since: SystemTime| now.duration_since(since).unwrap_or_default();
+        self.idle.is_some_and(|ttl| elapsed(last_accessed_at) >= ttl)
+            || self.absolute.is_some_and(|ttl| elapsed(created_at) >= ttl)
+    }
+
+    /// How long after its last recorded access a session is used again
+    /// before the new access is recorded, a small part of the idle TTL.
+    pub fn access_resolution(&self) -> Duration {
+        self.idle
+            .map_or(MAX_ACCESS_RESOLUTION, |idle| idle / 10)
+            .min(MAX_ACCESS_RESOLUTION)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ProtolithAuth<E: Engine> {
     engine: Arc<E>,
     metastore: meta_store::Config,
-    sessions: Arc<Mutex<HashMap<String, Session>>>,
+    ttl: SessionTtl,
+    /// Mints the JWTs `Login` returns on request.
+    jwt: Option<Arc<jwt::Jwt>>,
+    lockout: Option<Arc<lockout::Lockout>>,
 }
 
 impl<E: Engine> ProtolithAuth<E> {
-    pub fn new(engine: Arc<E>, metastore: meta_store::Config) -> Self {
+    pub fn new(engine: Arc<E>, metastore: meta_store::Config, ttl: SessionTtl) -> Self {
         Self {
             engine,
             metastore,
-            sessions: Arc::new(Mutex::new(HashMap::new())),
+            ttl,
+            jwt: None,
+            lockout: None,
+        }
+    }
+
+    pub async fn create_session(&self, session_id: String, username: String) -> Result<(), AuthError> {
+        let now = Some(to_timestamp(SystemTime::now()));
+        let session = StoredSession {
+            id: session_id,
+            username,
+            created_at: now.clone(),
+            last_accessed_at: now,
+        };
+        self.engine.put_session(session).await.map_err(internal)
+    }
+
+    /// Returns the session `session` refers to, deleting it when it expired
+    /// and recording the access otherwise.
+    pub async fn get_session(&self, session: String) -> Option<Session> {
+        let stored = match self.engine.get_session(session.clone()).await {
+            Ok(stored) => stored?,
+            Err(e) => {
+                error!(error = ?e, "failed to load session");
+                return None;
+            }
+        };
+        let now = SystemTime::now();
+        if self.is_expired(&stored, now) {
+            debug!(username = ?stored.username, "session expired");
+            let _ = self.engine.delete_session(session).await;
+            return None;
+        }
+        let last_accessed_at = stored.last_accessed_at.as_ref().map(from_timestamp).unwrap_or(UNIX_EPOCH);
+        if now.duration_since(last_accessed_at).unwrap_or_default() < self.ttl.access_resolution() {
+            return Some(Session::new(stored.username));
+        }
+        // Only updates the session if it was not logged out or revoked since
+        // it was read.
+        match self.engine.touch_session(session, to_timestamp(now)).await {
+            Ok(touched) => touched.map(|stored| Session::new(stored.username)),
+            Err(e) => {
+                error!(error = ?e, "failed to record session access");
+                Some(Session::new(stored.username))
+            }
         }
     }
 
-    pub fn create_session(&mut self, session_id: String, username: String) {
-        let mut sessions = self.sessions.lock().unwrap();
-        sessions.insert(session_id.clone(), Session { username: username });
+    /// Deletes the session `session`, returns whether it existed.
+    pub async fn delete_session(&self, session: String) -> Result<bool, AuthError> {
+        self.engine.delete_session(session).await.map_err(internal)
     }
 
-    pub fn get_session(&self, session: String) -> Option<Session> {
-        let sessions = self.sessions.lock().unwrap();
-        sessions.clone().get(&session).cloned()
+    pub async fn sweep_sessions(&self) -> Result<usize, AuthError> {
+        let now = SystemTime::now();
+        let mut swept = 0;
+        for session in self.engine.list_sessions().await.map_err(internal)? {
+            if self.is_expired(&session, now) {
+                self.engine.delete_session(session.id).await.map_err(internal)?;
+                swept += 1;
+            }
+        }
+        Ok(swept)
     }
 
-    pub fn sessions(&self) -> HashMap<String, Session> {
-        let sessions = self.sessions.lock().unwrap();
-        sessions.clone()
+    pub async fn sweep_api_keys(&self) -> Result<usize, AuthError> {
+        let now = SystemTime::now();
+        let mut swept = 0;
+        for api_key in self.engine.list_api_keys(None).await.map_err(internal)? {
+            if api_key.expires_at.as_ref().is_none_or(|expires_at| from_timestamp(expires_at) > now) {
+                continue;
+            }
+            match self.engine.revoke_api_key(api_key.id).await {
+                Ok(_) => swept += 1,
+                // Revoked meanwhile.
+                Err(EngineError::OpError(OpError::ApiKeyNotFound(_))) => {}
+                Err(e) => return Err(internal(e)),
+            }
+        }
+        Ok(swept)
+    }
+
+    pub async fn count_sessions(&self) -> Result<usize, AuthError> {
+        let now = SystemTime::now();
+        let sessions = self.engine.list_sessions().await.map_err(internal)?;
+        Ok(sessions.iter().filter(|session| !self.is_expired(session, now)).count())
     }
 
     pub async fn login_user(
@@ -102,22 +362,143 @@ impl<E: Engine> ProtolithAuth<E> {
         username: String,
         password: String,
     ) -> Result<String, AuthError> {
-        let session = self
-            .engine
-            .login_user(username.clone(), password)
-            .await
-            .map_err(|_e| AuthError::UserNotExists(username.clone()))?;
-        self.create_session(session.clone(), username);
+        let session = self.verify_login(&username, password, None).await?;
+        self.create_session(session.clone(), username).await?;
         Ok(session)
     }
 
+    /// Verifies the password of `username` logging in from `peer`, returning
+    /// a new session token, unless they are locked out by failed logins.
+    pub async fn verify_login(
+        &self,
+        username: &str,
+        password: String,
+        peer: Option<IpAddr>,
+    ) -> Result<String, AuthError> {
+        let attempt = match &self.lockout {
+            Some(lockout) => match lockout.attempt(username, peer, Instant::now()) {
+                Ok(attempt) => Some(attempt),
+                Err(remaining) => {
+                    debug!(username = ?username, peer = ?peer, "login locked out");
+                    return Err(AuthError::LockedOut(remaining));
+                }
+            },
+            None => None,
+        };
+        match self.engine.login_user(username.to_owned(), password).await {
+            Ok(session) => {
+                if let Some(attempt) = attempt {
+                    attempt.succeeded();
+                }
+                Ok(session)
+            }
+            Err(EngineError::OpError(OpError::UserNotFound(_) | OpError::InvalidPassword(_))) => {
+                info!(username = ?username, peer = ?peer, "failed login");
+                if let Some(attempt) = attempt {
+                    attempt.failed(Instant::now());
+                }
+                Err(AuthError::InvalidCredentials)
+            }
+            Err(EngineError::OpError(OpError::UserDisabled(user))) => Err(AuthError::UserDisabled(user)),
+            Err(e) => Err(internal(e)),
+        }
+    }
+
     pub fn create_user(&self, username: String, password: String) -> Result<Session, AuthError> {
-        self.engine.create_user(username.clone(), password);
-        Ok(Session { username })
+        self.engine.create_user(username.clone(), password, Vec::new());
+        Ok(Session::new(username))
+    }
+
+    fn is_expired(&self, session: &StoredSession, now: SystemTime) -> bool {
+        let created_at = session.created_at.as_ref().map(from_timestamp).unwrap_or(UNIX_EPOCH);
+        let last_accessed_at = session
+            .last_accessed_at
+            .as_ref()
+            .map(from_timestamp)
+            .unwrap_or(created_at);
+        self.ttl.is_expired(created_at, last_accessed_at, now)
     }
 }
 
+/// Whole seconds, rounded up so that retrying after them is not refused.
+fn retry_secs(duration: &Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+fn internal(err: EngineError) -> AuthError {
+    AuthError::Internal(err.to_string())
+}
+
+fn to_timestamp(time: SystemTime) -> Timestamp {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Timestamp {
+        seconds: since_epoch.as_secs() as i64,
+        nanos: since_epoch.subsec_nanos() as i32,
+    }
+}
+
+fn from_timestamp(timestamp: &Timestamp) -> SystemTime {
+    UNIX_EPOCH + Duration::new(timestamp.seconds.max(0) as u64, timestamp.nanos.max(0) as u32)
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Session {
     username: String,
+    /// Grants carried by the credential, see `jwt`.
+    #[serde(default)]
+    grants: Vec<Grant>,
+    /// The databases the credential is restricted to, see `api_key`.
+    #[serde(default)]
+    databases: Vec<String>,
+}
+
+impl Session {
+    pub fn new(username: String) -> Self {
+        Self {
+            username,
+            grants: Vec::new(),
+            databases: Vec::new(),
+        }
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn grants(&self) -> &[Grant] {
+        &self.grants
+    }
+
+    /// Every database the user can access when empty.
+    pub fn databases(&self) -> &[String] {
+        &self.databases
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_ttl() {
+        let login = UNIX_EPOCH + Duration::from_secs(1_000);
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+        let ttl = SessionTtl {
+            idle: Some(minutes(30)),
+            absolute: Some(minutes(12 * 60)),
+        };
+        assert!(!ttl.is_expired(login, login, login + minutes(29)));
+        assert!(ttl.is_expired(login, login, login + minutes(30)));
+        // Used recently, but logged in for too long.
+        let accessed = login + minutes(12 * 60 - 1);
+        assert!(!ttl.is_expired(login, accessed, accessed + minutes(1) - Duration::from_secs(1)));
+        assert!(ttl.is_expired(login, accessed, accessed + minutes(1)));
+        assert!(!SessionTtl::default().is_expired(login, login, login + minutes(100_000)));
+        assert_eq!(ttl.access_resolution(), minutes(1));
+        let short = SessionTtl {
+            idle: Some(Duration::from_secs(10)),
+            absolute: None,
+        };
+        assert_eq!(short.access_resolution(), Duration::from_secs(1));
+    }
 }
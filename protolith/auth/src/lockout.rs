@@ -0,0 +1,260 @@
+//! Locks out usernames and peers after repeated failed logins.
+//!
+//! Every failure past `max_failures` doubles how long further attempts are
+//! refused, up to `max_lockout`. Failures are forgotten once `max_lockout`
+//! passed since the last one and no lockout is left.
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// How many failed logins of a username are allowed before locking it out.
+    pub max_failures: u32,
+    /// How many failed logins from a peer address are allowed before locking
+    /// it out, larger as a peer may log in many users.
+    pub max_peer_failures: u32,
+    /// How long the first lockout lasts.
+    pub lockout: Duration,
+    pub max_lockout: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Subject {
+    Username(String),
+    Peer(IpAddr),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    /// The attempts whose password is being verified.
+    pending: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct Lockout {
+    config: Config,
+    failures: Mutex<HashMap<Subject, Failures>>,
+}
+
+/// A login attempt whose password is being verified. Dropping it without
+/// an outcome, when the password could not be verified, does not count it.
+#[derive(Debug)]
+#[must_use]
+pub struct Attempt<'a> {
+    lockout: &'a Lockout,
+    username: String,
+    peer: Option<IpAddr>,
+    settled: bool,
+}
+
+impl Lockout {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Starts a login attempt of `username` from `peer`, or returns how long
+    /// their logins are still refused. Concurrent attempts are refused once
+    /// they would exceed the failures left before a lockout should they all
+    /// fail, so that they can not all verify a password before the first
+    /// failure is recorded.
+    pub fn attempt(&self, username: &str, peer: Option<IpAddr>, now: Instant) -> Result<Attempt<'_>, Duration> {
+        let mut failures = self.failures.lock().unwrap();
+        self.evict(&mut failures, now);
+        let mut refused = None;
+        for subject in subjects(username, peer) {
+            let Some(f) = failures.get(&subject) else {
+                continue;
+            };
+            let remaining = match f.locked_until.and_then(|until| until.checked_duration_since(now)) {
+                Some(remaining) if !remaining.is_zero() => remaining,
+                _ if f.pending > 0 && f.count + f.pending >= self.max_failures(&subject) => self.config.lockout,
+                _ => continue,
+            };
+            refused = refused.max(Some(remaining));
+        }
+        if let Some(remaining) = refused {
+            return Err(remaining);
+        }
+        for subject in subjects(username, peer) {
+            failures
+                .entry(subject)
+                .or_insert(Failures {
+                    count: 0,
+                    pending: 0,
+                    last: now,
+                    locked_until: None,
+                })
+                .pending += 1;
+        }
+        Ok(Attempt {
+            lockout: self,
+            username: username.to_owned(),
+            peer,
+            settled: false,
+        })
+    }
+
+    /// Forgets the failures of the subjects without attempts in progress which
+    /// are not locked out anymore and failed last over `max_lockout` ago, the
+    /// longest a failure counts.
+    fn evict(&self, failures: &mut HashMap<Subject, Failures>, now: Instant) {
+        failures.retain(|_, f| {
+            f.pending > 0
+                || f.locked_until.is_some_and(|until| until > now)
+                || now.saturating_duration_since(f.last) < self.config.max_lockout
+        });
+    }
+
+    fn max_failures(&self, subject: &Subject) -> u32 {
+        match subject {
+            Subject::Username(_) => self.config.max_failures,
+            Subject::Peer(_) => self.config.max_peer_failures,
+        }
+    }
+
+    /// Ends an attempt of `subject`, recording a failure when `failed` and
+    /// forgetting it once nothing is left to count.
+    fn settle(&self, failures: &mut HashMap<Subject, Failures>, subject: Subject, failed: Option<Instant>) {
+        let max_failures = self.max_failures(&subject);
+        let Some(f) = failures.get_mut(&subject) else {
+            return;
+        };
+        f.pending = f.pending.saturating_sub(1);
+        if let Some(now) = failed {
+            f.count += 1;
+            f.last = now;
+            if f.count >= max_failures {
+                let doublings = (f.count - max_failures).min(31);
+                let lockout = self
+                    .config
+                    .lockout
+                    .saturating_mul(1 << doublings)
+                    .min(self.config.max_lockout);
+                f.locked_until = Some(now + lockout);
+            }
+        }
+        if f.count == 0 && f.pending == 0 {
+            failures.remove(&subject);
+        }
+    }
+}
+
+impl Attempt<'_> {
+    /// Records that the password was valid, which forgets the failures of the
+    /// username. Those of its peer are kept so that one known password does
+    /// not reset them.
+    pub fn succeeded(mut self) {
+        self.settled = true;
+        let mut failures = self.lockout.failures.lock().unwrap();
+        if let Some(f) = failures.get_mut(&Subject::Username(self.username.clone())) {
+            f.count = 0;
+            f.locked_until = None;
+        }
+        for subject in subjects(&self.username, self.peer) {
+            self.lockout.settle(&mut failures, subject, None);
+        }
+    }
+
+    /// Records that the password was invalid.
+    pub fn failed(mut self, now: Instant) {
+        self.settled = true;
+        let mut failures = self.lockout.failures.lock().unwrap();
+        for subject in subjects(&self.username, self.peer) {
+            self.lockout.settle(&mut failures, subject, Some(now));
+        }
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        if self.settled {
+            return;
+        }
+        let mut failures = self.lockout.failures.lock().unwrap();
+        for subject in subjects(&self.username, self.peer) {
+            self.lockout.settle(&mut failures, subject, None);
+        }
+    }
+}
+
+fn subjects(username: &str, peer: Option<IpAddr>) -> impl Iterator<Item = Subject> {
+    std::iter::once(Subject::Username(username.to_owned())).chain(peer.map(Subject::Peer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_lockout() {
+        let lockout = Lockout::new(Config {
+            max_failures: 3,
+            max_peer_failures: 5,
+            lockout: Duration::from_secs(1),
+            max_lockout: Duration::from_secs(60),
+        });
+        let peer = Some("10.0.0.1".parse().unwrap());
+        let now = Instant::now();
+        let fail = |username, peer| lockout.attempt(username, peer, now).unwrap().failed(now);
+        let locked = |username, peer| lockout.attempt(username, peer, now).err();
+        for _ in 0..2 {
+            fail("ann", peer);
+        }
+        assert_eq!(locked("ann", peer), None);
+        fail("ann", peer);
+        assert_eq!(locked("ann", None), Some(Duration::from_secs(1)));
+        let later = now + Duration::from_secs(1);
+        lockout.attempt("ann", peer, later).unwrap().failed(later);
+        assert_eq!(lockout.attempt("ann", None, later).err(), Some(Duration::from_secs(2)));
+        assert!(lockout.attempt("bob", None, later).is_ok());
+
+        // The peer is locked out for every username.
+        lockout.attempt("bob", peer, later).unwrap().failed(later);
+        assert_eq!(lockout.attempt("bob", peer, later).err(), Some(Duration::from_secs(1)));
+        assert!(lockout.attempt("bob", None, later).is_ok());
+
+        let after = later + Duration::from_secs(2);
+        lockout.attempt("ann", None, after).unwrap().succeeded();
+        assert!(lockout.attempt("ann", None, after).is_ok());
+        // The failures of the peer are kept.
+        lockout.attempt("carol", peer, after).unwrap().failed(after);
+        assert_eq!(lockout.attempt("carol", peer, after).err(), Some(Duration::from_secs(2)));
+
+        // Failures are forgotten along with their lockout.
+        let forgotten = after + Duration::from_secs(60);
+        assert!(lockout.attempt("dave", None, forgotten).is_ok());
+        assert!(lockout.failures.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_concurrent_attempts_past_the_failures_left() {
+        let lockout = Lockout::new(Config {
+            max_failures: 3,
+            max_peer_failures: 5,
+            lockout: Duration::from_secs(1),
+            max_lockout: Duration::from_secs(60),
+        });
+        let now = Instant::now();
+        lockout.attempt("ann", None, now).unwrap().failed(now);
+        let first = lockout.attempt("ann", None, now).unwrap();
+        let second = lockout.attempt("ann", None, now).unwrap();
+        // Should both fail, the username is locked out.
+        assert_eq!(lockout.attempt("ann", None, now).err(), Some(Duration::from_secs(1)));
+        // An attempt which could not verify the password is not counted.
+        drop(first);
+        let third = lockout.attempt("ann", None, now).unwrap();
+        second.succeeded();
+        third.failed(now);
+        assert_eq!(lockout.failures.lock().unwrap()[&Subject::Username("ann".to_owned())].count, 1);
+    }
+}
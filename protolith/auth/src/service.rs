@@ -1,8 +1,9 @@
-use crate::ProtolithAuth;
-use protolith_core::api::protolith::services::v1::{
-    auth_service_server::AuthService, LoginRequest, LoginResponse,
+use crate::{to_timestamp, AuthError, ProtolithAuth, HEADER_PROTOLITH_SESSION};
+use protolith_core::api::{
+    pbjson_types::Empty,
+    protolith::services::v1::{auth_service_server::AuthService, LoginRequest, LoginResponse},
 };
-use protolith_engine::Engine;
+use protolith_engine::{audit, Engine};
 use tonic::{Request, Response, Status};
 
 #[tonic::async_trait]
@@ -11,20 +12,51 @@ impl<E: Engine> AuthService for ProtolithAuth<E> {
         &self,
         request: Request<LoginRequest>,
     ) -> Result<Response<LoginResponse>, Status> {
+        let peer = request.remote_addr().map(|addr| addr.ip());
         let req = request.into_inner();
+        audit::record_user(&req.username);
         let session = self
-            .engine
-            .login_user(req.username.clone(), req.password)
+            .verify_login(&req.username, req.password, peer)
             .await
-            .map_err(|e| Status::not_found(e.to_string()))?;
-        let mut sessions = self.sessions.lock().unwrap();
-        sessions.insert(
-            session.clone(),
-            crate::Session {
-                username: req.username,
-            },
-        );
-        println!("{:?}", sessions);
-        Ok(Response::new(LoginResponse { session }))
+            .map_err(|e| match e {
+                AuthError::InvalidCredentials => Status::unauthenticated(e.to_string()),
+                AuthError::UserDisabled(_) => Status::permission_denied(e.to_string()),
+                AuthError::LockedOut(_) => Status::resource_exhausted(e.to_string()),
+                e => Status::internal(e.to_string()),
+            })?;
+        if req.issue_jwt {
+            let jwt = self
+                .jwt
+                .as_ref()
+                .ok_or_else(|| Status::failed_precondition("JWT issuing is not configured"))?;
+            let (access_token, expires_at) = jwt
+                .mint(&req.username)
+                .map_err(|e| Status::failed_precondition(e.to_string()))?;
+            return Ok(Response::new(LoginResponse {
+                access_token,
+                expires_at: Some(to_timestamp(expires_at)),
+                ..Default::default()
+            }));
+        }
+        self.create_session(session.clone(), req.username)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(LoginResponse {
+            session,
+            ..Default::default()
+        }))
+    }
+
+    async fn logout(&self, request: Request<Empty>) -> Result<Response<Empty>, Status> {
+        let session = request
+            .metadata()
+            .get(HEADER_PROTOLITH_SESSION)
+            .and_then(|session| session.to_str().ok())
+            .ok_or_else(|| Status::unauthenticated("missing protolith-session"))?
+            .to_owned();
+        self.delete_session(session)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(Empty {}))
     }
 }
@@ -0,0 +1,51 @@
+//! Client certificate authentication.
+//!
+//! Once the TLS handshake verified a client certificate against the
+//! configured CA bundle, the request is authenticated as the user its subject
+//! common name maps to. Certificates whose common name is not mapped are
+//! refused, unless the common name is opted in as the username itself.
+use std::collections::HashMap;
+
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::{AuthError, Session};
+
+#[derive(Debug, Clone, Default)]
+pub struct ClientCertificates {
+    /// Subject common names to the users they authenticate as.
+    users: HashMap<String, String>,
+    /// Whether unmapped common names authenticate as the user of that name.
+    common_name_as_username: bool,
+}
+
+impl ClientCertificates {
+    pub fn new(users: HashMap<String, String>, common_name_as_username: bool) -> Self {
+        Self {
+            users,
+            common_name_as_username,
+        }
+    }
+
+    /// Returns the session of the DER encoded, already verified, `certificate`.
+    pub fn session(&self, certificate: &[u8]) -> Result<Session, AuthError> {
+        let (_, certificate) = X509Certificate::from_der(certificate)
+            .map_err(|e| AuthError::InvalidCertificate(e.to_string()))?;
+        let common_name = certificate
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .ok_or_else(|| AuthError::InvalidCertificate("subject has no common name".to_owned()))?;
+        let username = match self.users.get(common_name) {
+            Some(username) => username.as_str(),
+            None if self.common_name_as_username => common_name,
+            None => {
+                return Err(AuthError::InvalidCertificate(format!(
+                    "subject {} is not mapped to a user",
+                    common_name
+                )))
+            }
+        };
+        Ok(Session::new(username.to_owned()))
+    }
+}
@@ -9,7 +9,7 @@ use protolith_core::{
     error::{Error, Result}, meta_store,
 };
 use protolith_engine::client::{self as engine, Collection};
-use tonic::transport::{Endpoint, Channel};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
 
 use protolith_admin as admin;
 use protolith_auth as auth;
@@ -77,7 +77,16 @@ pub struct ProtolithDb {
     admin: admin::Client,
     meta_store: meta_store::Client,
     channel: Channel,
-    session: Option<String>,
+    credentials: Credentials,
+}
+
+/// How the requests of a `ProtolithDb` are authenticated.
+#[derive(Debug, Clone)]
+enum Credentials {
+    Session(String),
+    ApiKey(String),
+    /// The client certificate presented during the TLS handshake.
+    Certificate,
 }
 
 #[derive(Debug, Clone)]
@@ -86,6 +95,12 @@ pub struct ConnectionOpt {
     username: String,
     password: String,
     clear_sessions: bool,
+    /// Authenticates with this API key rather than logging in.
+    api_key: Option<String>,
+    tls: Option<ClientTlsConfig>,
+    /// Whether a client identity is set, it authenticates the requests
+    /// rather than logging in.
+    identity: bool,
 }
 
 impl Default for ConnectionOpt {
@@ -95,15 +110,76 @@ impl Default for ConnectionOpt {
             password: "protolith".to_owned(),
             username: "protolith".to_owned(),
             clear_sessions: false,
+            api_key: None,
+            tls: None,
+            identity: false,
         }
     }
 }
 
+impl ConnectionOpt {
+    /// Authenticates with an API key created with `AdminService.CreateApiKey`,
+    /// for daemons that can not login interactively.
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Connects to `addr`, an `https://` address for TLS.
+    pub fn with_addr(mut self, addr: &'static str) -> Self {
+        self.addr = addr;
+        self
+    }
+
+    /// Verifies the server against the PEM encoded CA certificates `pem`
+    /// rather than the system roots.
+    pub fn with_ca_certificate(mut self, pem: impl AsRef<[u8]>) -> Self {
+        let tls = self.tls.take().unwrap_or_default();
+        self.tls = Some(tls.ca_certificate(Certificate::from_pem(pem)));
+        self
+    }
+
+    /// Presents the PEM encoded client certificate `cert` and private `key`,
+    /// for servers verifying client certificates.
+    pub fn with_identity(mut self, cert: impl AsRef<[u8]>, key: impl AsRef<[u8]>) -> Self {
+        let tls = self.tls.take().unwrap_or_default();
+        self.tls = Some(tls.identity(Identity::from_pem(cert, key)));
+        self.identity = true;
+        self
+    }
+
+    /// The name the server certificate is verified for (SNI), the host of
+    /// the address by default.
+    pub fn with_domain_name(mut self, domain_name: impl Into<String>) -> Self {
+        let tls = self.tls.take().unwrap_or_default();
+        self.tls = Some(tls.domain_name(domain_name));
+        self
+    }
+}
+
 impl ProtolithDb {
     pub async fn connect(connection_opt: ConnectionOpt) -> Result<Self, Error> {
-        let channel = Endpoint::from_static(&connection_opt.addr)
-            .connect()
-            .await?;
+        let mut endpoint = Endpoint::from_static(connection_opt.addr);
+        if let Some(tls) = connection_opt.tls.clone() {
+            endpoint = endpoint.tls_config(tls)?;
+        }
+        let channel = endpoint.connect().await?;
+        if let Some(api_key) = connection_opt.api_key {
+            return Ok(Self {
+                admin: admin::Client::with_api_key(channel.clone(), &api_key)?,
+                channel,
+                meta_store: meta_store::Client {  },
+                credentials: Credentials::ApiKey(api_key),
+            });
+        }
+        if connection_opt.identity {
+            return Ok(Self {
+                admin: admin::Client::new(channel.clone(), String::new()),
+                channel,
+                meta_store: meta_store::Client {  },
+                credentials: Credentials::Certificate,
+            });
+        }
         let home = env!("HOME");
         let path = format!("{}/{}", home, "protolith_session.txt");
         let cloned_path = path.clone();
@@ -136,16 +212,22 @@ impl ProtolithDb {
             channel: channel.clone(),
             admin: admin::Client::new(channel.clone(), session.clone()),
             meta_store: meta_store::Client {  },
-            session: Some(session),
+            credentials: Credentials::Session(session),
         })
     }
 
     pub fn db(&self, database: &str) -> Result<engine::Client, Error> {
-        if let Some(session) = &self.session {
-            let client = engine::Client::new(self.channel.clone(), database.to_owned(), session.to_string());
-            Ok(client)
-        } else {
-            Err("Must have a session before interacting with db".into())
+        match &self.credentials {
+            Credentials::Session(session) => {
+                let client = engine::Client::new(self.channel.clone(), database.to_owned(), session.to_string());
+                Ok(client)
+            }
+            Credentials::ApiKey(api_key) => {
+                engine::Client::with_api_key(self.channel.clone(), database.to_owned(), api_key)
+            }
+            Credentials::Certificate => {
+                Ok(engine::Client::new(self.channel.clone(), database.to_owned(), String::new()))
+            }
         }
     }
 
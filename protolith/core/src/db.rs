@@ -1,17 +1,18 @@
-use rocksdb::{Options, ColumnFamilyDescriptor, Cache, BlockBasedOptions, IteratorMode};
+use rocksdb::{BottommostLevelCompaction, BoundColumnFamily, CompactOptions, DBIteratorWithThreadMode, Options, ColumnFamilyDescriptor, Direction, IteratorMode, ReadOptions, WriteBatch, perf};
 
-use std::{path::PathBuf, sync::Arc};
+use std::{collections::{HashMap, HashSet}, path::PathBuf, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, MutexGuard}, time::Duration};
 use protolith_api::{protolith::{
-    core::v1::{Collection, Field},
-    metastore::v1::{SchemaVersion, Schema, Index}, annotation::v1::IndexType
-}, DescriptorPool, prost::bytes::Bytes, pbjson_types::field_descriptor_proto, prost_wkt_types::Any};
+    core::v1::{Collection, Field, ArchiveHeader},
+    metastore::v1::{ApiKey, SchemaVersion, Schema, Index, Session, User}, annotation::v1::{self, IndexType, Tuning},
+    types::v1::{AuditEvent, ExportFormat, Grant},
+}, DescriptorPool, prost::bytes::{Buf, Bytes}, pbjson_types::{field_descriptor_proto, Timestamp}, prost_wkt_types::Any};
 use protolith_error::Error;
 use thiserror::Error as tError;
-use crate::{meta_store::{self, MetaStore}, schema};
-use tracing::{debug, error};
+use crate::{encryption::{self, FieldCipher}, meta_store::{self, MetaStore}, memory::Memory, profile::{self, Profile, Profiler}, schema, tuning};
+use tracing::{debug, error, info, warn};
 pub use rocksdb::DB;
-use protolith_api::prost::Message;
-use prost_reflect::{Kind, DynamicMessage};
+use protolith_api::prost::{Message, encode_length_delimiter, decode_length_delimiter};
+use prost_reflect::{Kind, DynamicMessage, FieldDescriptor, MessageDescriptor, ReflectMessage};
 
 #[derive(Debug, Clone, tError)]
 pub enum CoreError {
@@ -19,6 +20,28 @@ pub enum CoreError {
     SchemaNotExists(String),
     #[error("key {1} already exists on collection {0}.")]
     KeyAlreadyExists(String, String),
+    #[error("key {1} not found on collection {0}.")]
+    KeyNotFound(String, String),
+    #[error("invalid document: {0}")]
+    InvalidDocument(String),
+    #[error("user {0} already exists")]
+    UserAlreadyExists(String),
+    #[error("user {0} not found")]
+    UserNotFound(String),
+    #[error("invalid password for user {0}")]
+    InvalidPassword(String),
+    #[error("user {0} is disabled")]
+    UserDisabled(String),
+    #[error("invalid schema: {0}")]
+    InvalidSchema(String),
+    #[error("invalid encryption key: {0}")]
+    InvalidEncryptionKey(String),
+    #[error("{0}")]
+    QuotaExceeded(String),
+    #[error("background work of database {0} is paused")]
+    BackgroundWorkPaused(String),
+    #[error("{0}")]
+    PermissionDenied(String),
     #[error("internal error: {0}")]
     Internal(String)
 }
@@ -26,9 +49,79 @@ pub enum CoreError {
 #[derive(Debug, Clone)]
 pub struct Config {
     pub db_path: PathBuf,
+    /// The size of the block cache shared by every database.
     pub cache_size: usize,
+    /// The size the memtables of every database may grow to together, or
+    /// unlimited when zero.
+    pub write_buffer_budget: usize,
     pub max_open_files: i32,
     pub descriptor_file_name: String,
+    /// The key fields annotated as encrypted are encrypted with, documents
+    /// with such fields can not be inserted without it.
+    pub encryption_key: Option<encryption::Key>,
+    /// The storage quota of each database.
+    pub quota: Quota,
+    /// Document operations taking at least as long are logged as slow queries.
+    pub slow_query_threshold: Duration,
+    /// The RocksDB tuning of every database.
+    pub tuning: Tuning,
+    /// The RocksDB tuning of the databases named, over `tuning`.
+    pub database_tuning: HashMap<String, Tuning>,
+}
+
+/// How much a database may store, unlimited when `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub max_documents: Option<u64>,
+    /// The size of the stored keys and documents, encrypted fields included.
+    pub max_bytes: Option<u64>,
+}
+
+impl Quota {
+    fn is_unlimited(&self) -> bool {
+        self.max_documents.is_none() && self.max_bytes.is_none()
+    }
+
+    /// Checks that `database` holding `usage` may store a document of `bytes`.
+    fn check(&self, database: &str, usage: &Usage, bytes: u64) -> Result<(), CoreError> {
+        if let Some(max) = self.max_documents.filter(|max| usage.documents >= *max) {
+            return Err(CoreError::QuotaExceeded(format!(
+                "database {} exceeds its quota of {} documents", database, max
+            )));
+        }
+        if let Some(max) = self.max_bytes.filter(|max| usage.bytes + bytes > *max) {
+            return Err(CoreError::QuotaExceeded(format!(
+                "database {} exceeds its quota of {} bytes, {} are used", database, max, usage.bytes
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// What a database stores, counted once on the first insert under a quota.
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    documents: u64,
+    bytes: u64,
+}
+
+/// A document decoded and validated for an insert, before its key is
+/// checked and its writes are staged.
+struct Prepared {
+    collection: String,
+    key: String,
+    value: Vec<u8>,
+    /// The index column families and keys of its `HASH` indexes.
+    index_keys: Vec<(String, Vec<u8>)>,
+}
+
+/// The writes of the documents inserted together, with their keys and what
+/// they add to the usage of the database.
+#[derive(Default)]
+struct Staged {
+    batch: WriteBatch,
+    keys: HashSet<String>,
+    added: Usage,
 }
 
 impl Config {
@@ -38,59 +131,23 @@ impl Config {
         meta_store: meta_store::Config,
         schema: schema::Config,
         pool: DescriptorPool,
+        memory: &Memory,
     ) -> Result<RocksDb, Error> {
         let mut db_opts = Options::default();
-        let mut block_db_opts = BlockBasedOptions::default();
         db_opts.create_if_missing(true);
         db_opts.create_missing_column_families(true);
         db_opts.set_max_open_files(self.max_open_files);
+        // Counts the block cache hits and misses `RocksDb::stats` reports.
+        db_opts.enable_statistics();
 
-        let lru_cache = Cache::new_lru_cache(self.cache_size);
-        block_db_opts.set_block_cache(&lru_cache);
-        // Get the default column families
-        let cloned_metastore = meta_store.clone();
-        let cf_descriptors = vec![
-            ColumnFamilyDescriptor::new("default", Options::default()),
-            ColumnFamilyDescriptor::new(cloned_metastore.schema_cf_name, Options::default()),
-            ColumnFamilyDescriptor::new(cloned_metastore.index_cf_name, Options::default()),
-            ColumnFamilyDescriptor::new(cloned_metastore.schema_versions_cf_name, Options::default()),
-            ColumnFamilyDescriptor::new(cloned_metastore.user_cf_name, Options::default()),
-        ];
-
-        db_opts.set_block_based_table_factory(&block_db_opts);
-        let path =self.db_path.join(&name);
-        let existing_cf_names = match DB::list_cf(&Options::default(), &path) {
-            Err(_) => None,
-            Ok(list_cf) => Some(list_cf)
-        };
-
-        let existing_cf_names = match existing_cf_names {
-            None => {
-                debug!(db = ?name, "First init");
-                vec![]
-            },
-            Some(cf_names) => {
-                debug!(db = ?name, cfs = ?cf_names, "Existing column families for");
-                cf_names
-            }
-        };
-        
-        // Filter out any column families that already exist
-        let new_cf_descriptors: Vec<ColumnFamilyDescriptor> = cf_descriptors.into_iter().filter(|cf_desc| {
-            !existing_cf_names.contains(&cf_desc.name().to_string())
-        }).collect();
-
-        // Combine existing and new column families
-        let mut combined_cf_descriptors = existing_cf_names.iter().map(|cf_name| {
-            ColumnFamilyDescriptor::new(cf_name, Options::default())
-        }).collect::<Vec<_>>();
-        combined_cf_descriptors.extend(new_cf_descriptors);
-
+        let tuning = tuning::merge(&self.tuning, self.database_tuning.get(&name).unwrap_or(&Tuning::default()));
+        let mut tunings = HashMap::new();
         // Process schema
         let mut collections = Vec::new();
         if let Some(collection_ext) = pool
             .get_extension_by_name("protolith.annotation.v1.collection") {
-            let key_ext = pool.get_extension_by_name("protolith.annotation.v1.key").unwrap();
+            let key_ext = pool.get_extension_by_name("protolith.annotation.v1.key")
+                .ok_or_else(|| CoreError::InvalidDocument("missing the protolith.annotation.v1.key extension".to_string()))?;
             for msg in pool.all_messages() {
                 if msg.options().has_extension(&collection_ext) {
                     let fields = msg
@@ -100,7 +157,7 @@ impl Config {
                             r#type: Some(parse_field_type(f.kind()))
                         })
                         .collect();
-                    let indexes = msg
+                    let mut indexes: Vec<Index> = msg
                         .fields()
                         .filter(|f| f.options().has_extension(&key_ext))
                         .map(|f| Index {
@@ -110,6 +167,17 @@ impl Config {
                             index_type: IndexType::Key.into(),
                             ..Default::default()
                         }).collect();
+                    indexes.extend(hash_indexes(&msg, |f| f.options().has_extension(&key_ext))?);
+                    let annotation = msg
+                        .options()
+                        .get_extension(&collection_ext)
+                        .as_message()
+                        .and_then(|m| m.transcode_to::<v1::Collection>().ok())
+                        .unwrap_or_default();
+                    tunings.insert(
+                        msg.full_name().to_owned(),
+                        tuning::merge(&tuning, &annotation.tuning.unwrap_or_default()),
+                    );
                     collections.push(Collection {
                         name: msg.name().to_owned(),
                         full_name: msg.full_name().to_owned(),
@@ -121,9 +189,67 @@ impl Config {
             }
         }
 
+        let cloned_metastore = meta_store.clone();
+        let cf_options = CfOptions {
+            system: vec![
+                "default".to_string(),
+                cloned_metastore.schema_cf_name,
+                cloned_metastore.index_cf_name,
+                cloned_metastore.schema_versions_cf_name,
+                cloned_metastore.user_cf_name,
+                cloned_metastore.session_cf_name,
+                cloned_metastore.api_key_cf_name,
+                cloned_metastore.audit_cf_name,
+            ],
+            tuning,
+            collections: tunings,
+            memory: memory.clone(),
+        };
+        if let Some(collection) = collections.iter().find(|c| !cf_options.is_collection(&c.full_name)) {
+            return Err(CoreError::InvalidSchema(format!(
+                "collection {} is named like a column family of the metastore", collection.full_name
+            )).into());
+        }
+
+        // Get the default column families
+        let cf_descriptors = cf_options.system.iter().map(|cf_name| {
+            ColumnFamilyDescriptor::new(cf_name, cf_options.options(cf_name))
+        }).collect::<Vec<_>>();
+
+        let path =self.db_path.join(&name);
+        let existing_cf_names = match DB::list_cf(&Options::default(), &path) {
+            Err(_) => None,
+            Ok(list_cf) => Some(list_cf)
+        };
+
+        let existing_cf_names = match existing_cf_names {
+            None => {
+                debug!(db = ?name, "First init");
+                vec![]
+            },
+            Some(cf_names) => {
+                debug!(db = ?name, cfs = ?cf_names, "Existing column families for");
+                cf_names
+            }
+        };
+        
+        // Filter out any column families that already exist
+        let new_cf_descriptors: Vec<ColumnFamilyDescriptor> = cf_descriptors.into_iter().filter(|cf_desc| {
+            !existing_cf_names.contains(&cf_desc.name().to_string())
+        }).collect();
+
+        // Combine existing and new column families
+        let mut combined_cf_descriptors = existing_cf_names.iter().map(|cf_name| {
+            ColumnFamilyDescriptor::new(cf_name, cf_options.options(cf_name))
+        }).collect::<Vec<_>>();
+        combined_cf_descriptors.extend(new_cf_descriptors);
+
         for collection in &collections {
-            let cf_descriptors = parse_collection_to_cf(collection.clone());
-            debug!(collection = ?collection.full_name, index_cfs =? cf_descriptors.len(), "Building CFs for");
+            let cf_descriptors: Vec<_> = parse_collection_to_cf(collection.clone(), |cf_name| cf_options.options(cf_name))
+                .into_iter()
+                .filter(|cf_desc| !existing_cf_names.contains(&cf_desc.name().to_string()))
+                .collect();
+            debug!(collection = ?collection.full_name, cfs =? cf_descriptors.len(), "Building CFs for");
             combined_cf_descriptors.extend(cf_descriptors);
         }
 
@@ -132,8 +258,14 @@ impl Config {
         
         debug!(db = ?name, config = ?meta_store, "Building Metastore");
         let db = Arc::new(db);
-        
+        memory.write_buffers().register(&db);
         let meta_store = meta_store.clone().build(db.clone(), collections, schema).unwrap();
+        let migrated = migrate_default_cf(&db, &cf_options, |collection| {
+            meta_store.get_schema(collection.to_owned()).is_ok()
+        })?;
+        if migrated > 0 {
+            info!(db = ?name, documents = migrated, "moved documents into the column families of their collections");
+        }
         Ok(RocksDb {
             db,
             name,
@@ -141,6 +273,12 @@ impl Config {
             opts: db_opts,
             meta_store: meta_store,
             pool,
+            cipher: self.encryption_key.as_ref().map(FieldCipher::new),
+            quota: self.quota,
+            usage: Arc::new(Mutex::new(None)),
+            slow_query_threshold: self.slow_query_threshold,
+            cf_options,
+            background_paused: Arc::new(AtomicBool::new(false)),
         })
     }
 }
@@ -153,6 +291,70 @@ pub struct RocksDb {
     pub opts: Options,
     meta_store: MetaStore,
     pool: DescriptorPool,
+    cipher: Option<FieldCipher>,
+    quota: Quota,
+    usage: Arc<Mutex<Option<Usage>>>,
+    slow_query_threshold: Duration,
+    cf_options: CfOptions,
+    /// Set while automatic compactions are disabled by
+    /// `pause_background_work`.
+    background_paused: Arc<AtomicBool>,
+}
+
+/// Builds the options of the column families of a database from its tuning
+/// and that of its collections.
+#[derive(Clone)]
+struct CfOptions {
+    /// The `default` and metastore column families.
+    system: Vec<String>,
+    tuning: Tuning,
+    collections: HashMap<String, Tuning>,
+    memory: Memory,
+}
+
+impl CfOptions {
+    /// Whether `cf_name` holds the documents of the collection of that name,
+    /// rather than an index or the metastore.
+    fn is_collection(&self, cf_name: &str) -> bool {
+        !cf_name.contains(':') && !self.system.iter().any(|name| name == cf_name)
+    }
+
+    fn options(&self, cf_name: &str) -> Options {
+        let cache = self.memory.cache();
+        if self.is_collection(cf_name) {
+            let tuning = self.collections.get(cf_name).unwrap_or(&self.tuning);
+            // Document keys start with the name of their collection.
+            let prefix_length = tuning.prefix_length.map(|length| length + cf_name.len() as u64 + 1);
+            return tuning::options(&Tuning { prefix_length, ..tuning.clone() }, cache);
+        }
+        // Index column families are named after their collection, the
+        // prefix extractor is only meant for the document keys.
+        let tuning = cf_name
+            .rsplit_once(':')
+            .and_then(|(collection, _)| self.collections.get(collection))
+            .unwrap_or(&self.tuning);
+        tuning::options(&Tuning { prefix_length: None, ..tuning.clone() }, cache)
+    }
+}
+
+/// The values of RocksDB properties of a column family, by name.
+pub type Properties = HashMap<String, String>;
+
+/// The number of LSM levels, the RocksDB default.
+const NUM_LEVELS: usize = 7;
+
+/// The RocksDB statistics of a database, summed over its column families.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub block_cache_hits: u64,
+    pub block_cache_misses: u64,
+    pub memtable_bytes: u64,
+    /// The memory held by the indexes and filters of the SST files outside of
+    /// the block cache.
+    pub table_readers_bytes: u64,
+    pub pending_compaction_bytes: u64,
+    pub sst_files: u64,
+    pub sst_bytes: u64,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, tError)]
@@ -165,25 +367,123 @@ pub enum DBError {
 
 impl RocksDb {
 
-    pub fn create_user(&self, username: String, password: String) -> Result<(), Error> {
-        self.meta_store.register_user(username, password);
-        Ok(())
+    pub fn create_user(&self, username: String, password: String, grants: Vec<Grant>) -> Result<User, Error> {
+        self.meta_store.register_user(username, password, grants)
+    }
+
+    pub fn get_user(&self, username: &str) -> Result<Option<User>, Error> {
+        self.meta_store.get_user(username)
     }
 
-    pub fn login_user(&self, username: String, password: String) -> Option<String> {
+    pub fn update_user(&self, user: &User) -> Result<(), Error> {
+        self.meta_store.put_user(user)
+    }
+
+    pub fn delete_user(&self, username: &str) -> Result<User, Error> {
+        self.meta_store.delete_user(username)
+    }
+
+    pub fn list_users(&self) -> Result<Vec<User>, Error> {
+        self.meta_store.list_users()
+    }
+
+    pub fn verify_password(&self, username: &str, password: &str) -> Result<bool, Error> {
+        self.meta_store.verify_password(username, password)
+    }
+
+    pub fn change_password(&self, username: &str, password: String) -> Result<User, Error> {
+        self.meta_store.change_password(username, password)
+    }
+
+    pub fn get_session(&self, id: &str) -> Result<Option<Session>, Error> {
+        self.meta_store.get_session(id)
+    }
+
+    pub fn put_session(&self, session: &Session) -> Result<(), Error> {
+        self.meta_store.put_session(session)
+    }
+
+    pub fn touch_session(&self, id: &str, at: Timestamp) -> Result<Option<Session>, Error> {
+        self.meta_store.touch_session(id, at)
+    }
+
+    pub fn delete_session(&self, id: &str) -> Result<bool, Error> {
+        self.meta_store.delete_session(id)
+    }
+
+    pub fn list_sessions(&self) -> Result<Vec<Session>, Error> {
+        self.meta_store.list_sessions()
+    }
+
+    pub fn delete_user_sessions(&self, username: &str) -> Result<usize, Error> {
+        self.meta_store.delete_user_sessions(username)
+    }
+
+    pub fn create_api_key(
+        &self,
+        username: &str,
+        name: String,
+        expires_at: Option<Timestamp>,
+        databases: Vec<String>,
+    ) -> Result<(ApiKey, String), Error> {
+        self.meta_store.create_api_key(username, name, expires_at, databases)
+    }
+
+    pub fn verify_api_key(&self, key: &str) -> Result<Option<ApiKey>, Error> {
+        self.meta_store.verify_api_key(key)
+    }
+
+    pub fn list_api_keys(&self, username: Option<&str>) -> Result<Vec<ApiKey>, Error> {
+        self.meta_store.list_api_keys(username)
+    }
+
+    pub fn delete_api_key(&self, id: &str) -> Result<Option<ApiKey>, Error> {
+        self.meta_store.delete_api_key(id)
+    }
+
+    pub fn delete_user_api_keys(&self, username: &str) -> Result<usize, Error> {
+        self.meta_store.delete_user_api_keys(username)
+    }
+
+    pub fn append_audit_event(&self, event: &AuditEvent) -> Result<(), Error> {
+        self.meta_store.append_audit_event(event)
+    }
+
+    pub fn list_audit_events(
+        &self,
+        since: Option<&Timestamp>,
+        until: Option<&Timestamp>,
+        username: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<AuditEvent>, Error> {
+        self.meta_store.list_audit_events(since, until, username, limit)
+    }
+
+    pub fn login_user(&self, username: String, password: String) -> Result<String, Error> {
         self.meta_store.login_user(username, password)
     }
     
-    pub fn get(&self, collection: String, key: &[u8]) -> Result<Any, Error> {
-        let cf = self.db.cf_handle("default").unwrap();
-        let value = self.db.get_cf(cf, key).unwrap();
+    /// Gets a document, with its encrypted fields when `decrypt` and without
+    /// them otherwise.
+    pub fn get(&self, collection: String, key: &[u8], decrypt: bool, profile: &mut Profile) -> Result<Any, Error> {
+        let mut profiler = Profiler::start(profile, "get", &collection, &self.name, self.slow_query_threshold);
+        let profile = profiler.profile();
+        let cf = self.collection_cf(&collection)?;
+        profile.keys_scanned += 1;
+        let value = profile::timed(&mut profile.seek, || self.db.get_cf(&cf, key))?
+            .ok_or_else(|| CoreError::KeyNotFound(collection.clone(), String::from_utf8_lossy(key).into_owned()))?;
+        profile.keys_returned += 1;
 
-        let message_desc = self.pool.get_message_by_name(&collection).unwrap();
+        let message_desc = profile::timed(&mut profile.schema_lookup, || self.pool.get_message_by_name(&collection)).unwrap();
         
-        let buf = Bytes::from(value.unwrap());
-        let dynamic_message = DynamicMessage::decode(message_desc, buf).unwrap();
-        let mut buf = Vec::new();
-        dynamic_message.encode(&mut buf).unwrap();
+        let buf = profile::timed(&mut profile.decode, || -> Result<Vec<u8>, Error> {
+            let buf = Bytes::from(value);
+            let mut dynamic_message = DynamicMessage::decode(message_desc, buf).unwrap();
+            encryption::open(self.cipher.as_ref(), &mut dynamic_message, key, decrypt)?;
+            let mut buf = Vec::new();
+            dynamic_message.encode(&mut buf).unwrap();
+            Ok(buf)
+        })?;
         let any = Any { 
             type_url: format!("type.googleapis.com/{}", collection),
             value: buf
@@ -191,27 +491,37 @@ impl RocksDb {
         Ok(any)
     }
 
+    pub fn descriptor_pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
     pub fn get_schema(&self, collection: String) -> Result<Schema, Error> {
         let schema = self.meta_store.get_schema(collection)?;
         Ok(schema)
     }
 
-    pub fn create_schema(&mut self, collection: String, key: String, version: u64) -> Result<Schema, Error> {
-        self.meta_store.create_schema(Collection { 
+    pub fn create_schema(&self, collection: String, key: String, version: u64) -> Result<Schema, Error> {
+        let mut indexes = vec![
+            Index {
+                field_name: key.clone(),
+                schema_id: format!("{}:{}", collection, version),
+                index_type: IndexType::Key.into(),
+                index_id: format!("{}:{}", collection, key),
+                ..Default::default()
+            }
+        ];
+        if let Some(msg) = self.pool.get_message_by_name(&collection) {
+            indexes.extend(hash_indexes(&msg, |f| f.name() == key)?);
+        }
+        let collection = Collection { 
             name: collection.clone(),
             full_name: collection.clone(),
             fields: vec![],
-            indexes: vec![
-                Index {
-                    field_name: key.clone(),
-                    schema_id: format!("{}:{}", collection, version),
-                    index_type: IndexType::Key.into(),
-                    index_id: format!("{}:{}", collection, key),
-                    ..Default::default()
-                }
-            ],
+            indexes,
             ..Default::default()
-        })
+        };
+        self.create_collection_cf(&collection)?;
+        self.meta_store.create_schema(collection)
     }
     
     pub fn get_collection(&self, collection: String) -> Result<Collection, Error> {
@@ -221,6 +531,118 @@ impl RocksDb {
         Ok(col)
     }
 
+    pub fn stats(&self) -> Result<Stats, Error> {
+        let mut stats = Stats::default();
+        if let Some(statistics) = self.opts.get_statistics() {
+            stats.block_cache_hits = ticker(&statistics, "rocksdb.block.cache.hit");
+            stats.block_cache_misses = ticker(&statistics, "rocksdb.block.cache.miss");
+        }
+        stats.table_readers_bytes = perf::get_memory_usage_stats(Some(&[&self.db]), None)?.mem_table_readers_total;
+        for name in DB::list_cf(&Options::default(), &self.path)? {
+            let Some(cf) = self.db.cf_handle(&name) else {
+                continue;
+            };
+            let property = |property: &str| -> Result<u64, Error> {
+                Ok(self.db.property_int_value_cf(&cf, property)?.unwrap_or_default())
+            };
+            stats.memtable_bytes += property("rocksdb.cur-size-all-mem-tables")?;
+            stats.pending_compaction_bytes += property("rocksdb.estimate-pending-compaction-bytes")?;
+            stats.sst_bytes += property("rocksdb.live-sst-files-size")?;
+            for level in 0..NUM_LEVELS {
+                let files = self.db.property_value_cf(&cf, format!("rocksdb.num-files-at-level{}", level).as_str())?;
+                stats.sst_files += files.and_then(|files| files.trim().parse::<u64>().ok()).unwrap_or_default();
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Compacts every level of the column families of `collection`, or of
+    /// the whole database, blocking until done.
+    pub fn compact(&self, collection: Option<&str>) -> Result<(), Error> {
+        if self.background_work_paused() {
+            return Err(CoreError::BackgroundWorkPaused(self.name.clone()).into());
+        }
+        let mut opts = CompactOptions::default();
+        // Rewrites the last level as well, dropping the tombstones of deleted
+        // documents rather than only moving them down.
+        opts.set_bottommost_level_compaction(BottommostLevelCompaction::ForceOptimized);
+        for name in self.maintenance_cf_names(collection)? {
+            let Some(cf) = self.db.cf_handle(&name) else {
+                continue;
+            };
+            info!(db = ?self.name, cf = ?name, "compacting column family");
+            self.db.compact_range_cf_opt(&cf, None::<&[u8]>, None::<&[u8]>, &opts);
+        }
+        Ok(())
+    }
+
+    /// Flushes the memtables of the column families of `collection`, or of
+    /// the whole database, blocking until done.
+    pub fn flush(&self, collection: Option<&str>) -> Result<(), Error> {
+        for name in self.maintenance_cf_names(collection)? {
+            let Some(cf) = self.db.cf_handle(&name) else {
+                continue;
+            };
+            debug!(db = ?self.name, cf = ?name, "flushing column family");
+            self.db.flush_cf(&cf)?;
+        }
+        Ok(())
+    }
+
+    /// Disables or enables the automatic compactions of every column family.
+    /// The `rocksdb` crate does not bind `PauseBackgroundWork`, flushes keep
+    /// running so that writes are not stalled on full memtables.
+    pub fn pause_background_work(&self, paused: bool) -> Result<(), Error> {
+        self.background_paused.store(paused, Ordering::SeqCst);
+        for name in DB::list_cf(&Options::default(), &self.path)? {
+            let Some(cf) = self.db.cf_handle(&name) else {
+                continue;
+            };
+            self.db.set_options_cf(&cf, &[("disable_auto_compactions", if paused { "true" } else { "false" })])?;
+        }
+        info!(db = ?self.name, paused, "set background work");
+        Ok(())
+    }
+
+    pub fn background_work_paused(&self) -> bool {
+        self.background_paused.load(Ordering::SeqCst)
+    }
+
+    /// Returns the values of the RocksDB `properties` of the column families
+    /// of `collection`, or of the whole database, by column family. The
+    /// properties RocksDB does not know are left out.
+    pub fn properties(&self, collection: Option<&str>, properties: &[String]) -> Result<Vec<(String, Properties)>, Error> {
+        let mut values = Vec::new();
+        for name in self.maintenance_cf_names(collection)? {
+            let Some(cf) = self.db.cf_handle(&name) else {
+                continue;
+            };
+            let mut cf_values = HashMap::new();
+            for property in properties {
+                if let Some(value) = self.db.property_value_cf(&cf, property.as_str())? {
+                    cf_values.insert(property.clone(), value);
+                }
+            }
+            values.push((name, cf_values));
+        }
+        Ok(values)
+    }
+
+    /// Returns the column families of the documents and indexes of
+    /// `collection`, or every column family of the database.
+    fn maintenance_cf_names(&self, collection: Option<&str>) -> Result<Vec<String>, Error> {
+        let names = DB::list_cf(&Options::default(), &self.path)?;
+        let Some(collection) = collection else {
+            return Ok(names);
+        };
+        self.collection_cf(collection)?;
+        let index_prefix = format!("{}:", collection);
+        Ok(names
+            .into_iter()
+            .filter(|name| name == collection || name.starts_with(&index_prefix))
+            .collect())
+    }
+
     pub fn get_collections(&self) -> Result<Vec<Collection>, Error> {
         let mut collections = Vec::new();
         // Retrieve handle for the schema_versions column family
@@ -237,7 +659,7 @@ impl RocksDb {
             // Seek for latest version of the schemas
             let mut latest_versions = std::collections::HashMap::new();
             // let iter_mode = IteratorMode::From((), ())
-            let iter = self.db.iterator_cf(versions_cf_handle, IteratorMode::Start);
+            let iter = self.db.iterator_cf(&versions_cf_handle, IteratorMode::Start);
             for key_value in iter {
             match key_value {
                 Err(e) => error!("{}", e.into_string()),
@@ -260,7 +682,7 @@ impl RocksDb {
             // Retrieve schemas by the cf schema and collect them
             for (schema_id, version) in latest_versions {
             let key = make_schema_key(&schema_id, &version); // Implement this based on your key structure
-            if let Some(schema_bytes) = self.db.get_cf(schema_cf_handle, &key)? {
+            if let Some(schema_bytes) = self.db.get_cf(&schema_cf_handle, &key)? {
                 let schema = deserialize_schema(&schema_bytes); // Implement schema deserialization
                 let buf = Bytes::from(schema.schema_definition);
                 let col = Collection::decode(buf).unwrap();
@@ -272,7 +694,7 @@ impl RocksDb {
             for collection in &mut collections {
                 let collection_key_prefix = collection.full_name.clone().into_bytes();
                 let iter_mod = IteratorMode::From(&collection_key_prefix, rocksdb::Direction::Forward);
-                let iter = self.db.iterator_cf(index_cf_handle, iter_mod);
+                let iter = self.db.iterator_cf(&index_cf_handle, iter_mod);
                 for key_value in iter {
                     match key_value {
                         Err(err) => error!("{}", err.into_string()),
@@ -291,7 +713,7 @@ impl RocksDb {
                 }
             }
         } else {
-            let iter = self.db.iterator_cf(schema_cf_handle, IteratorMode::Start);
+            let iter = self.db.iterator_cf(&schema_cf_handle, IteratorMode::Start);
             for schema in iter {
                 match schema {
                     Err(err) => error!("{}", err),
@@ -302,7 +724,7 @@ impl RocksDb {
                         let mut collection = Collection::decode(buf).unwrap();
                         let collection_key_prefix = schema_id.into_bytes();
                         let iter_mod = IteratorMode::From(&collection_key_prefix, rocksdb::Direction::Forward);
-                        let iter = self.db.iterator_cf(index_cf_handle, iter_mod);
+                        let iter = self.db.iterator_cf(&index_cf_handle, iter_mod);
                         for idx in iter {
                             match idx {
                                 Err(err) => error!("{}", err.into_string()),
@@ -330,56 +752,279 @@ impl RocksDb {
         Ok(collections)
     }
 
-    pub fn insert(&self, message: Any) -> Result<String, CoreError> {
-        let message_name = message.type_url.split("/").collect::<Vec<&str>>()[1];
-        let message_desc = self.pool.get_message_by_name(&message_name).unwrap();
+    pub fn insert(&self, message: Any, profile: &mut Profile) -> Result<String, CoreError> {
+        let message_name = collection_of(&message.type_url)?.to_owned();
+        let mut profiler = Profiler::start(profile, "insert", &message_name, &self.name, self.slow_query_threshold);
+        let profile = profiler.profile();
+        let document = self.prepare(message, profile)?;
+        let mut usage = self.usage()?;
+        let mut staged = Staged::default();
+        self.stage(document, &mut staged, usage.as_deref().and_then(Option::as_ref), profile)?;
+        self.commit(staged, usage.as_deref_mut().and_then(Option::as_mut), profile)?;
+        Ok(message_name)
+    }
+
+    /// Inserts `documents` in a single write, none of them when one of them
+    /// can not be inserted.
+    fn insert_batch(&self, collection: &str, documents: Vec<Any>) -> Result<u64, CoreError> {
+        let mut profile = Profile::default();
+        let mut profiler = Profiler::start(&mut profile, "import", collection, &self.name, self.slow_query_threshold);
+        let profile = profiler.profile();
+        let inserted = documents.len() as u64;
+        let documents = documents.into_iter()
+            .map(|document| self.prepare(document, profile))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut usage = self.usage()?;
+        let mut staged = Staged::default();
+        for document in documents {
+            self.stage(document, &mut staged, usage.as_deref().and_then(Option::as_ref), profile)?;
+        }
+        self.commit(staged, usage.as_deref_mut().and_then(Option::as_mut), profile)?;
+        Ok(inserted)
+    }
+
+    /// Decodes `message` against its collection and returns its key, the
+    /// value to store, encrypted fields sealed, and its index entries.
+    fn prepare(&self, message: Any, profile: &mut Profile) -> Result<Prepared, CoreError> {
+        let message_name = collection_of(&message.type_url)?;
+        let message_desc = profile::timed(&mut profile.schema_lookup, || self.pool.get_message_by_name(message_name))
+            .ok_or_else(|| CoreError::SchemaNotExists(format!("schema {} not found", message_name)))?;
         
-        let buf = Bytes::from(message.clone().value);
-        let dynamic_message = DynamicMessage::decode(message_desc, buf).unwrap();
+        let buf = Bytes::from(message.value.clone());
+        let encrypted_fields = encryption::encrypted_fields(&message_desc);
+        let dynamic_message = profile::timed(&mut profile.decode, || DynamicMessage::decode(message_desc, buf))
+            .map_err(|e| CoreError::InvalidDocument(e.to_string()))?;
         
-        let cf = self.db.cf_handle("default").unwrap();
-        let schema: Schema = self.meta_store.get_schema(message_name.to_owned())
-            .map_err(|e| CoreError::SchemaNotExists(e.to_string()))?;
-        let buf = Bytes::from(schema.schema_definition);
-        let col = Collection::decode(buf).unwrap();
-        let idx = col.indexes.iter().find(|key| key.index_type()==IndexType::Key).unwrap();
-        let binding = dynamic_message.get_field_by_name(&idx.field_name).unwrap();
-        let idx_field = binding.as_ref();
-        let key = format!("{}:{}", message_name, idx_field.as_str().unwrap());
+        let col = profile::timed(&mut profile.schema_lookup, || -> Result<Collection, CoreError> {
+            let schema: Schema = self.meta_store.get_schema(message_name.to_owned())
+                .map_err(|e| CoreError::SchemaNotExists(e.to_string()))?;
+            let buf = Bytes::from(schema.schema_definition);
+            Collection::decode(buf).map_err(|e| CoreError::InvalidDocument(format!("invalid schema of {}: {}", message_name, e)))
+        })?;
+        let idx = col.indexes.iter().find(|key| key.index_type()==IndexType::Key)
+            .ok_or_else(|| CoreError::InvalidDocument(format!("collection {} has no key", message_name)))?;
+        let binding = dynamic_message.get_field_by_name(&idx.field_name)
+            .ok_or_else(|| CoreError::InvalidDocument(format!("missing key {} of {}", idx.field_name, message_name)))?;
+        let idx_field = key_string(binding.as_ref())
+            .ok_or_else(|| CoreError::InvalidDocument(format!("unsupported key {:?} of {}", binding, message_name)))?;
+        let key = format!("{}:{}", message_name, idx_field);
         debug!(collection = ?message_name, key = ?key, bytes = ?message.value.len(), "insert");
-        let exist = self.db.get_pinned_cf(cf, key.clone().into_bytes())
+        let value = if encrypted_fields.is_empty() {
+            message.value
+        } else {
+            let cipher = self.cipher.as_ref().ok_or_else(|| CoreError::Internal(format!(
+                "collection {} has encrypted fields but no encryption key is configured", message_name
+            )))?;
+            profile::timed(&mut profile.decode, || {
+                encryption::seal(cipher, &dynamic_message, &encrypted_fields, key.as_bytes())
+            })?
+        };
+        let mut index_keys = Vec::new();
+        for idx in col.indexes.iter().filter(|idx| idx.index_type() == IndexType::Hash) {
+            let cf_name = format!("{}:{}", col.full_name, idx.field_name);
+            index_keys.push((cf_name, self.index_key(&dynamic_message, idx, key.as_bytes())?));
+        }
+        Ok(Prepared {
+            collection: message_name.to_owned(),
+            key,
+            value,
+            index_keys,
+        })
+    }
+
+    /// Adds the writes inserting `document` and its index entries to
+    /// `staged`, checking that its key is neither stored nor staged and that
+    /// it fits the quota along with the documents staged before it.
+    fn stage(&self, document: Prepared, staged: &mut Staged, usage: Option<&Usage>, profile: &mut Profile) -> Result<(), CoreError> {
+        let Prepared { collection, key, value, index_keys } = document;
+        let cf = self.collection_cf(&collection)?;
+        profile.keys_scanned += 1;
+        let exist = profile::timed(&mut profile.seek, || self.db.get_pinned_cf(&cf, key.clone().into_bytes()))
             .map_err(|e| CoreError::Internal(e.into_string()))?;
-        match exist {
-            None => {
-                self.db.put_cf(cf, key.into_bytes(), message.value).unwrap();
-                Ok(message_name.to_owned())
-            },
-            Some(_) => Err(CoreError::KeyAlreadyExists(message_name.to_owned(),key).into())
+        if exist.is_some() || staged.keys.contains(&key) {
+            return Err(CoreError::KeyAlreadyExists(collection, key));
+        }
+        let bytes = (key.len() + value.len()) as u64;
+        if let Some(usage) = usage {
+            let usage = Usage {
+                documents: usage.documents + staged.added.documents,
+                bytes: usage.bytes + staged.added.bytes,
+            };
+            self.quota.check(&self.name, &usage, bytes)?;
+        }
+        for (cf_name, index_key) in index_keys {
+            let index_cf = self.db.cf_handle(&cf_name)
+                .ok_or_else(|| CoreError::Internal(format!("missing index column family {}", cf_name)))?;
+            staged.batch.put_cf(&index_cf, index_key, []);
+        }
+        staged.batch.put_cf(&cf, key.as_bytes(), value);
+        staged.keys.insert(key);
+        staged.added.documents += 1;
+        staged.added.bytes += bytes;
+        Ok(())
+    }
+
+    /// Writes the documents of `staged` and adds them to `usage`.
+    fn commit(&self, staged: Staged, usage: Option<&mut Usage>, profile: &mut Profile) -> Result<(), CoreError> {
+        let written = staged.batch.size_in_bytes();
+        profile::timed(&mut profile.write, || self.db.write(staged.batch))
+            .map_err(|e| CoreError::Internal(e.into_string()))?;
+        self.cf_options.memory.write_buffers().written(written);
+        if let Some(usage) = usage {
+            usage.documents += staged.added.documents;
+            usage.bytes += staged.added.bytes;
         }
+        Ok(())
+    }
+
+    /// Returns what the database stores when it has a quota, counting its
+    /// documents the first time, and `None` without a quota. Inserts under
+    /// a quota hold the lock from checking that their key is free until they
+    /// are written, so that concurrent inserts can not exceed it.
+    fn usage(&self) -> Result<Option<MutexGuard<'_, Option<Usage>>>, CoreError> {
+        if self.quota.is_unlimited() {
+            return Ok(None);
+        }
+        let mut usage = self.usage.lock().unwrap();
+        if usage.is_some() {
+            return Ok(Some(usage));
+        }
+        let mut counted = Usage::default();
+        for name in self.collection_cf_names().map_err(|e| CoreError::Internal(e.to_string()))? {
+            let cf = self.collection_cf(&name)?;
+            for item in self.db.iterator_cf_opt(&cf, scan_options(), IteratorMode::Start) {
+                let (key, value) = item.map_err(|e| CoreError::Internal(e.into_string()))?;
+                counted.documents += 1;
+                counted.bytes += (key.len() + value.len()) as u64;
+            }
+        }
+        debug!(db = ?self.name, usage = ?counted, "counted documents under quota");
+        *usage = Some(counted);
+        Ok(Some(usage))
+    }
+
+    /// Returns the key of the `HASH` index `idx` for the document `key`, the
+    /// length delimited encoding of the indexed field followed by the document
+    /// key. The values of encrypted fields are deterministically encrypted.
+    fn index_key(&self, document: &DynamicMessage, idx: &Index, key: &[u8]) -> Result<Vec<u8>, CoreError> {
+        let mut index_key = self.index_prefix(document, idx)?;
+        index_key.extend_from_slice(key);
+        Ok(index_key)
+    }
+
+    /// Returns the prefix the keys of the `HASH` index `idx` share for the
+    /// value of the indexed field of `document`.
+    fn index_prefix(&self, document: &DynamicMessage, idx: &Index) -> Result<Vec<u8>, CoreError> {
+        let field = document.descriptor().get_field_by_name(&idx.field_name)
+            .ok_or_else(|| CoreError::Internal(format!("index {} on unknown field", idx.index_id)))?;
+        let mut value = DynamicMessage::new(document.descriptor());
+        value.set_field(&field, document.get_field(&field).into_owned());
+        let mut token = value.encode_to_vec();
+        if idx.encrypted {
+            let cipher = self.cipher.as_ref()
+                .ok_or_else(|| CoreError::Internal("no encryption key is configured".to_string()))?;
+            token = cipher.index_token(&token, &idx.index_id)?;
+        }
+        let mut prefix = Vec::with_capacity(token.len() + 10);
+        encode_length_delimiter(token.len(), &mut prefix)
+            .map_err(|e| CoreError::Internal(e.to_string()))?;
+        prefix.extend(token);
+        Ok(prefix)
+    }
+
+    /// Returns the documents of `collection` whose `field`, which has a `HASH`
+    /// index, equals `value` in the protobuf JSON mapping. Encrypted fields
+    /// are only looked up when `decrypt`.
+    pub fn find(
+        &self,
+        collection: String,
+        field: &str,
+        value: serde_json::Value,
+        decrypt: bool,
+        profile: &mut Profile,
+    ) -> Result<Vec<Any>, Error> {
+        let mut profiler = Profiler::start(profile, "find", &collection, &self.name, self.slow_query_threshold);
+        let profile = profiler.profile();
+        let col = profile::timed(&mut profile.schema_lookup, || self.get_collection(collection.clone()))
+            .map_err(|_| CoreError::SchemaNotExists(collection.clone()))?;
+        let idx = col.indexes.iter()
+            .find(|idx| idx.index_type() == IndexType::Hash && idx.field_name == field)
+            .ok_or_else(|| CoreError::InvalidDocument(format!("collection {} has no HASH index on {}", collection, field)))?;
+        if idx.encrypted && !decrypt {
+            return Err(CoreError::PermissionDenied(format!(
+                "looking up the encrypted field {} of {} requires the Decrypt permission", field, collection
+            )).into());
+        }
+        let message_desc = self.pool.get_message_by_name(&collection)
+            .ok_or_else(|| CoreError::SchemaNotExists(collection.clone()))?;
+        let json_name = message_desc.get_field_by_name(field)
+            .map(|f| f.json_name().to_owned())
+            .ok_or_else(|| CoreError::InvalidDocument(format!("unknown field {} of {}", field, collection)))?;
+        let document = serde_json::json!({ json_name: value });
+        check_64_bit_integers(&message_desc, &document)?;
+        let document = DynamicMessage::deserialize(message_desc.clone(), document)
+            .map_err(|e| CoreError::InvalidDocument(e.to_string()))?;
+        let prefix = self.index_prefix(&document, idx)?;
+
+        let index_cf_name = format!("{}:{}", col.full_name, idx.field_name);
+        let index_cf = self.db.cf_handle(&index_cf_name)
+            .ok_or_else(|| CoreError::Internal(format!("missing index column family {}", index_cf_name)))?;
+        let cf = self.collection_cf(&collection)?;
+        let mut data = Vec::new();
+        let iter = profile::timed(&mut profile.seek, || {
+            self.db.iterator_cf_opt(&index_cf, scan_options(), IteratorMode::From(&prefix, Direction::Forward))
+        });
+        for item in iter {
+            let (index_key, _) = item?;
+            let Some(key) = index_key.strip_prefix(prefix.as_slice()) else {
+                break;
+            };
+            profile.keys_scanned += 1;
+            // Entries of documents which no longer exist are skipped.
+            let Some(value) = profile::timed(&mut profile.seek, || self.db.get_cf(&cf, key))? else {
+                continue;
+            };
+            let buf = profile::timed(&mut profile.decode, || -> Result<Vec<u8>, Error> {
+                let mut dynamic_message = DynamicMessage::decode(message_desc.clone(), value.as_slice())?;
+                encryption::open(self.cipher.as_ref(), &mut dynamic_message, key, decrypt)?;
+                Ok(dynamic_message.encode_to_vec())
+            })?;
+            data.push(Any {
+                type_url: type_url(&collection),
+                value: buf,
+            });
+        }
+        profile.keys_returned = data.len() as u64;
+        Ok(data)
     }
 
     pub fn list(
         &self,
         collection: String,
+        decrypt: bool,
+        profile: &mut Profile,
     ) -> Result<Vec<Any>, Error> {
         debug!(db = self.name.clone(), collection = collection);
-        let message_desc = self.pool.get_message_by_name(&collection).unwrap();
+        let mut profiler = Profiler::start(profile, "list", &collection, &self.name, self.slow_query_threshold);
+        let profile = profiler.profile();
+        let message_desc = profile::timed(&mut profile.schema_lookup, || self.pool.get_message_by_name(&collection))
+            .ok_or_else(|| CoreError::SchemaNotExists(collection.clone()))?;
         let mut data = Vec::new();
-        let prefix = collection.clone().into_bytes();
-        let cf_handle = self.db.cf_handle("default").unwrap();
-        let iter_mode = IteratorMode::From(&prefix, rocksdb::Direction::Forward);
-        let iter = self.db.iterator_cf(cf_handle, iter_mode);
+        let cf_handle = self.collection_cf(&collection)?;
+        // The iterator seeks to the first document as it is created.
+        let iter = profile::timed(&mut profile.seek, || self.db.iterator_cf_opt(&cf_handle, scan_options(), IteratorMode::Start));
         for i in iter {
+            profile.keys_scanned += 1;
             match i {
                 Ok(item) => {
-                    if !item.0.starts_with(&prefix) {
-                        break;
-                    } 
-        
-                    let buf = Bytes::from(item.1);
-                    let dynamic_message = DynamicMessage::decode(message_desc.clone(), buf).unwrap();
-                    let mut buf = Vec::new();
-                    dynamic_message.encode(&mut buf).unwrap();
+                    let buf = profile::timed(&mut profile.decode, || -> Result<Vec<u8>, Error> {
+                        let buf = Bytes::from(item.1);
+                        let mut dynamic_message = DynamicMessage::decode(message_desc.clone(), buf).unwrap();
+                        encryption::open(self.cipher.as_ref(), &mut dynamic_message, &item.0, decrypt)?;
+                        let mut buf = Vec::new();
+                        dynamic_message.encode(&mut buf).unwrap();
+                        Ok(buf)
+                    })?;
                     let any = Any { 
                         type_url: format!("type.googleapis.com/{}", collection),
                         value: buf
@@ -389,8 +1034,317 @@ impl RocksDb {
                 Err(e) => error!(err = ?e, "failed to iter item")
             }
         }
+        profile.keys_returned = data.len() as u64;
         Ok(data)
     }
+
+    /// Exports the documents of `collection`, or of every collection when `None`,
+    /// encoded as `format`. Every chunk holds a single document, archives start
+    /// with an additional chunk holding the `ArchiveHeader`. Encrypted fields
+    /// are exported decrypted when `decrypt`, otherwise they are left out
+    /// when `omit_encrypted` and the export of their collection fails.
+    ///
+    /// The documents are read from the column families as the chunks are
+    /// taken, one collection after the other.
+    pub fn export(
+        &self,
+        collection: Option<String>,
+        format: ExportFormat,
+        decrypt: bool,
+        omit_encrypted: bool,
+    ) -> Result<Export<'_>, Error> {
+        let names = match &collection {
+            Some(collection) => vec![collection.clone()],
+            None => self.collection_cf_names()?,
+        };
+        let mut collections = Vec::with_capacity(names.len());
+        for name in names {
+            self.collection_cf(&name)?;
+            let message_desc = self.pool.get_message_by_name(&name)
+                .ok_or_else(|| CoreError::SchemaNotExists(name.clone()))?;
+            let encrypted = !encryption::encrypted_fields(&message_desc).is_empty();
+            if encrypted && !decrypt && !omit_encrypted {
+                return Err(CoreError::PermissionDenied(format!(
+                    "collection {} has encrypted fields, which are only exported with the Decrypt permission or omitted", name
+                )).into());
+            }
+            collections.push((name, message_desc, encrypted));
+        }
+
+        let header = if format == ExportFormat::Archive {
+            let mut schemas = Vec::new();
+            for col in self.get_collections()? {
+                if collection.is_none() || collection.as_ref() == Some(&col.full_name) {
+                    schemas.push(self.meta_store.get_schema(col.full_name)?);
+                }
+            }
+            let header = ArchiveHeader {
+                database: self.name.clone(),
+                file_descriptor_set: self.pool.encode_to_vec(),
+                schemas,
+            };
+            Some(header.encode_length_delimited_to_vec())
+        } else {
+            None
+        };
+        debug!(db = ?self.name, collection = ?collection, format = ?format, "export");
+        Ok(Export {
+            db: self,
+            format,
+            raw: collection.is_some(),
+            decrypt,
+            header,
+            collections: collections.into_iter(),
+            current: None,
+        })
+    }
+
+    /// Starts an import of documents encoded as `format`, fed to it in chunks.
+    /// `collection` is required to read raw `ExportFormat::Delimited` messages
+    /// and used for NDJSON documents without an `@type`. Documents of a
+    /// collection `writable` does not allow fail the import.
+    pub fn import<'a>(
+        &'a self,
+        collection: Option<String>,
+        format: ExportFormat,
+        writable: &'a dyn Fn(&str) -> bool,
+    ) -> Import<'a> {
+        Import {
+            db: self,
+            collection,
+            format,
+            writable,
+            buf: Vec::new(),
+            header_read: format != ExportFormat::Archive,
+            documents: Vec::new(),
+            imported: 0,
+        }
+    }
+
+    /// Returns the column family holding the documents of `collection`.
+    fn collection_cf(&self, collection: &str) -> Result<Arc<BoundColumnFamily<'_>>, CoreError> {
+        self.db.cf_handle(collection)
+            .filter(|_| self.cf_options.is_collection(collection))
+            .ok_or_else(|| CoreError::SchemaNotExists(format!("schema {} not found", collection)))
+    }
+
+    /// Creates the column families of the documents and indexes of
+    /// `collection` which do not exist.
+    fn create_collection_cf(&self, collection: &Collection) -> Result<(), Error> {
+        if !self.cf_options.is_collection(&collection.full_name) {
+            return Err(CoreError::InvalidSchema(format!(
+                "collection {} is named like a column family of the metastore", collection.full_name
+            )).into());
+        }
+        let index_cf_names = collection.indexes.iter().map(|idx| format!("{}:{}", collection.full_name, idx.field_name));
+        for cf_name in std::iter::once(collection.full_name.clone()).chain(index_cf_names) {
+            if self.db.cf_handle(&cf_name).is_none() {
+                info!(db = ?self.name, cf = ?cf_name, "creating the column family of collection");
+                let mut opts = self.cf_options.options(&cf_name);
+                opts.set_disable_auto_compactions(self.background_work_paused());
+                self.db.create_cf(&cf_name, &opts)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the names of the collections, in the order of their names, which
+    /// are those of their column families.
+    fn collection_cf_names(&self) -> Result<Vec<String>, Error> {
+        let mut names: Vec<_> = DB::list_cf(&Options::default(), &self.path)?
+            .into_iter()
+            .filter(|name| self.cf_options.is_collection(name))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// Inserts a document given in the protobuf JSON mapping of `collection`,
+    /// or of the message named by its `@type` field.
+    pub fn insert_json(&self, collection: Option<&str>, json: serde_json::Value, profile: &mut Profile) -> Result<String, CoreError> {
+        let any = self.json_to_any(collection, json)?;
+        self.insert(any, profile)
+    }
+
+    /// Gets a document in the protobuf JSON mapping of `collection`.
+    pub fn get_json(&self, collection: String, key: &[u8], decrypt: bool, profile: &mut Profile) -> Result<serde_json::Value, Error> {
+        let any = self.get(collection.clone(), key, decrypt, profile)?;
+        let message_desc = self.pool.get_message_by_name(&collection)
+            .ok_or_else(|| CoreError::SchemaNotExists(collection.clone()))?;
+        let dynamic_message = DynamicMessage::decode(message_desc, any.value.as_slice())?;
+        Ok(serde_json::to_value(&dynamic_message)?)
+    }
+
+    fn parse_json_document(&self, collection: Option<&str>, line: &[u8]) -> Result<Any, CoreError> {
+        let json: serde_json::Value = serde_json::from_slice(line)
+            .map_err(|e| CoreError::InvalidDocument(e.to_string()))?;
+        self.json_to_any(collection, json)
+    }
+
+    /// Strictly decodes `json` against the collection's message descriptor,
+    /// unknown fields and mismatching types are rejected.
+    fn json_to_any(&self, collection: Option<&str>, mut json: serde_json::Value) -> Result<Any, CoreError> {
+        let url = json
+            .as_object_mut()
+            .and_then(|fields| fields.remove("@type"))
+            .and_then(|url| url.as_str().map(str::to_owned));
+        let name = match (&url, collection) {
+            (Some(url), _) => collection_of(url)?,
+            (None, Some(collection)) => collection,
+            (None, None) => return Err(CoreError::InvalidDocument("missing @type".to_string())),
+        };
+        if let Some(collection) = collection.filter(|collection| *collection != name) {
+            return Err(CoreError::InvalidDocument(format!("@type {} does not match collection {}", name, collection)));
+        }
+        let message_desc = self.pool.get_message_by_name(name)
+            .ok_or_else(|| CoreError::InvalidDocument(format!("unknown message type {}", name)))?;
+        check_64_bit_integers(&message_desc, &json)?;
+        let dynamic_message = DynamicMessage::deserialize(message_desc, json)
+            .map_err(|e| CoreError::InvalidDocument(e.to_string()))?;
+        Ok(Any {
+            type_url: type_url(name),
+            value: dynamic_message.encode_to_vec(),
+        })
+    }
+
+    fn restore_schemas(&self, schemas: Vec<Schema>) -> Result<(), Error> {
+        for schema in schemas {
+            if self.meta_store.get_schema(schema.schema_id.clone()).is_err() {
+                let collection = Collection::decode(schema.schema_definition.as_slice())?;
+                info!(db = ?self.name, collection = ?collection.full_name, "restoring schema");
+                self.create_collection_cf(&collection)?;
+                self.meta_store.create_schema(collection)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the count of the ticker `name` from a statistics dump, made of lines
+/// such as `rocksdb.block.cache.hit COUNT : 42`.
+fn ticker(statistics: &str, name: &str) -> u64 {
+    statistics
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.trim_start().strip_prefix("COUNT :"))
+        .and_then(|count| count.trim().parse().ok())
+        .unwrap_or_default()
+}
+
+/// Decodes the `ArchiveHeader` an `ExportFormat::Archive` export starts with,
+/// `None` until `buf` holds all of it.
+pub fn read_archive_header(buf: &mut &[u8]) -> Result<Option<ArchiveHeader>, CoreError> {
+    let Some((prefix, len)) = delimited(buf)? else {
+        return Ok(None);
+    };
+    let header = ArchiveHeader::decode(&buf[prefix..prefix + len])
+        .map_err(|e| CoreError::InvalidDocument(format!("invalid archive header: {}", e)))?;
+    buf.advance(prefix + len);
+    Ok(Some(header))
+}
+
+/// Returns the length of the length delimiter `buf` starts with and the
+/// length it holds, `None` until `buf` holds the whole message.
+fn delimited(buf: &[u8]) -> Result<Option<(usize, usize)>, CoreError> {
+    let mut rest = buf;
+    match decode_length_delimiter(&mut rest) {
+        Ok(len) if rest.len() >= len => Ok(Some((buf.len() - rest.len(), len))),
+        Ok(_) => Ok(None),
+        // A varint is at most 10 bytes, every byte but its last has the
+        // continuation bit set.
+        Err(_) if buf.len() < 10 && buf.iter().all(|b| b & 0x80 != 0) => Ok(None),
+        Err(e) => Err(CoreError::InvalidDocument(e.to_string())),
+    }
+}
+
+fn type_url(collection: &str) -> String {
+    format!("type.googleapis.com/{}", collection)
+}
+
+/// The collection a document with `type_url` belongs to, the full message
+/// name after the last `/` of `<prefix>/<full.message.Name>`.
+pub fn collection_of(type_url: &str) -> Result<&str, CoreError> {
+    match type_url.rsplit_once('/') {
+        Some((prefix, name))
+            if !prefix.is_empty()
+                && !name.is_empty()
+                && name.split('.').all(|part| {
+                    !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                }) =>
+        {
+            Ok(name)
+        }
+        _ => Err(CoreError::InvalidDocument(format!("invalid type url {:?}", type_url))),
+    }
+}
+
+/// Formats the key field `value` of a document the way its key is stored,
+/// `None` for the types keys can not have.
+pub fn key_string(value: &prost_reflect::Value) -> Option<String> {
+    use prost_reflect::Value;
+    let key = match value {
+        Value::String(s) => s.clone(),
+        Value::I32(n) => n.to_string(),
+        Value::I64(n) => n.to_string(),
+        Value::U32(n) => n.to_string(),
+        Value::U64(n) => n.to_string(),
+        Value::F32(n) => n.to_string(),
+        Value::F64(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// The largest integer every JSON number, an `f64`, holds exactly.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+/// Rejects the 64-bit integers of `json` given as floating point numbers
+/// past `MAX_SAFE_INTEGER`, which have already been rounded. A `Struct`
+/// carries all its numbers as `f64`, such values must be given as strings as
+/// the protobuf JSON mapping encodes them.
+fn check_64_bit_integers(message_desc: &MessageDescriptor, json: &serde_json::Value) -> Result<(), CoreError> {
+    let Some(fields) = json.as_object() else {
+        return Ok(());
+    };
+    for (name, value) in fields {
+        let Some(field) = message_desc.get_field_by_json_name(name)
+            .or_else(|| message_desc.get_field_by_name(name)) else {
+            continue;
+        };
+        if field.is_map() {
+            let Kind::Message(entry) = field.kind() else { continue };
+            let kind = entry.map_entry_value_field().kind();
+            for value in value.as_object().into_iter().flat_map(|values| values.values()) {
+                check_64_bit_integer(&field, &kind, value)?;
+            }
+        } else if field.is_list() {
+            for value in value.as_array().into_iter().flatten() {
+                check_64_bit_integer(&field, &field.kind(), value)?;
+            }
+        } else {
+            check_64_bit_integer(&field, &field.kind(), value)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_64_bit_integer(field: &FieldDescriptor, kind: &Kind, value: &serde_json::Value) -> Result<(), CoreError> {
+    match kind {
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 | Kind::Uint64 | Kind::Fixed64 => {}
+        Kind::Message(message_desc)
+            if matches!(message_desc.full_name(), "google.protobuf.Int64Value" | "google.protobuf.UInt64Value") => {}
+        Kind::Message(message_desc) => return check_64_bit_integers(message_desc, value),
+        _ => return Ok(()),
+    }
+    match value.as_number() {
+        Some(n) if n.is_f64() && n.as_f64().is_some_and(|n| n.abs() > MAX_SAFE_INTEGER) => {
+            Err(CoreError::InvalidDocument(format!(
+                "{} must be given as a string, {} is past the integers a JSON number holds exactly",
+                field.full_name(), n
+            )))
+        }
+        _ => Ok(()),
+    }
 }
 
 fn deserialize_schema_version(schema_version_bytes: &[u8]) -> SchemaVersion {
@@ -430,14 +1384,320 @@ fn parse_schema_id_from_key(key:&[u8]) -> Result<String, Error> {
 }
 
 
-fn parse_collection_to_cf(collection: Collection) -> Vec<ColumnFamilyDescriptor> {
-    let mut idx_cfs = Vec::new();
+/// Returns the read options of an iterator over every document of a column
+/// family, whatever its prefix extractor.
+fn scan_options() -> ReadOptions {
+    let mut opts = ReadOptions::default();
+    opts.set_total_order_seek(true);
+    opts
+}
+
+/// The chunks of an export, see `RocksDb::export`.
+pub struct Export<'a> {
+    db: &'a RocksDb,
+    format: ExportFormat,
+    /// Whether `ExportFormat::Delimited` documents are exported as raw
+    /// messages, when a single collection is.
+    raw: bool,
+    decrypt: bool,
+    header: Option<Vec<u8>>,
+    collections: std::vec::IntoIter<(String, MessageDescriptor, bool)>,
+    current: Option<ExportedCollection<'a>>,
+}
+
+struct ExportedCollection<'a> {
+    name: String,
+    message_desc: MessageDescriptor,
+    encrypted: bool,
+    documents: DBIteratorWithThreadMode<'a, DB>,
+}
+
+impl Iterator for Export<'_> {
+    type Item = Result<Vec<u8>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(header) = self.header.take() {
+            return Some(Ok(header));
+        }
+        loop {
+            if let Some(current) = &mut self.current {
+                if let Some(item) = current.documents.next() {
+                    return Some(item.map_err(Error::from).and_then(|(key, value)| {
+                        self.db.export_chunk(current, &key, value.into_vec(), self.format, self.raw, self.decrypt)
+                    }));
+                }
+            }
+            let (name, message_desc, encrypted) = self.collections.next()?;
+            let documents = match self.db.collection_cf(&name) {
+                Ok(cf) => self.db.db.iterator_cf_opt(&cf, scan_options(), IteratorMode::Start),
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.current = Some(ExportedCollection { name, message_desc, encrypted, documents });
+        }
+    }
+}
+
+impl RocksDb {
+    /// Encodes the document stored as `value` under `key` in `collection` as
+    /// `format`, with its encrypted fields opened as `get` does.
+    fn export_chunk(
+        &self,
+        collection: &ExportedCollection<'_>,
+        key: &[u8],
+        mut value: Vec<u8>,
+        format: ExportFormat,
+        raw: bool,
+        decrypt: bool,
+    ) -> Result<Vec<u8>, Error> {
+        let name = &collection.name;
+        if collection.encrypted {
+            let mut dynamic_message = DynamicMessage::decode(collection.message_desc.clone(), value.as_slice())?;
+            encryption::open(self.cipher.as_ref(), &mut dynamic_message, key, decrypt)?;
+            value = dynamic_message.encode_to_vec();
+        }
+        let chunk = match format {
+            ExportFormat::Ndjson => {
+                let dynamic_message = DynamicMessage::decode(collection.message_desc.clone(), value.as_slice())?;
+                let mut json = serde_json::to_value(&dynamic_message)?;
+                if let Some(fields) = json.as_object_mut() {
+                    fields.insert("@type".to_string(), type_url(name).into());
+                }
+                let mut line = serde_json::to_vec(&json)?;
+                line.push(b'\n');
+                line
+            },
+            ExportFormat::Delimited if raw => {
+                let mut buf = Vec::with_capacity(value.len() + 10);
+                encode_length_delimiter(value.len(), &mut buf)?;
+                buf.extend(value);
+                buf
+            },
+            ExportFormat::Delimited | ExportFormat::Archive => Any {
+                type_url: type_url(name),
+                value,
+            }.encode_length_delimited_to_vec(),
+        };
+        Ok(chunk)
+    }
+}
+
+/// How many documents an import writes at once.
+const IMPORT_BATCH: usize = 1000;
+
+/// An import fed the chunks of its documents as they arrive, see
+/// `RocksDb::import`. The documents are written in batches of `IMPORT_BATCH`,
+/// so those of the batches written before a failure stay imported.
+pub struct Import<'a> {
+    db: &'a RocksDb,
+    collection: Option<String>,
+    format: ExportFormat,
+    writable: &'a dyn Fn(&str) -> bool,
+    /// What is left of the chunks once their whole documents are read.
+    buf: Vec<u8>,
+    header_read: bool,
+    documents: Vec<Any>,
+    imported: u64,
+}
+
+impl Import<'_> {
+    /// How many documents are written.
+    pub fn imported(&self) -> u64 {
+        self.imported
+    }
+
+    /// Reads the documents `chunk` completes, writing them once a batch is
+    /// full.
+    pub fn write(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        self.buf.extend_from_slice(chunk);
+        let read = match self.format {
+            ExportFormat::Ndjson => self.read_lines(false)?,
+            ExportFormat::Delimited | ExportFormat::Archive => self.read_delimited()?,
+        };
+        self.buf.drain(..read);
+        Ok(())
+    }
+
+    /// Writes the documents left, returning how many were imported.
+    pub fn finish(mut self) -> Result<u64, Error> {
+        let read = match self.format {
+            ExportFormat::Ndjson => self.read_lines(true)?,
+            ExportFormat::Delimited | ExportFormat::Archive => self.read_delimited()?,
+        };
+        if read < self.buf.len() || !self.header_read {
+            return Err(CoreError::InvalidDocument("truncated message".to_string()).into());
+        }
+        self.flush()?;
+        debug!(db = ?self.db.name, format = ?self.format, documents = ?self.imported, "import");
+        Ok(self.imported)
+    }
+
+    /// Reads the lines of `buf`, the last one only when it ends with a newline
+    /// or `last`, and returns how many bytes were read.
+    fn read_lines(&mut self, last: bool) -> Result<usize, Error> {
+        let end = match self.buf.iter().rposition(|b| *b == b'\n') {
+            _ if last => self.buf.len(),
+            Some(newline) => newline + 1,
+            None => return Ok(0),
+        };
+        let buf = std::mem::take(&mut self.buf);
+        let read = buf[..end]
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+            .try_for_each(|line| {
+                let document = self.db.parse_json_document(self.collection.as_deref(), line)?;
+                self.push(document)
+            });
+        self.buf = buf;
+        read.map(|()| end)
+    }
+
+    /// Reads the length delimited documents of `buf`, after the archive header
+    /// for archives, and returns how many bytes were read.
+    fn read_delimited(&mut self) -> Result<usize, Error> {
+        let mut buf = self.buf.as_slice();
+        if !self.header_read {
+            match read_archive_header(&mut buf)? {
+                Some(header) => self.db.restore_schemas(header.schemas)?,
+                None => return Ok(0),
+            }
+            self.header_read = true;
+        }
+        let mut documents = Vec::new();
+        while let Some((prefix, len)) = delimited(buf)? {
+            let value = &buf[prefix..prefix + len];
+            let document = match &self.collection {
+                Some(name) if self.format == ExportFormat::Delimited => Any { type_url: type_url(name), value: value.to_vec() },
+                _ => Any::decode(value).map_err(|e| CoreError::InvalidDocument(e.to_string()))?,
+            };
+            documents.push(document);
+            buf.advance(prefix + len);
+        }
+        let read = self.buf.len() - buf.len();
+        for document in documents {
+            self.push(document)?;
+        }
+        Ok(read)
+    }
+
+    fn push(&mut self, document: Any) -> Result<(), Error> {
+        let name = collection_of(&document.type_url)?;
+        if !(self.writable)(name) {
+            return Err(CoreError::PermissionDenied(format!(
+                "missing Write permission on collection {} of {}", name, self.db.name
+            )).into());
+        }
+        self.documents.push(document);
+        if self.documents.len() == IMPORT_BATCH {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.documents.is_empty() {
+            return Ok(());
+        }
+        let documents = std::mem::take(&mut self.documents);
+        self.imported += self.db.insert_batch(self.collection.as_deref().unwrap_or_default(), documents)?;
+        Ok(())
+    }
+}
+
+/// The documents moved by each batch of `migrate_default_cf`.
+const MIGRATION_BATCH: usize = 1000;
+
+/// Moves the documents stored in the `default` column family, before each
+/// collection had its own, into the column family of their collection which
+/// is created when missing. Each batch is moved atomically, an interrupted
+/// migration resumes on the next start. Keys which name no collection that
+/// `exists` in the metastore are logged and left in the `default` column
+/// family.
+fn migrate_default_cf(db: &DB, cf_options: &CfOptions, exists: impl Fn(&str) -> bool) -> Result<u64, Error> {
+    let default_cf = db.cf_handle("default")
+        .ok_or_else(|| DBError::InvalidColumnFamily("default".to_string()))?;
+    let mut migrated = 0;
+    let mut moved = 0;
+    let mut batch = WriteBatch::default();
+    // The iterator reads a snapshot, so the batches written meanwhile do not
+    // move it.
+    for item in db.iterator_cf_opt(&default_cf, scan_options(), IteratorMode::Start) {
+        let (key, value) = item?;
+        let collection = std::str::from_utf8(&key)
+            .ok()
+            .and_then(|key| key.split_once(':'))
+            .map(|(collection, _)| collection)
+            .filter(|collection| !collection.is_empty() && cf_options.is_collection(collection) && exists(collection));
+        let Some(collection) = collection else {
+            warn!(key = ?String::from_utf8_lossy(&key), "left a key naming no collection in the default column family");
+            continue;
+        };
+        if db.cf_handle(collection).is_none() {
+            db.create_cf(collection, &cf_options.options(collection))?;
+        }
+        let cf = db.cf_handle(collection)
+            .ok_or_else(|| DBError::InvalidColumnFamily(collection.to_string()))?;
+        batch.put_cf(&cf, &key, value);
+        batch.delete_cf(&default_cf, &key);
+        moved += 1;
+        if moved == MIGRATION_BATCH {
+            db.write(std::mem::take(&mut batch))?;
+            migrated += moved as u64;
+            moved = 0;
+        }
+    }
+    db.write(batch)?;
+    Ok(migrated + moved as u64)
+}
+
+/// Returns the `HASH` indexes annotated on the fields of `msg`, checking that
+/// encrypted fields are neither keys, for which `is_key`, nor otherwise
+/// indexed.
+fn hash_indexes(msg: &MessageDescriptor, is_key: impl Fn(&FieldDescriptor) -> bool) -> Result<Vec<Index>, CoreError> {
+    let mut indexes = Vec::new();
+    for f in msg.fields() {
+        let Some(annotation) = encryption::field_annotation(&f) else {
+            continue;
+        };
+        if annotation.encrypted && is_key(&f) {
+            return Err(CoreError::InvalidSchema(format!("key field {} can not be encrypted", f.full_name())));
+        }
+        let Some(index) = annotation.index else {
+            continue;
+        };
+        // Only equality lookups keep working over the
+        // deterministic encryption of values.
+        if annotation.encrypted && index.r#type() != IndexType::Hash {
+            return Err(CoreError::InvalidSchema(format!(
+                "encrypted field {} only allows HASH indexes, not {}",
+                f.full_name(),
+                index.r#type().as_str_name(),
+            )));
+        }
+        if index.r#type() == IndexType::Hash {
+            indexes.push(Index {
+                index_id: format!("{}:{}", msg.full_name(), f.name()),
+                schema_id: msg.full_name().to_string(),
+                field_name: f.name().to_string(),
+                index_type: IndexType::Hash.into(),
+                encrypted: annotation.encrypted,
+                ..Default::default()
+            });
+        }
+    }
+    Ok(indexes)
+}
+
+/// Returns the column family of the documents of `collection`, named after
+/// it, followed by those of its indexes.
+fn parse_collection_to_cf(collection: Collection, cf_options: impl Fn(&str) -> Options) -> Vec<ColumnFamilyDescriptor> {
+    let mut cfs = vec![ColumnFamilyDescriptor::new(collection.full_name.clone(), cf_options(&collection.full_name))];
     for idx in collection.indexes {
         let cf_name = format!("{}:{}", collection.full_name, idx.field_name);
-        idx_cfs.push(ColumnFamilyDescriptor::new(cf_name, Options::default()));
+        let options = cf_options(&cf_name);
+        cfs.push(ColumnFamilyDescriptor::new(cf_name, options));
     }
 
-    idx_cfs
+    cfs
 }
 
 fn parse_field_type(kind: Kind ) -> i32 {
@@ -460,4 +1720,216 @@ fn parse_field_type(kind: Kind ) -> i32 {
         Kind::Sint32 => field_descriptor_proto::Type::Sint32.into(),
         Kind::Sint64 => field_descriptor_proto::Type::Sint64.into(),
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use protolith_api::protolith::test::v1::{IndexedDocument, MyCollection, OtherCollection};
+
+    use super::*;
+
+    const MY_COLLECTION: &str = "protolith.test.v1.MyCollection";
+    const INDEXED_DOCUMENT: &str = "protolith.test.v1.IndexedDocument";
+
+    /// Opens the database `name` under `path` with the test collections.
+    fn open(path: &std::path::Path, name: &str, quota: Quota) -> RocksDb {
+        let config = Config {
+            db_path: path.to_owned(),
+            cache_size: 1024 * 1024,
+            write_buffer_budget: 0,
+            max_open_files: -1,
+            descriptor_file_name: "descriptor.bin".to_owned(),
+            encryption_key: None,
+            quota,
+            slow_query_threshold: Duration::from_secs(60),
+            tuning: Tuning::default(),
+            database_tuning: HashMap::new(),
+        };
+        let meta_store = meta_store::Config {
+            schema_cf_name: "schema".to_owned(),
+            index_cf_name: "index".to_owned(),
+            schema_versions_cf_name: "schema_versions".to_owned(),
+            user_cf_name: "users".to_owned(),
+            session_cf_name: "sessions".to_owned(),
+            api_key_cf_name: "api_keys".to_owned(),
+            audit_cf_name: "audit".to_owned(),
+            default_db: name.to_owned(),
+        };
+        let schema = schema::Config {
+            enable_versioning: false,
+            default_version: 1,
+        };
+        let pool = DescriptorPool::decode(protolith_api::FILE_DESCRIPTOR_SET).unwrap();
+        let memory = Memory::new(config.cache_size, config.write_buffer_budget);
+        config.build(name.to_owned(), meta_store, schema, pool, &memory).unwrap()
+    }
+
+    fn my_document(id: &str) -> Vec<u8> {
+        MyCollection {
+            id: id.to_owned(),
+            name: format!("name of {}", id),
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn migrates_documents_out_of_the_default_column_family() {
+        let path = std::env::temp_dir().join(format!("protolith-migration-{}", std::process::id()));
+        let name = "before_column_families";
+        // Documents were stored in the default column family, keyed by
+        // their collection.
+        {
+            let mut opts = Options::default();
+            opts.create_if_missing(true);
+            let db = DB::open_cf(&opts, path.join(name), ["default"]).unwrap();
+            db.put(format!("{}:1", MY_COLLECTION), my_document("1")).unwrap();
+            db.put(format!("{}:2", MY_COLLECTION), my_document("2")).unwrap();
+            let other = OtherCollection {
+                some_key: "a".to_owned(),
+                data: "data".to_owned(),
+            };
+            db.put("protolith.test.v1.OtherCollection:a", other.encode_to_vec()).unwrap();
+            db.put("malformed", b"left").unwrap();
+            db.put(b"\xff:1", b"left").unwrap();
+            db.put("protolith.test.v1.Dropped:1", b"left").unwrap();
+        }
+
+        let db = open(&path, name, Quota {
+            max_documents: Some(3),
+            max_bytes: None,
+        });
+
+        let mut profile = Profile::default();
+        let documents = db.list(MY_COLLECTION.to_owned(), false, &mut profile).unwrap();
+        assert_eq!(documents.iter().map(|any| any.value.clone()).collect::<Vec<_>>(), [my_document("1"), my_document("2")]);
+        let key = format!("{}:2", MY_COLLECTION);
+        let document = db.get(MY_COLLECTION.to_owned(), key.as_bytes(), false, &mut profile).unwrap();
+        assert_eq!(document.value, my_document("2"));
+        // The quota counts the documents of every collection once moved.
+        let any = Any {
+            type_url: type_url(MY_COLLECTION),
+            value: my_document("3"),
+        };
+        assert!(matches!(db.insert(any, &mut profile), Err(CoreError::QuotaExceeded(_))));
+        // Keys naming no collection do not keep the database from opening,
+        // nor get a column family.
+        let default_cf = db.db.cf_handle("default").unwrap();
+        let left = db.db.iterator_cf(&default_cf, IteratorMode::Start).count();
+        assert_eq!(left, 3);
+        assert!(!db.collection_cf_names().unwrap().iter().any(|name| name == "protolith.test.v1.Dropped"));
+
+        drop(default_cf);
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn finds_documents_by_hash_index() {
+        let path = std::env::temp_dir().join(format!("protolith-hash-index-{}", std::process::id()));
+        let db = open(&path, "indexed", Quota::default());
+        // The index column family is created along with the collection.
+        db.create_schema(INDEXED_DOCUMENT.to_owned(), "id".to_owned(), 1).unwrap();
+        let mut profile = Profile::default();
+        for (id, email) in [("1", "a@example.com"), ("2", "b@example.com"), ("3", "a@example.com")] {
+            let document = IndexedDocument {
+                id: id.to_owned(),
+                email: email.to_owned(),
+            };
+            let any = Any {
+                type_url: type_url(INDEXED_DOCUMENT),
+                value: document.encode_to_vec(),
+            };
+            db.insert(any, &mut profile).unwrap();
+        }
+
+        let find = |email: &str| -> Vec<String> {
+            db.find(INDEXED_DOCUMENT.to_owned(), "email", email.into(), false, &mut Profile::default())
+                .unwrap()
+                .into_iter()
+                .map(|any| IndexedDocument::decode(any.value.as_slice()).unwrap().id)
+                .collect()
+        };
+        assert_eq!(find("a@example.com"), ["1", "3"]);
+        assert_eq!(find("b@example.com"), ["2"]);
+        assert!(find("a@example").is_empty());
+        // Only fields with a HASH index are looked up.
+        assert!(db.find(INDEXED_DOCUMENT.to_owned(), "id", "1".into(), false, &mut profile).is_err());
+
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_64_bit_integers_rounded_through_f64() {
+        let pool = DescriptorPool::decode(protolith_api::FILE_DESCRIPTOR_SET).unwrap();
+        let profile = pool.get_message_by_name("protolith.types.v1.OpProfile").unwrap();
+        let check = |json| check_64_bit_integers(&profile, &json).is_ok();
+        // The numbers of a Struct, past 2^53 they have been rounded.
+        assert!(check(serde_json::json!({ "totalNs": 9_007_199_254_740_991.0 })));
+        assert!(!check(serde_json::json!({ "totalNs": 9_007_199_254_740_993.0 })));
+        assert!(!check(serde_json::json!({ "total_ns": 1e19 })));
+        // Integers parsed from JSON text and strings are exact.
+        assert!(check(serde_json::json!({ "totalNs": u64::MAX })));
+        assert!(check(serde_json::json!({ "totalNs": "18446744073709551615" })));
+    }
+
+    #[test]
+    fn imports_what_it_exports() {
+        let path = std::env::temp_dir().join(format!("protolith-export-{}", std::process::id()));
+        let source = open(&path, "source", Quota::default());
+        let mut profile = Profile::default();
+        // More documents than fit a batch of the import.
+        for i in 0..IMPORT_BATCH + 5 {
+            let any = Any {
+                type_url: type_url(MY_COLLECTION),
+                value: my_document(&format!("{:04}", i)),
+            };
+            source.insert(any, &mut profile).unwrap();
+        }
+        let exported = source.list(MY_COLLECTION.to_owned(), false, &mut profile).unwrap();
+
+        for format in [ExportFormat::Ndjson, ExportFormat::Delimited, ExportFormat::Archive] {
+            let data: Vec<u8> = source
+                .export(Some(MY_COLLECTION.to_owned()), format, false, false)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+                .concat();
+            let target = open(&path, &format!("{:?}", format).to_lowercase(), Quota::default());
+            let writable = |_: &str| true;
+            let mut import = target.import(Some(MY_COLLECTION.to_owned()), format, &writable);
+            // Documents are split across the chunks they arrive in.
+            for chunk in data.chunks(7) {
+                import.write(chunk).unwrap();
+            }
+            assert_eq!(import.imported(), IMPORT_BATCH as u64);
+            assert_eq!(import.finish().unwrap(), exported.len() as u64);
+            let imported = target.list(MY_COLLECTION.to_owned(), false, &mut profile).unwrap();
+            assert_eq!(imported, exported, "{:?}", format);
+        }
+
+        drop(source);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_batches_imported_before_a_failure() {
+        let path = std::env::temp_dir().join(format!("protolith-import-{}", std::process::id()));
+        let db = open(&path, "partial", Quota::default());
+        let mut data = Vec::new();
+        for i in 0..IMPORT_BATCH + 1 {
+            data.extend(format!(r#"{{"id":"{}","name":"{}"}}"#, i, i).bytes());
+            data.push(b'\n');
+        }
+        data.extend(b"{\"unknown\":1}\n");
+
+        let writable = |_: &str| true;
+        let mut import = db.import(Some(MY_COLLECTION.to_owned()), ExportFormat::Ndjson, &writable);
+        assert!(import.write(&data).is_err());
+        assert_eq!(import.imported(), IMPORT_BATCH as u64);
+        let stored = db.list(MY_COLLECTION.to_owned(), false, &mut Profile::default()).unwrap();
+        assert_eq!(stored.len(), IMPORT_BATCH);
+
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+//! Field level encryption of documents.
+//!
+//! The fields annotated with `(protolith.annotation.v1.field).encrypted` are
+//! moved out of a document before it is stored, encoded as a message of
+//! their own and encrypted with AES-256-GCM under a random nonce, bound to
+//! the document key. The ciphertext is appended to the document as the
+//! bytes field `ENCRYPTED_FIELDS_NUMBER`, which no collection declares.
+//!
+//! `HASH` indexes on encrypted fields index the deterministic AES-256-GCM-SIV
+//! encryption of the values, so that equal values have equal index keys.
+use std::fmt;
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use aes_gcm_siv::Aes256GcmSiv;
+use base64::Engine as _;
+use prost_reflect::{DynamicMessage, FieldDescriptor, MessageDescriptor, ReflectMessage};
+use protolith_api::{
+    prost::{
+        encoding::{decode_key, decode_varint, encode_key, encode_varint, WireType},
+        Message,
+    },
+    protolith::annotation::v1::Field as FieldAnnotation,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::db::CoreError;
+
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// The field number holding the encrypted fields of a document, the largest
+/// valid field number.
+pub const ENCRYPTED_FIELDS_NUMBER: u32 = 536_870_911;
+
+const FIELD_EXTENSION: &str = "protolith.annotation.v1.field";
+
+/// The key encrypted fields are derived from.
+#[derive(Clone)]
+pub struct Key([u8; KEY_LEN]);
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+impl Key {
+    /// Parses a key of 32 bytes given raw, hex or base64 encoded.
+    pub fn parse(bytes: &[u8]) -> Result<Self, CoreError> {
+        let text = std::str::from_utf8(bytes).map(str::trim).unwrap_or_default();
+        let key = if bytes.len() == KEY_LEN {
+            bytes.to_vec()
+        } else if text.len() == 2 * KEY_LEN && text.bytes().all(|b| b.is_ascii_hexdigit()) {
+            (0..KEY_LEN)
+                .map(|i| u8::from_str_radix(&text[2 * i..2 * i + 2], 16))
+                .collect::<Result<_, _>>()
+                .map_err(|e| CoreError::InvalidEncryptionKey(e.to_string()))?
+        } else {
+            base64::engine::general_purpose::STANDARD
+                .decode(text)
+                .map_err(|_| CoreError::InvalidEncryptionKey("not hex or base64 encoded".to_string()))?
+        };
+        let key = key
+            .try_into()
+            .map_err(|_| CoreError::InvalidEncryptionKey(format!("must be {} bytes", KEY_LEN)))?;
+        Ok(Key(key))
+    }
+
+    /// Derives the key of `purpose` with HKDF-SHA256, so that values and
+    /// index tokens are not encrypted under the same key.
+    fn derive(&self, purpose: &str) -> [u8; KEY_LEN] {
+        let mut key = [0; KEY_LEN];
+        Hkdf::<Sha256>::new(None, &self.0)
+            .expand(purpose.as_bytes(), &mut key)
+            .expect("a key of 32 bytes is a valid HKDF-SHA256 output length");
+        key
+    }
+}
+
+/// Encrypts and decrypts the encrypted fields of documents.
+#[derive(Clone)]
+pub struct FieldCipher {
+    value: Aes256Gcm,
+    index: Aes256GcmSiv,
+}
+
+impl FieldCipher {
+    pub fn new(key: &Key) -> Self {
+        Self {
+            value: Aes256Gcm::new(&key.derive("protolith.field.value").into()),
+            index: Aes256GcmSiv::new(&key.derive("protolith.field.index").into()),
+        }
+    }
+
+    /// Encrypts `plaintext` under a random nonce, which the result starts with.
+    pub fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, CoreError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .value
+            .encrypt(&nonce, Payload { msg: plaintext, aad })
+            .map_err(|_| CoreError::Internal("failed to encrypt fields".to_string()))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    pub fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, CoreError> {
+        if ciphertext.len() < NONCE_LEN {
+            return Err(CoreError::Internal("truncated encrypted fields".to_string()));
+        }
+        let (nonce, ciphertext) = ciphertext.split_at(NONCE_LEN);
+        self.value
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+            .map_err(|_| CoreError::Internal("failed to decrypt fields, is the encryption key the one they were written with?".to_string()))
+    }
+
+    /// Deterministically encrypts the indexed value `plaintext` of the index
+    /// `index_id`.
+    pub fn index_token(&self, plaintext: &[u8], index_id: &str) -> Result<Vec<u8>, CoreError> {
+        // A fixed nonce makes AES-GCM-SIV deterministic, which only reveals
+        // whether two values of the same index are equal.
+        let nonce = aes_gcm_siv::Nonce::default();
+        self.index
+            .encrypt(&nonce, Payload { msg: plaintext, aad: index_id.as_bytes() })
+            .map_err(|_| CoreError::Internal("failed to encrypt index value".to_string()))
+    }
+}
+
+/// Returns the `(protolith.annotation.v1.field)` annotation of `field`.
+pub fn field_annotation(field: &FieldDescriptor) -> Option<FieldAnnotation> {
+    let extension = field.parent_pool().get_extension_by_name(FIELD_EXTENSION)?;
+    let options = field.options();
+    if !options.has_extension(&extension) {
+        return None;
+    }
+    options.get_extension(&extension).as_message()?.transcode_to().ok()
+}
+
+/// Returns the fields of `message` annotated as encrypted.
+pub fn encrypted_fields(message: &MessageDescriptor) -> Vec<FieldDescriptor> {
+    message
+        .fields()
+        .filter(|field| field_annotation(field).is_some_and(|annotation| annotation.encrypted))
+        .collect()
+}
+
+/// Encodes `document` with its `fields` encrypted, bound to the document `key`.
+pub fn seal(
+    cipher: &FieldCipher,
+    document: &DynamicMessage,
+    fields: &[FieldDescriptor],
+    key: &[u8],
+) -> Result<Vec<u8>, CoreError> {
+    let mut document = document.clone();
+    // Only the envelope written below may hold the encrypted fields.
+    document.take_unknown_fields().for_each(drop);
+    let mut secret = DynamicMessage::new(document.descriptor());
+    for field in fields {
+        if document.has_field(field) {
+            secret.set_field(field, document.get_field(field).into_owned());
+            document.clear_field(field);
+        }
+    }
+    let sealed = cipher.encrypt(&secret.encode_to_vec(), key)?;
+    let mut buf = document.encode_to_vec();
+    encode_key(ENCRYPTED_FIELDS_NUMBER, WireType::LengthDelimited, &mut buf);
+    encode_varint(sealed.len() as u64, &mut buf);
+    buf.extend_from_slice(&sealed);
+    Ok(buf)
+}
+
+/// Restores the encrypted fields of a stored `document` when `decrypt`,
+/// they are left out otherwise.
+pub fn open(
+    cipher: Option<&FieldCipher>,
+    document: &mut DynamicMessage,
+    key: &[u8],
+    decrypt: bool,
+) -> Result<(), CoreError> {
+    let Some(sealed) = document
+        .unknown_fields()
+        .find(|field| field.number() == ENCRYPTED_FIELDS_NUMBER)
+    else {
+        return Ok(());
+    };
+    let mut buf = Vec::new();
+    sealed.encode(&mut buf);
+    // Sealed documents hold no other unknown fields.
+    document.take_unknown_fields().for_each(drop);
+    if !decrypt {
+        return Ok(());
+    }
+    let cipher = cipher.ok_or_else(|| {
+        CoreError::Internal("document has encrypted fields but no encryption key is configured".to_string())
+    })?;
+    let mut payload = buf.as_slice();
+    let len = decode_key(&mut payload)
+        .and_then(|_| decode_varint(&mut payload))
+        .map_err(|e| CoreError::Internal(e.to_string()))?;
+    if payload.len() as u64 != len {
+        return Err(CoreError::Internal("malformed encrypted fields".to_string()));
+    }
+    let plaintext = cipher.decrypt(payload, key)?;
+    document
+        .merge(plaintext.as_slice())
+        .map_err(|e| CoreError::Internal(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_and_tokens() {
+        let hex = "00".repeat(31) + "01";
+        let key = Key::parse(hex.as_bytes()).unwrap();
+        assert_eq!(key.0[31], 1);
+        let base64 = base64::engine::general_purpose::STANDARD.encode(key.0);
+        assert_eq!(Key::parse(base64.as_bytes()).unwrap().0, key.0);
+        assert!(Key::parse(b"too short").is_err());
+        assert_ne!(key.derive("protolith.field.value"), key.derive("protolith.field.index"));
+        assert_ne!(key.derive("protolith.field.value"), Key::parse(&[2; KEY_LEN]).unwrap().derive("protolith.field.value"));
+
+        let cipher = FieldCipher::new(&key);
+        let sealed = cipher.encrypt(b"secret", b"doc:1").unwrap();
+        assert_ne!(sealed, cipher.encrypt(b"secret", b"doc:1").unwrap());
+        assert_eq!(cipher.decrypt(&sealed, b"doc:1").unwrap(), b"secret");
+        assert!(cipher.decrypt(&sealed, b"doc:2").is_err());
+
+        let token = cipher.index_token(b"secret", "doc:email").unwrap();
+        assert_eq!(token, cipher.index_token(b"secret", "doc:email").unwrap());
+        assert_ne!(token, cipher.index_token(b"secret", "doc:name").unwrap());
+    }
+}
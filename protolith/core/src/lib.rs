@@ -8,6 +8,10 @@ pub use protolith_error as error;
 pub mod meta_store;
 pub mod db;
 pub mod schema;
+pub mod encryption;
+pub mod profile;
+pub mod tuning;
+pub mod memory;
 use serde::Serialize; // Make sure to add serde traits
 
 // Define a struct for your key wrapper, now generic over T
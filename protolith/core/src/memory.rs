@@ -0,0 +1,212 @@
+//! The memory shared by every database of the process, one block cache and
+//! one budget for the memtables of all of them.
+//!
+//! The `rocksdb` crate does not bind the `WriteBufferManager` of RocksDB, so
+//! `WriteBufferManager` enforces the budget the same way from the outside:
+//! once the memtables of all the databases outgrow it, the largest ones are
+//! flushed.
+use std::{
+    cmp::Reverse,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+use protolith_error::Error;
+use rocksdb::{perf, Cache, Options, DB};
+use tracing::{debug, warn};
+
+/// The block cache and memtable budget of the databases, cheap to clone.
+#[derive(Clone)]
+pub struct Memory {
+    cache: Cache,
+    cache_size: usize,
+    write_buffers: Arc<WriteBufferManager>,
+}
+
+/// How much of the shared memory is in use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub block_cache_capacity: u64,
+    pub block_cache_bytes: u64,
+    /// The blocks held by readers, which can not be evicted.
+    pub block_cache_pinned_bytes: u64,
+    /// Zero when the memtables are not limited.
+    pub write_buffer_budget: u64,
+    pub write_buffer_bytes: u64,
+}
+
+impl Memory {
+    /// Allocates a block cache of `cache_size` bytes and limits the memtables
+    /// of all the databases to `write_buffer_budget` bytes, or not at all when
+    /// zero.
+    pub fn new(cache_size: usize, write_buffer_budget: usize) -> Self {
+        Self {
+            cache: Cache::new_lru_cache(cache_size),
+            cache_size,
+            write_buffers: Arc::new(WriteBufferManager {
+                budget: write_buffer_budget,
+                written: AtomicUsize::new(0),
+                databases: Mutex::default(),
+            }),
+        }
+    }
+
+    pub(crate) fn cache(&self) -> &Cache {
+        &self.cache
+    }
+
+    pub(crate) fn write_buffers(&self) -> &WriteBufferManager {
+        &self.write_buffers
+    }
+
+    pub fn usage(&self) -> Usage {
+        let write_buffer_bytes = self.write_buffers.usage();
+        Usage {
+            block_cache_capacity: self.cache_size as u64,
+            block_cache_bytes: self.cache.get_usage() as u64,
+            block_cache_pinned_bytes: self.cache.get_pinned_usage() as u64,
+            write_buffer_budget: self.write_buffers.budget as u64,
+            write_buffer_bytes,
+        }
+    }
+}
+
+/// Limits the memtables of every registered database to a shared budget.
+pub(crate) struct WriteBufferManager {
+    budget: usize,
+    /// The bytes written since the memtables were last measured.
+    written: AtomicUsize,
+    databases: Mutex<Vec<Registered>>,
+}
+
+struct Registered {
+    db: Weak<DB>,
+    /// The size of its memtables after their last flush, the arenas of empty
+    /// memtables which flushing does not reclaim.
+    floor: u64,
+}
+
+impl WriteBufferManager {
+    /// Accounts the memtables of `db` to the budget until it is dropped.
+    pub(crate) fn register(&self, db: &Arc<DB>) {
+        let floor = memtable_bytes(db).unwrap_or_default();
+        let mut databases = self.databases.lock().unwrap();
+        databases.retain(|registered| registered.db.strong_count() > 0);
+        databases.push(Registered { db: Arc::downgrade(db), floor });
+    }
+
+    /// Records `bytes` written, flushing the largest memtables once those of
+    /// all the databases exceed the budget. The writes are not failed when the
+    /// flush fails, memtables keep growing until the next one.
+    pub(crate) fn written(&self, bytes: usize) {
+        if self.budget == 0 {
+            return;
+        }
+        // Measuring every database on each write would cost more than the
+        // write, the budget is checked every sixteenth of it.
+        let interval = (self.budget / 16).max(1);
+        let written = self.written.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if written < interval {
+            return;
+        }
+        self.written.store(0, Ordering::Relaxed);
+        // Another writer is already flushing.
+        let Ok(mut databases) = self.databases.try_lock() else {
+            return;
+        };
+
+        let mut measured: Vec<_> = databases
+            .iter_mut()
+            .filter_map(|registered| {
+                let db = registered.db.upgrade()?;
+                let bytes = measure(&db);
+                Some((registered, db, bytes))
+            })
+            .collect();
+        let mut total: u64 = measured.iter().map(|(_, _, bytes)| bytes).sum();
+        measured.sort_by_key(|(registered, _, bytes)| Reverse(bytes.saturating_sub(registered.floor)));
+        for (registered, db, bytes) in measured {
+            if total <= self.budget as u64 || bytes.saturating_sub(registered.floor) < interval as u64 {
+                break;
+            }
+            debug!(db = ?db.path(), memtable_bytes = bytes, total, budget = self.budget, "flushing memtables over the write buffer budget");
+            if let Err(e) = flush(&db) {
+                warn!(db = ?db.path(), error = ?e, "failed to flush memtables over the write buffer budget");
+                return;
+            }
+            registered.floor = measure(&db);
+            total = total - bytes + registered.floor;
+        }
+    }
+
+    /// The size of the memtables of every database still open.
+    fn usage(&self) -> u64 {
+        let databases: Vec<_> = self
+            .databases
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|registered| registered.db.upgrade())
+            .collect();
+        databases.iter().map(|db| measure(db)).sum()
+    }
+}
+
+fn measure(db: &DB) -> u64 {
+    memtable_bytes(db).unwrap_or_else(|e| {
+        warn!(db = ?db.path(), error = ?e, "failed to measure memtables");
+        0
+    })
+}
+
+/// The size of the memtables of every column family of `db`.
+pub(crate) fn memtable_bytes(db: &DB) -> Result<u64, Error> {
+    Ok(perf::get_memory_usage_stats(Some(&[db]), None)?.mem_table_total)
+}
+
+/// Flushes the memtables of the column families of `db` holding entries,
+/// the others would only be replaced by empty memtables of the same size.
+fn flush(db: &DB) -> Result<(), Error> {
+    for name in DB::list_cf(&Options::default(), db.path())? {
+        let Some(cf) = db.cf_handle(&name) else {
+            continue;
+        };
+        let entries = db.property_int_value_cf(&cf, "rocksdb.num-entries-active-mem-table")?.unwrap_or_default();
+        if entries > 0 {
+            db.flush_cf(&cf)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flushes_over_budget() {
+        let path = std::env::temp_dir().join(format!("protolith-memory-{}", std::process::id()));
+        let memory = Memory::new(1024 * 1024, 2 * 1024 * 1024);
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        // Large enough that RocksDB would not flush on its own.
+        opts.set_write_buffer_size(64 * 1024 * 1024);
+        let db = Arc::new(DB::open_cf(&opts, &path, ["default"]).unwrap());
+        memory.write_buffers().register(&db);
+
+        let value = vec![0u8; 4096];
+        for i in 0..1024u32 {
+            db.put(i.to_be_bytes(), &value).unwrap();
+            memory.write_buffers().written(value.len());
+        }
+        let usage = memory.usage();
+        assert_eq!(usage.write_buffer_budget, 2 * 1024 * 1024);
+        assert!(usage.write_buffer_bytes < 4 * 1024 * 1024, "{:?}", usage);
+        assert_eq!(db.get(0u32.to_be_bytes()).unwrap(), Some(value));
+
+        drop(db);
+        DB::destroy(&Options::default(), &path).unwrap();
+    }
+}
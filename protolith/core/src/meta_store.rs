@@ -1,14 +1,25 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use protolith_api::pbjson_types::Timestamp;
 use protolith_api::protolith::{
-    metastore::v1::{SchemaVersion, Schema},
-    core::v1::Collection
+    metastore::v1::{ApiKey, SchemaVersion, Schema, Session, User},
+    core::v1::Collection,
+    types::v1::{AuditEvent, Grant},
 };
 use protolith_error::{Result, Error};
-use rocksdb::{DB, IteratorMode};
+use rocksdb::{DB, Direction, IteratorMode};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
 use tracing::{error, debug, info};
 use protolith_api::prost::Message;
-use crate::schema;
+use crate::{db::CoreError, schema};
+
+/// A hash of a random password with the cost of the stored hashes.
+const DUMMY_PASSWORD_HASH: &str = "$2b$12$qhYNfC1pD7z5aZQ.PMC73euzZhuMSl6mA7/jC5U1FGoNm1dHIF6U.";
 
 #[derive(Debug, Clone)]
 pub struct MetaStore {
@@ -17,10 +28,17 @@ pub struct MetaStore {
     pub(crate) schema: String,
     pub(crate) schema_config: schema::Config,
     pub(crate) user_cf_name: String,
+    pub(crate) session_cf_name: String,
+    pub(crate) api_key_cf_name: String,
+    pub(crate) audit_cf_name: String,
     collections: Vec<Collection>,
     users: Vec<()>,
     db: Arc<DB>,
-    cache: HashMap<String, Schema>,
+    /// The current schema of each collection, shared by the clones.
+    cache: Arc<RwLock<HashMap<String, Schema>>>,
+    /// Held while writing sessions, so that recording an access can not
+    /// recreate a session deleted meanwhile.
+    sessions: Arc<Mutex<()>>,
 }
 
 #[derive(Debug, Clone)]
@@ -37,6 +55,15 @@ pub struct Config {
     /// Column family name for storing user metadata.
     pub user_cf_name: String,
 
+    /// Column family name for storing the login sessions.
+    pub session_cf_name: String,
+
+    /// Column family name for storing the API keys.
+    pub api_key_cf_name: String,
+
+    /// Column family name for storing the audit log.
+    pub audit_cf_name: String,
+
     pub default_db: String,
 }
 
@@ -58,6 +85,9 @@ impl Config {
             schema_cf_name,
             schema_versions_cf_name,
             user_cf_name,
+            session_cf_name,
+            api_key_cf_name,
+            audit_cf_name,
             ..
         } = self;
         let mut cache = HashMap::new();
@@ -83,9 +113,13 @@ impl Config {
             schema: schema_cf_name,
             schema_versions: schema_versions_cf_name,
             user_cf_name,
+            session_cf_name,
+            api_key_cf_name,
+            audit_cf_name,
             schema_config: schema,
             db,
-            cache,
+            cache: Arc::new(RwLock::new(cache)),
+            sessions: Arc::new(Mutex::new(())),
         })
     }
 
@@ -95,9 +129,9 @@ impl Config {
 
 impl MetaStore {
 
-    pub fn create_schema(&mut self, mut collection_schema: Collection) -> Result<Schema, Error> {
+    pub fn create_schema(&self, mut collection_schema: Collection) -> Result<Schema, Error> {
         let schema = handle_no_version_schema(self.schema.clone(), self.db.clone(), &mut collection_schema);
-        self.cache.insert(collection_schema.full_name, schema.clone());
+        self.cache.write().unwrap().insert(collection_schema.full_name, schema.clone());
         Ok(schema)
     }
 
@@ -106,12 +140,12 @@ impl MetaStore {
         if self.schema_config.enable_versioning {
             todo!()
         } else {
-            if let Some(schema) = self.cache.get(&collection) {
+            if let Some(schema) = self.cache.read().unwrap().get(&collection) {
                 return Ok(schema.clone())
             } else {
                 let schema_cf = self.db.cf_handle(&self.schema).unwrap();
                 let key: Vec<u8> = format!("{}:{}", collection, self.schema_config.default_version).into_bytes();
-                let schema = self.db.get_cf(schema_cf, key)?;
+                let schema = self.db.get_cf(&schema_cf, key)?;
                 if let Some(schema) = schema {
                     let schema = deserialize_schema(&schema); // Implement schema deserialization
                     return Ok(schema)
@@ -123,27 +157,321 @@ impl MetaStore {
     }
 
 
-    pub fn register_user(&self, username: String, password: String) {
-        let hashed_password = bcrypt::hash(password, bcrypt::DEFAULT_COST).unwrap();
-        // Store username and hashed_password in RocksDB
+    /// Stores a new user, failing with `CoreError::UserAlreadyExists` rather
+    /// than replacing an existing one.
+    pub fn register_user(&self, username: String, password: String, grants: Vec<Grant>) -> Result<User, Error> {
+        if self.get_user(&username)?.is_some() {
+            return Err(CoreError::UserAlreadyExists(username).into());
+        }
+        let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST)?;
+        let user = User {
+            username,
+            password_hash,
+            grants,
+            created_at: Some(now()),
+            disabled: false,
+        };
+        self.put_user(&user)?;
+        Ok(user)
+    }
+
+    /// Verifies the password of `username`, returning a new session token.
+    ///
+    /// Unknown users are verified against a dummy hash, so that how long
+    /// logins take does not tell which usernames exist.
+    pub fn login_user(&self, username: String, password: String) -> Result<String, Error> {
+        let Some(user) = self.get_user(&username)? else {
+            let _ = bcrypt::verify(password, DUMMY_PASSWORD_HASH);
+            return Err(CoreError::UserNotFound(username).into());
+        };
+        if !bcrypt::verify(password, &user.password_hash)? {
+            return Err(CoreError::InvalidPassword(username).into());
+        }
+        if user.disabled {
+            return Err(CoreError::UserDisabled(username).into());
+        }
+        Ok(uuid::Uuid::new_v4().to_string())
+    }
+
+    /// Whether `password` is the password of `username`.
+    pub fn verify_password(&self, username: &str, password: &str) -> Result<bool, Error> {
+        match self.get_user(username)? {
+            Some(user) => Ok(bcrypt::verify(password, &user.password_hash)?),
+            None => Err(CoreError::UserNotFound(username.to_owned()).into()),
+        }
+    }
+
+    pub fn change_password(&self, username: &str, password: String) -> Result<User, Error> {
+        let mut user = self
+            .get_user(username)?
+            .ok_or_else(|| CoreError::UserNotFound(username.to_owned()))?;
+        user.password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST)?;
+        self.put_user(&user)?;
+        Ok(user)
+    }
+
+    pub fn get_user(&self, username: &str) -> Result<Option<User>, Error> {
+        let user_cf = self.db.cf_handle(&self.user_cf_name).unwrap();
+        match self.db.get_cf(&user_cf, username)? {
+            Some(bytes) => Ok(Some(deserialize_user(username, &bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put_user(&self, user: &User) -> Result<(), Error> {
+        let user_cf = self.db.cf_handle(&self.user_cf_name).unwrap();
+        self.db.put_cf(&user_cf, &user.username, user.encode_to_vec())?;
+        Ok(())
+    }
+
+    pub fn delete_user(&self, username: &str) -> Result<User, Error> {
+        let user = self
+            .get_user(username)?
+            .ok_or_else(|| CoreError::UserNotFound(username.to_owned()))?;
         let user_cf = self.db.cf_handle(&self.user_cf_name).unwrap();
-        self.db.put_cf(user_cf, username, hashed_password).unwrap();
+        self.db.delete_cf(&user_cf, username)?;
+        Ok(user)
     }
 
-    pub fn login_user(&self, username: String, password: String) -> Option<String> {
-        // Retrieve user and hashed password from RocksDB
+    pub fn list_users(&self) -> Result<Vec<User>, Error> {
         let user_cf = self.db.cf_handle(&self.user_cf_name).unwrap();
-        let user = self.db.get_cf(user_cf, username).unwrap();
-        if let Some(hashed_password) = user {
-            let hashed_password = std::str::from_utf8(&hashed_password).unwrap();
-            if bcrypt::verify(password, hashed_password).unwrap() {
-                let session_token = uuid::Uuid::new_v4().to_string();
-                // Store session_token in RocksDB with an expiration time
-                return Some(session_token);
+        let mut users = Vec::new();
+        for entry in self.db.iterator_cf(&user_cf, IteratorMode::Start) {
+            let (key, value) = entry?;
+            users.push(deserialize_user(std::str::from_utf8(&key)?, &value)?);
+        }
+        Ok(users)
+    }
+
+    pub fn get_session(&self, id: &str) -> Result<Option<Session>, Error> {
+        let session_cf = self.db.cf_handle(&self.session_cf_name).unwrap();
+        match self.db.get_cf(&session_cf, id)? {
+            Some(bytes) => Ok(Some(Session::decode(bytes.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put_session(&self, session: &Session) -> Result<(), Error> {
+        let _sessions = self.sessions.lock().unwrap();
+        let session_cf = self.db.cf_handle(&self.session_cf_name).unwrap();
+        self.db.put_cf(&session_cf, &session.id, session.encode_to_vec())?;
+        Ok(())
+    }
+
+    /// Records an access to the session `id` at `at`, returns the updated
+    /// session or `None` when it no longer exists.
+    pub fn touch_session(&self, id: &str, at: Timestamp) -> Result<Option<Session>, Error> {
+        let _sessions = self.sessions.lock().unwrap();
+        let Some(mut session) = self.get_session(id)? else {
+            return Ok(None);
+        };
+        session.last_accessed_at = Some(at);
+        let session_cf = self.db.cf_handle(&self.session_cf_name).unwrap();
+        self.db.put_cf(&session_cf, &session.id, session.encode_to_vec())?;
+        Ok(Some(session))
+    }
+
+    /// Deletes a session, returns whether it existed.
+    pub fn delete_session(&self, id: &str) -> Result<bool, Error> {
+        let _sessions = self.sessions.lock().unwrap();
+        let existed = self.get_session(id)?.is_some();
+        let session_cf = self.db.cf_handle(&self.session_cf_name).unwrap();
+        self.db.delete_cf(&session_cf, id)?;
+        Ok(existed)
+    }
+
+    pub fn list_sessions(&self) -> Result<Vec<Session>, Error> {
+        let session_cf = self.db.cf_handle(&self.session_cf_name).unwrap();
+        let mut sessions = Vec::new();
+        for entry in self.db.iterator_cf(&session_cf, IteratorMode::Start) {
+            let (_, value) = entry?;
+            sessions.push(Session::decode(value.as_ref())?);
+        }
+        Ok(sessions)
+    }
+
+    /// Deletes every session of `username`, returns how many were deleted.
+    pub fn delete_user_sessions(&self, username: &str) -> Result<usize, Error> {
+        let _sessions = self.sessions.lock().unwrap();
+        let session_cf = self.db.cf_handle(&self.session_cf_name).unwrap();
+        let mut deleted = 0;
+        for session in self.list_sessions()? {
+            if session.username == username {
+                self.db.delete_cf(&session_cf, &session.id)?;
+                deleted += 1;
             }
-        } 
-        None
+        }
+        Ok(deleted)
+    }
+
+    /// Stores a new API key of `username`, returns it with the key itself,
+    /// of which only the hash is kept.
+    pub fn create_api_key(
+        &self,
+        username: &str,
+        name: String,
+        expires_at: Option<Timestamp>,
+        databases: Vec<String>,
+    ) -> Result<(ApiKey, String), Error> {
+        if self.get_user(username)?.is_none() {
+            return Err(CoreError::UserNotFound(username.to_owned()).into());
+        }
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let secret = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+        let key = format!("pk_{}.{}", id, secret);
+        let api_key = ApiKey {
+            id,
+            username: username.to_owned(),
+            name,
+            key_hash: hash_api_key(&key),
+            created_at: Some(now()),
+            expires_at,
+            databases,
+        };
+        let api_key_cf = self.db.cf_handle(&self.api_key_cf_name).unwrap();
+        self.db.put_cf(&api_key_cf, &api_key.id, api_key.encode_to_vec())?;
+        Ok((api_key, key))
+    }
+
+    pub fn get_api_key(&self, id: &str) -> Result<Option<ApiKey>, Error> {
+        let api_key_cf = self.db.cf_handle(&self.api_key_cf_name).unwrap();
+        match self.db.get_cf(&api_key_cf, id)? {
+            Some(bytes) => Ok(Some(ApiKey::decode(bytes.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the stored API key matching `key`, if it exists and has not
+    /// expired.
+    pub fn verify_api_key(&self, key: &str) -> Result<Option<ApiKey>, Error> {
+        let Some(id) = key.strip_prefix("pk_").and_then(|key| key.split_once('.')).map(|(id, _)| id) else {
+            return Ok(None);
+        };
+        let Some(api_key) = self.get_api_key(id)? else {
+            return Ok(None);
+        };
+        // Compared in constant time, so that timing does not tell how much
+        // of the hash of a guessed key matches.
+        if !bool::from(api_key.key_hash.as_bytes().ct_eq(hash_api_key(key).as_bytes())) {
+            return Ok(None);
+        }
+        let now = now();
+        match &api_key.expires_at {
+            Some(expires_at) if (expires_at.seconds, expires_at.nanos) <= (now.seconds, now.nanos) => Ok(None),
+            _ => Ok(Some(api_key)),
+        }
+    }
+
+    /// Lists the API keys of `username`, or every key when `None`.
+    pub fn list_api_keys(&self, username: Option<&str>) -> Result<Vec<ApiKey>, Error> {
+        let api_key_cf = self.db.cf_handle(&self.api_key_cf_name).unwrap();
+        let mut api_keys = Vec::new();
+        for entry in self.db.iterator_cf(&api_key_cf, IteratorMode::Start) {
+            let (_, value) = entry?;
+            let api_key = ApiKey::decode(value.as_ref())?;
+            if username.is_none_or(|username| api_key.username == username) {
+                api_keys.push(api_key);
+            }
+        }
+        Ok(api_keys)
+    }
+
+    /// Deletes an API key, returns it when it existed.
+    pub fn delete_api_key(&self, id: &str) -> Result<Option<ApiKey>, Error> {
+        let api_key = self.get_api_key(id)?;
+        if api_key.is_some() {
+            let api_key_cf = self.db.cf_handle(&self.api_key_cf_name).unwrap();
+            self.db.delete_cf(&api_key_cf, id)?;
+        }
+        Ok(api_key)
+    }
+
+    /// Deletes every API key of `username`, returns how many were deleted.
+    pub fn delete_user_api_keys(&self, username: &str) -> Result<usize, Error> {
+        let api_key_cf = self.db.cf_handle(&self.api_key_cf_name).unwrap();
+        let api_keys = self.list_api_keys(Some(username))?;
+        for api_key in &api_keys {
+            self.db.delete_cf(&api_key_cf, &api_key.id)?;
+        }
+        Ok(api_keys.len())
+    }
+
+    /// Appends `event` to the audit log, events are keyed by their time and
+    /// never updated.
+    pub fn append_audit_event(&self, event: &AuditEvent) -> Result<(), Error> {
+        let audit_cf = self.db.cf_handle(&self.audit_cf_name).unwrap();
+        let mut key = audit_key(event.time.as_ref());
+        key.extend_from_slice(event.id.as_bytes());
+        self.db.put_cf(&audit_cf, key, event.encode_to_vec())?;
+        Ok(())
+    }
+
+    /// Lists up to `limit` audit events recorded in `[since, until)`, of
+    /// `username` when set, oldest first.
+    pub fn list_audit_events(
+        &self,
+        since: Option<&Timestamp>,
+        until: Option<&Timestamp>,
+        username: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<AuditEvent>, Error> {
+        let audit_cf = self.db.cf_handle(&self.audit_cf_name).unwrap();
+        let start = audit_key(since);
+        let end = until.map(|until| audit_key(Some(until)));
+        let mut events = Vec::new();
+        for entry in self.db.iterator_cf(&audit_cf, IteratorMode::From(&start, Direction::Forward)) {
+            let (key, value) = entry?;
+            if end.as_ref().is_some_and(|end| key.as_ref() >= end.as_slice()) || events.len() >= limit {
+                break;
+            }
+            let event = AuditEvent::decode(value.as_ref())?;
+            if username.is_none_or(|username| event.username == username) {
+                events.push(event);
+            }
+        }
+        Ok(events)
+    }
+}
+
+/// The big endian seconds and nanoseconds of `time`, so that audit events
+/// sort by time.
+fn audit_key(time: Option<&Timestamp>) -> Vec<u8> {
+    let (seconds, nanos) = time.map(|time| (time.seconds.max(0) as u64, time.nanos.max(0) as u32)).unwrap_or_default();
+    let mut key = Vec::with_capacity(12);
+    key.extend_from_slice(&seconds.to_be_bytes());
+    key.extend_from_slice(&nanos.to_be_bytes());
+    key
+}
+
+/// API keys are random enough for a single unsalted SHA-256 round.
+fn hash_api_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn now() -> Timestamp {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Timestamp {
+        seconds: since_epoch.as_secs() as i64,
+        nanos: since_epoch.subsec_nanos() as i32,
+    }
+}
+
+/// Decodes a stored `User`, users stored before roles were introduced only
+/// hold their bcrypt hash and have no grants.
+fn deserialize_user(username: &str, bytes: &[u8]) -> Result<User, Error> {
+    if bytes.starts_with(b"$2") {
+        return Ok(User {
+            username: username.to_owned(),
+            password_hash: std::str::from_utf8(bytes)?.to_owned(),
+            ..Default::default()
+        });
     }
+    Ok(User::decode(bytes)?)
 }
 
 
@@ -164,7 +492,7 @@ fn handle_versioned_schema(schema_cf_name: String, schema_versions_cf_name: Stri
     let schema_handle = db.cf_handle(&schema_cf_name).unwrap();
     let schema_versions_handle = db.cf_handle(&schema_versions_cf_name).unwrap();
     let iter_mod = IteratorMode::From(&prefix, rocksdb::Direction::Forward);
-    let iter = db.iterator_cf(schema_versions_handle, iter_mod);
+    let iter = db.iterator_cf(&schema_versions_handle, iter_mod);
     let mut latest = 0;
     for schema_ver in iter {
         match schema_ver {
@@ -203,8 +531,8 @@ fn handle_versioned_schema(schema_cf_name: String, schema_versions_cf_name: Stri
         let mut buf_schema = vec![];
         schema_ver.encode(&mut buf_ver).unwrap();
         schema.encode(&mut buf_schema).unwrap();
-        db.put_cf(schema_versions_handle, &key, buf_ver).unwrap();
-        db.put_cf(schema_handle, &key, buf_schema).unwrap();
+        db.put_cf(&schema_versions_handle, &key, buf_ver).unwrap();
+        db.put_cf(&schema_handle, &key, buf_schema).unwrap();
         info!(collection = ?key, version = ?latest, "Created new versioned schema:");
         return schema
     } else {
@@ -232,7 +560,7 @@ fn handle_no_version_schema(schema_cf_name: String, db: Arc<DB>, collection: &mu
 
     let mut buf_schema = vec![];
     schema.encode(&mut buf_schema).unwrap();
-    db.put_cf(schema_cf_handle, key.clone(), buf_schema).unwrap();
+    db.put_cf(&schema_cf_handle, key.clone(), buf_schema).unwrap();
     info!(collection = ?schema_id, version = ?default_ver, "Created new schema:");
     schema
 }
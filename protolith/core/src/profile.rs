@@ -0,0 +1,196 @@
+//! Timing breakdowns of the document operations of `RocksDb` and the slow
+//! query log.
+//!
+//! Every `list`, `get` and `insert` fills a `Profile`, operations taking
+//! longer than the configured threshold are logged under `TRACE_TARGET`.
+//! The reads of RocksDB are counted with its thread local `PerfContext`,
+//! which is only valid as the operations do not leave their thread.
+use std::time::{Duration, Instant};
+
+use protolith_api::protolith::types::v1::OpProfile;
+use rocksdb::perf::{self, PerfContext, PerfMetric, PerfStatsLevel};
+use tracing::warn;
+
+/// The target of the slow query log events.
+pub const TRACE_TARGET: &str = "slow_query";
+
+/// What an operation spent its time on and how much it read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Also measures the time RocksDB spends reading blocks, at the cost of
+    /// reading the clock for every block.
+    timed: bool,
+    pub operation: &'static str,
+    pub collection: String,
+    pub total: Duration,
+    /// Spent finding the descriptor and schema of the collection.
+    pub schema_lookup: Duration,
+    /// Spent seeking iterators to the collection, or looking up the key of
+    /// point reads.
+    pub seek: Duration,
+    /// Spent decoding and encoding documents, encrypted fields included.
+    pub decode: Duration,
+    /// Spent writing the document and its indexes.
+    pub write: Duration,
+    pub keys_scanned: u64,
+    pub keys_returned: u64,
+    /// Internal entries RocksDB stepped over, such as deleted and overwritten
+    /// keys.
+    pub internal_keys_skipped: u64,
+    /// The size of the keys and values RocksDB returned.
+    pub bytes_read: u64,
+    pub block_cache_hits: u64,
+    pub block_reads: u64,
+    pub block_read_bytes: u64,
+    /// Only measured when the profile is `timed`.
+    pub block_read_time: Duration,
+}
+
+impl Profile {
+    pub fn new(timed: bool) -> Self {
+        Self {
+            timed,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Profile> for OpProfile {
+    fn from(profile: Profile) -> Self {
+        let nanos = |d: Duration| d.as_nanos() as u64;
+        Self {
+            operation: profile.operation.to_owned(),
+            collection: profile.collection,
+            total_ns: nanos(profile.total),
+            schema_lookup_ns: nanos(profile.schema_lookup),
+            seek_ns: nanos(profile.seek),
+            decode_ns: nanos(profile.decode),
+            write_ns: nanos(profile.write),
+            keys_scanned: profile.keys_scanned,
+            keys_returned: profile.keys_returned,
+            internal_keys_skipped: profile.internal_keys_skipped,
+            bytes_read: profile.bytes_read,
+            block_cache_hits: profile.block_cache_hits,
+            block_reads: profile.block_reads,
+            block_read_bytes: profile.block_read_bytes,
+            block_read_ns: nanos(profile.block_read_time),
+        }
+    }
+}
+
+/// Profiles an operation until dropped, early returns included, then logs it
+/// when it took longer than the threshold.
+pub(crate) struct Profiler<'a> {
+    profile: &'a mut Profile,
+    database: &'a str,
+    threshold: Duration,
+    perf: PerfContext,
+    start: Instant,
+}
+
+impl<'a> Profiler<'a> {
+    pub(crate) fn start(
+        profile: &'a mut Profile,
+        operation: &'static str,
+        collection: &str,
+        database: &'a str,
+        threshold: Duration,
+    ) -> Self {
+        profile.operation = operation;
+        profile.collection = collection.to_owned();
+        perf::set_perf_stats(if profile.timed {
+            PerfStatsLevel::EnableTimeExceptForMutex
+        } else {
+            PerfStatsLevel::EnableCount
+        });
+        let mut perf = PerfContext::default();
+        perf.reset();
+        Self {
+            profile,
+            database,
+            threshold,
+            perf,
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn profile(&mut self) -> &mut Profile {
+        self.profile
+    }
+}
+
+impl Drop for Profiler<'_> {
+    fn drop(&mut self) {
+        let profile = &mut *self.profile;
+        profile.total = self.start.elapsed();
+        let metric = |metric| self.perf.metric(metric);
+        profile.internal_keys_skipped =
+            metric(PerfMetric::InternalKeySkippedCount) + metric(PerfMetric::InternalDeleteSkippedCount);
+        profile.bytes_read = metric(PerfMetric::GetReadBytes)
+            + metric(PerfMetric::MultigetReadBytes)
+            + metric(PerfMetric::IterReadBytes);
+        profile.block_cache_hits = metric(PerfMetric::BlockCacheHitCount);
+        profile.block_reads = metric(PerfMetric::BlockReadCount);
+        profile.block_read_bytes = metric(PerfMetric::BlockReadByte);
+        profile.block_read_time = Duration::from_nanos(metric(PerfMetric::BlockReadTime));
+        perf::set_perf_stats(PerfStatsLevel::Disable);
+
+        if profile.total >= self.threshold {
+            warn!(
+                target: TRACE_TARGET,
+                database = %self.database,
+                collection = %profile.collection,
+                operation = profile.operation,
+                total = ?profile.total,
+                schema_lookup = ?profile.schema_lookup,
+                seek = ?profile.seek,
+                decode = ?profile.decode,
+                write = ?profile.write,
+                keys_scanned = profile.keys_scanned,
+                keys_returned = profile.keys_returned,
+                internal_keys_skipped = profile.internal_keys_skipped,
+                bytes_read = profile.bytes_read,
+                block_cache_hits = profile.block_cache_hits,
+                block_reads = profile.block_reads,
+                block_read_bytes = profile.block_read_bytes,
+                "slow query"
+            );
+        }
+    }
+}
+
+/// Runs `f`, adding the time it took to `spent`.
+pub(crate) fn timed<T>(spent: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let output = f();
+    *spent += start.elapsed();
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocksdb::DB;
+
+    #[test]
+    fn counts_reads() {
+        let path = std::env::temp_dir().join(format!("protolith-profile-{}", std::process::id()));
+        let db = DB::open_default(&path).unwrap();
+        db.put(b"key", b"value").unwrap();
+
+        let mut profile = Profile::new(true);
+        {
+            let mut profiler = Profiler::start(&mut profile, "get", "test", "test", Duration::MAX);
+            let profile = profiler.profile();
+            let value = timed(&mut profile.seek, || db.get(b"key").unwrap());
+            assert_eq!(value.as_deref(), Some(b"value".as_slice()));
+        }
+        assert_eq!(profile.operation, "get");
+        assert_eq!(profile.bytes_read, 5);
+        assert!(profile.seek > Duration::ZERO);
+        assert!(profile.total >= profile.seek);
+
+        drop(db);
+        DB::destroy(&rocksdb::Options::default(), &path).unwrap();
+    }
+}
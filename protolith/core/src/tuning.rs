@@ -0,0 +1,110 @@
+//! The RocksDB options of the column families of a database, tuned for every
+//! database, for each database and for each collection.
+use protolith_api::{
+    protolith::annotation::v1::{CompactionStyle, Compression, Tuning},
+    DescriptorPool, DynamicMessage, FILE_DESCRIPTOR_SET,
+};
+use protolith_error::Error;
+use rocksdb::{BlockBasedOptions, Cache, DBCompactionStyle, DBCompressionType, Options, SliceTransform};
+
+/// Returns `tuning` with the fields set in `over` replaced.
+pub fn merge(tuning: &Tuning, over: &Tuning) -> Tuning {
+    let compression_per_level = if over.compression_per_level.is_empty() {
+        tuning.compression_per_level.clone()
+    } else {
+        over.compression_per_level.clone()
+    };
+    Tuning {
+        compression: over.compression.or(tuning.compression),
+        compression_per_level,
+        bloom_filter_bits: over.bloom_filter_bits.or(tuning.bloom_filter_bits),
+        block_size: over.block_size.or(tuning.block_size),
+        write_buffer_size: over.write_buffer_size.or(tuning.write_buffer_size),
+        compaction_style: over.compaction_style.or(tuning.compaction_style),
+        prefix_length: over.prefix_length.or(tuning.prefix_length),
+    }
+}
+
+/// Decodes a `Tuning` given in its protobuf JSON mapping, such as
+/// `{"compression": "LZ4", "bloomFilterBits": 10}`.
+pub fn from_json(json: serde_json::Value) -> Result<Tuning, Error> {
+    let pool = DescriptorPool::decode(FILE_DESCRIPTOR_SET)?;
+    let message_desc = pool
+        .get_message_by_name("protolith.annotation.v1.Tuning")
+        .ok_or("missing protolith.annotation.v1.Tuning descriptor")?;
+    Ok(DynamicMessage::deserialize(message_desc, json)?.transcode_to()?)
+}
+
+/// Builds the options of a column family tuned by `tuning`, reading its
+/// blocks through `cache`.
+pub(crate) fn options(tuning: &Tuning, cache: &Cache) -> Options {
+    let mut opts = Options::default();
+    let mut block_opts = BlockBasedOptions::default();
+    block_opts.set_block_cache(cache);
+    if tuning.compression.is_some() {
+        opts.set_compression_type(compression_type(tuning.compression()));
+    }
+    if !tuning.compression_per_level.is_empty() {
+        let levels: Vec<_> = tuning.compression_per_level().map(compression_type).collect();
+        opts.set_compression_per_level(&levels);
+    }
+    if let Some(bits) = tuning.bloom_filter_bits {
+        block_opts.set_bloom_filter(bits, false);
+    }
+    if let Some(block_size) = tuning.block_size {
+        block_opts.set_block_size(block_size as usize);
+    }
+    if let Some(write_buffer_size) = tuning.write_buffer_size {
+        opts.set_write_buffer_size(write_buffer_size as usize);
+    }
+    if tuning.compaction_style.is_some() {
+        opts.set_compaction_style(match tuning.compaction_style() {
+            CompactionStyle::Level => DBCompactionStyle::Level,
+            CompactionStyle::Universal => DBCompactionStyle::Universal,
+            CompactionStyle::Fifo => DBCompactionStyle::Fifo,
+        });
+    }
+    if let Some(prefix_length) = tuning.prefix_length {
+        opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(prefix_length as usize));
+    }
+    opts.set_block_based_table_factory(&block_opts);
+    opts
+}
+
+fn compression_type(compression: Compression) -> DBCompressionType {
+    match compression {
+        Compression::None => DBCompressionType::None,
+        Compression::Snappy => DBCompressionType::Snappy,
+        Compression::Zlib => DBCompressionType::Zlib,
+        Compression::Bz2 => DBCompressionType::Bz2,
+        Compression::Lz4 => DBCompressionType::Lz4,
+        Compression::Lz4hc => DBCompressionType::Lz4hc,
+        Compression::Zstd => DBCompressionType::Zstd,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_over_database_tuning() {
+        let database = from_json(serde_json::json!({
+            "compression": "LZ4",
+            "compressionPerLevel": ["NONE", "LZ4"],
+            "blockSize": 4096,
+        }))
+        .unwrap();
+        let collection = from_json(serde_json::json!({
+            "compression": "ZSTD",
+            "bloomFilterBits": 10,
+        }))
+        .unwrap();
+        let tuning = merge(&database, &collection);
+        assert_eq!(tuning.compression(), Compression::Zstd);
+        assert_eq!(tuning.compression_per_level().collect::<Vec<_>>(), [Compression::None, Compression::Lz4]);
+        assert_eq!(tuning.bloom_filter_bits, Some(10.0));
+        assert_eq!(tuning.block_size, Some(4096));
+        assert_eq!(tuning.write_buffer_size, None);
+    }
+}
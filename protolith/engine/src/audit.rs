@@ -0,0 +1,78 @@
+//! What the audit log records about the request being served.
+//!
+//! The tracing layer runs each request within a `Scope`, the services it
+//! reaches fill in the user and the target of the request, which the audit
+//! layer and the access log record once the request completes.
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use tonic::Code;
+
+tokio::task_local! {
+    static SCOPE: Scope;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Target {
+    pub username: String,
+    pub database: String,
+    pub collection: String,
+    pub key: String,
+    /// The outcome of requests not answered with a gRPC status, such as
+    /// those of the HTTP gateway.
+    pub outcome: Option<(Code, String)>,
+}
+
+/// The target of an audited request, shared by the audit layer and the
+/// services.
+#[derive(Debug, Clone, Default)]
+pub struct Scope(Arc<Mutex<Target>>);
+
+impl Scope {
+    /// Returns the scope of the current request, if any.
+    pub fn current() -> Option<Self> {
+        SCOPE.try_with(Clone::clone).ok()
+    }
+
+    /// Runs `f` within this scope.
+    pub fn run<F: Future>(&self, f: F) -> impl Future<Output = F::Output> {
+        SCOPE.scope(self.clone(), f)
+    }
+
+    pub fn target(&self) -> Target {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+fn with_target(f: impl FnOnce(&mut Target)) {
+    let _ = SCOPE.try_with(|scope| f(&mut scope.0.lock().unwrap()));
+}
+
+/// Records the user of the current request.
+pub fn record_user(username: &str) {
+    with_target(|target| target.username = username.to_owned());
+}
+
+/// Records the database and collection of the current request, the first
+/// recorded collection is kept when a request touches several.
+pub fn record_collection(database: &str, collection: &str) {
+    with_target(|target| {
+        if target.collection.is_empty() {
+            target.database = database.to_owned();
+            target.collection = collection.to_owned();
+        }
+    });
+}
+
+/// Records the document key of the current request, or the user or API key
+/// an admin request acts on.
+pub fn record_key(key: &str) {
+    with_target(|target| target.key = key.to_owned());
+}
+
+/// Records the outcome of the current request.
+pub fn record_outcome(code: Code, message: &str) {
+    with_target(|target| target.outcome = Some((code, message.to_owned())));
+}
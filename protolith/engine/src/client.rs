@@ -2,18 +2,25 @@ use std::{fmt::Debug, future::Future, marker::PhantomData};
 
 use protolith_core::{
     api::{
+        pbjson_types::Value,
         prost::{Message, Name},
         prost_wkt_types::{Any, MessageSerde},
-        protolith::services::v1::{
-            engine_service_client::EngineServiceClient, GetRequest, InsertRequest,
-            InsertResponse, ListRequest,
+        protolith::{
+            services::v1::{
+                engine_service_client::EngineServiceClient, ExportRequest, GetRequest,
+                ImportRequest, ImportResponse, InsertRequest, InsertResponse, ListRequest,
+            },
+            types::v1::{DocumentEncoding, ExportFormat},
         },
         service::MetadataSvc,
     },
     error::Error,
     Key,
 };
-use tonic::{transport::Channel, IntoRequest};
+use tonic::{codegen::http::HeaderValue, transport::Channel, IntoRequest};
+
+/// The maximal size of a single chunk streamed by [`Client::import`].
+const IMPORT_CHUNK_SIZE: usize = 1024 * 1024;
 
 #[derive(Debug, Clone)]
 pub struct Client {
@@ -38,7 +45,46 @@ impl Client {
         }
     }
 
+    /// Creates a client authenticating with an API key rather than a session.
+    pub fn with_api_key(channel: Channel, database: String, api_key: &str) -> Result<Self, Error> {
+        const VERSION: Option<&str> = option_env!("CARGO_PKG_VERSION");
+
+        let api_key: HeaderValue = api_key.parse()?;
+        let channel = tower::ServiceBuilder::new()
+            .layer_fn(|service| MetadataSvc::new(service, VERSION.unwrap().to_owned()).with_api_key(api_key.clone()))
+            .service(channel);
+        let engine_client = EngineServiceClient::new(channel);
+        Ok(Self {
+            engine_client,
+            database,
+            session: String::new(),
+        })
+    }
+
     pub async fn list<C>(&mut self) -> Result<Vec<Response<C::Message>>, Error>
+    where
+        C: Collection,
+        C::Message: Name,
+    {
+        self.list_request::<C>(ListRequest::default()).await
+    }
+
+    /// Lists the documents whose `field`, which has a HASH index, equals
+    /// `value`.
+    pub async fn find<C>(&mut self, field: &str, value: Value) -> Result<Vec<Response<C::Message>>, Error>
+    where
+        C: Collection,
+        C::Message: Name,
+    {
+        self.list_request::<C>(ListRequest {
+            index_field: field.to_owned(),
+            index_value: Some(value),
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn list_request<C>(&mut self, request: ListRequest) -> Result<Vec<Response<C::Message>>, Error>
     where
         C: Collection,
         C::Message: Name,
@@ -47,6 +93,7 @@ impl Client {
         let mut request = ListRequest {
             database: self.database.clone(),
             collection: collection.clone(),
+            ..request
         }
         .into_request();
         request
@@ -78,6 +125,7 @@ impl Client {
             database: self.database.clone(),
             collection: collection,
             key: Some(key.as_value()),
+            ..Default::default()
         }
         .into_request();
 
@@ -110,6 +158,27 @@ impl Client {
         let mut request = InsertRequest {
             database: self.database.clone(),
             data: Some(any),
+            ..Default::default()
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let rep = self.engine_client.insert(request).await?;
+        Ok(rep.into_inner())
+    }
+
+    /// Inserts a document given in the protobuf JSON mapping of `collection`.
+    pub async fn insert_json(
+        &mut self,
+        collection: &str,
+        document: &str,
+    ) -> Result<InsertResponse, Error> {
+        let mut request = InsertRequest {
+            database: self.database.clone(),
+            collection: collection.to_owned(),
+            json_data: document.to_owned(),
+            ..Default::default()
         }
         .into_request();
         request
@@ -118,6 +187,82 @@ impl Client {
         let rep = self.engine_client.insert(request).await?;
         Ok(rep.into_inner())
     }
+
+    /// Gets a document of `collection` in its protobuf JSON mapping.
+    pub async fn get_json<K>(&mut self, collection: &str, key: &Key<K>) -> Result<String, Error>
+    where
+        K: serde::Serialize + 'static,
+    {
+        let mut request = GetRequest {
+            database: self.database.clone(),
+            collection: collection.to_owned(),
+            key: Some(key.as_value()),
+            encoding: DocumentEncoding::Json.into(),
+            ..Default::default()
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let rep = self.engine_client.get(request).await?;
+        Ok(rep.into_inner().json_data)
+    }
+
+    /// Exports `collection`, or the whole database when `None`, encoded as `format`.
+    pub async fn export(
+        &mut self,
+        collection: Option<&str>,
+        format: ExportFormat,
+    ) -> Result<Vec<u8>, Error> {
+        let mut request = ExportRequest {
+            database: self.database.clone(),
+            collection: collection.unwrap_or_default().to_owned(),
+            format: format.into(),
+            omit_encrypted_fields: false,
+        }
+        .into_request();
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let mut stream = self.engine_client.export(request).await?.into_inner();
+        let mut data = Vec::new();
+        while let Some(rep) = stream.message().await? {
+            data.extend(rep.chunk);
+        }
+        Ok(data)
+    }
+
+    /// Imports documents encoded as `format`, as produced by [`Client::export`].
+    ///
+    /// `collection` must be set when importing raw `ExportFormat::Delimited`
+    /// messages of a single collection.
+    pub async fn import(
+        &mut self,
+        collection: Option<&str>,
+        format: ExportFormat,
+        data: Vec<u8>,
+    ) -> Result<ImportResponse, Error> {
+        let mut chunks = data
+            .chunks(IMPORT_CHUNK_SIZE)
+            .map(|chunk| ImportRequest {
+                chunk: chunk.to_vec(),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        if chunks.is_empty() {
+            chunks.push(ImportRequest::default());
+        }
+        chunks[0].database = self.database.clone();
+        chunks[0].collection = collection.unwrap_or_default().to_owned();
+        chunks[0].format = format.into();
+
+        let mut request = tonic::Request::new(tokio_stream::iter(chunks));
+        request
+            .metadata_mut()
+            .insert("protolith-session", self.session.parse().unwrap());
+        let rep = self.engine_client.import(request).await?;
+        Ok(rep.into_inner())
+    }
 }
 
 #[derive(Debug)]
@@ -0,0 +1,383 @@
+//! Typed gRPC services generated at runtime from the databases descriptors.
+//!
+//! Every collection `<Collection>` of a database `<db>` is served as:
+//!
+//! ```proto
+//! package protolith.dyn.<db>;
+//!
+//! service <Collection>Service {
+//!     rpc Get(google.protobuf.<Key>Value) returns (<Collection>);
+//!     rpc Insert(<Collection>) returns (google.protobuf.<Key>Value);
+//!     rpc List(google.protobuf.Empty) returns (stream <Collection>);
+//! }
+//! ```
+//!
+//! where `google.protobuf.<Key>Value` is the wrapper of the collection key
+//! field type, so clients can call it with stubs generated from their own
+//! protobuf definitions.
+
+// The handlers fail with `tonic::Status` like the generated services do.
+#![allow(clippy::result_large_err)]
+
+use std::{convert::Infallible, task::{Context, Poll}};
+
+use prost_reflect::{Kind, MessageDescriptor};
+use protolith_core::api::{
+    prost::{bytes::Buf, Message},
+    prost_wkt_types::Any,
+    protolith::annotation::v1::IndexType,
+    DescriptorPool, DynamicMessage,
+};
+use protolith_core::db;
+use tonic::{
+    body::BoxBody,
+    codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder},
+    codegen::{http, BoxFuture, Service},
+    server::Grpc,
+    transport::Body,
+    Request, Response, Status,
+};
+use tracing::debug;
+
+use crate::{
+    audit,
+    rbac::{self, Permission, Principal},
+    service::status,
+    Engine, ProtolithDbEngine,
+};
+
+/// The path prefix of the dynamic services.
+pub const DYNAMIC_PREFIX: &str = "/protolith.dyn.";
+
+#[derive(Clone)]
+pub struct DynamicService {
+    engine: ProtolithDbEngine,
+}
+
+/// A resolved `<Collection>Service` of a database.
+#[derive(Clone)]
+struct Target {
+    database: String,
+    message: MessageDescriptor,
+    key_field: String,
+    key_wrapper: MessageDescriptor,
+}
+
+impl DynamicService {
+    pub fn new(engine: ProtolithDbEngine) -> Self {
+        Self { engine }
+    }
+
+    /// Resolves the `/protolith.dyn.<db>.<Collection>Service/<Method>` path.
+    async fn resolve(&self, path: &str) -> Result<(Target, String), Status> {
+        let unimplemented = || Status::unimplemented(format!("unknown service method {}", path));
+        let rest = path.strip_prefix(DYNAMIC_PREFIX).ok_or_else(unimplemented)?;
+        let (service, method) = rest.split_once('/').ok_or_else(unimplemented)?;
+        let (database, service) = service.rsplit_once('.').ok_or_else(unimplemented)?;
+        let collection = service.strip_suffix("Service").ok_or_else(unimplemented)?;
+
+        let pool = self.engine.descriptor_pool(database).await.ok_or_else(unimplemented)?;
+        let collection = self
+            .engine
+            .get_databse_collections(database)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .into_iter()
+            .find(|c| c.name == collection)
+            .ok_or_else(unimplemented)?;
+        let message = pool
+            .get_message_by_name(&collection.full_name)
+            .ok_or_else(unimplemented)?;
+        let key_field = collection
+            .indexes
+            .iter()
+            .find(|idx| idx.index_type() == IndexType::Key)
+            .map(|idx| idx.field_name.clone())
+            .ok_or_else(|| Status::failed_precondition(format!("collection {} has no key", collection.full_name)))?;
+        let key_kind = message
+            .get_field_by_name(&key_field)
+            .map(|field| field.kind())
+            .ok_or_else(|| Status::failed_precondition(format!("unknown key field {}", key_field)))?;
+        let key_wrapper = key_wrapper(&key_kind)?;
+        let target = Target {
+            database: database.to_string(),
+            message,
+            key_field,
+            key_wrapper,
+        };
+        Ok((target, method.to_string()))
+    }
+
+    async fn handle(self, req: http::Request<Body>) -> http::Response<BoxBody> {
+        let (target, method) = match self.resolve(req.uri().path()).await {
+            Ok(resolved) => resolved,
+            Err(status) => return status.to_http(),
+        };
+        debug!(db = ?target.database, collection = ?target.message.full_name(), method = ?method, "dynamic request");
+        let permission = if method == "Insert" { Permission::Write } else { Permission::Read };
+        let principal = req.extensions().get::<Principal>();
+        let decrypt = match rbac::authorize(&self.engine, principal, permission, &target.database, target.message.full_name()).await {
+            Ok(grants) => rbac::permits(&grants, Permission::Decrypt, &target.database, target.message.full_name()),
+            Err(status) => return status.to_http(),
+        };
+        let engine = self.engine;
+        let empty = DescriptorPool::global()
+            .get_message_by_name("google.protobuf.Empty")
+            .expect("well known types");
+        match method.as_str() {
+            "Get" => {
+                let codec = DynamicCodec(target.key_wrapper.clone());
+                let handler = tower::service_fn(move |req: Request<DynamicMessage>| {
+                    let (engine, target) = (engine.clone(), target.clone());
+                    async move {
+                        let key = key_string(&req.into_inner(), "value")?;
+                        let collection = target.message.full_name().to_string();
+                        let key = format!("{}:{}", collection, key);
+                        audit::record_key(&key);
+                        let any = engine
+                            .get(target.database, collection, key.as_bytes(), decrypt)
+                            .await
+                            .map_err(status)?;
+                        let message = DynamicMessage::decode(target.message, any.value.as_slice())
+                            .map_err(|e| Status::internal(e.to_string()))?;
+                        Ok(Response::new(message))
+                    }
+                });
+                Grpc::new(codec).unary(handler, req).await
+            }
+            "Insert" => {
+                let codec = DynamicCodec(target.message.clone());
+                let handler = tower::service_fn(move |req: Request<DynamicMessage>| {
+                    let (engine, target) = (engine.clone(), target.clone());
+                    async move {
+                        let message = req.into_inner();
+                        let key = message.get_field_by_name(&target.key_field).map(|v| v.into_owned());
+                        let any = Any {
+                            type_url: format!("type.googleapis.com/{}", target.message.full_name()),
+                            value: message.encode_to_vec(),
+                        };
+                        engine.insert(target.database, any).await.map_err(status)?;
+                        let mut rep = DynamicMessage::new(target.key_wrapper);
+                        if let Some(key) = key {
+                            rep.set_field_by_name("value", key);
+                        }
+                        Ok(Response::new(rep))
+                    }
+                });
+                Grpc::new(codec).unary(handler, req).await
+            }
+            "List" => {
+                let codec = DynamicCodec(empty);
+                let handler = tower::service_fn(move |_req: Request<DynamicMessage>| {
+                    let (engine, target) = (engine.clone(), target.clone());
+                    async move {
+                        let documents = engine
+                            .list(target.database, target.message.full_name().to_string(), decrypt)
+                            .await
+                            .map_err(status)?;
+                        let messages = documents.into_iter().map(move |any| {
+                            DynamicMessage::decode(target.message.clone(), any.value.as_slice())
+                                .map_err(|e| Status::internal(e.to_string()))
+                        });
+                        Ok(Response::new(tokio_stream::iter(messages)))
+                    }
+                });
+                Grpc::new(codec).server_streaming(handler, req).await
+            }
+            _ => Status::unimplemented(format!("unknown method {}", method)).to_http(),
+        }
+    }
+}
+
+impl Service<http::Request<Body>> for DynamicService {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<Body>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move { Ok(service.handle(req).await) })
+    }
+}
+
+/// Returns the `google.protobuf` wrapper message of a key of type `kind`.
+fn key_wrapper(kind: &Kind) -> Result<MessageDescriptor, Status> {
+    let name = match kind {
+        Kind::String => "google.protobuf.StringValue",
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => "google.protobuf.Int32Value",
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => "google.protobuf.Int64Value",
+        Kind::Uint32 | Kind::Fixed32 => "google.protobuf.UInt32Value",
+        Kind::Uint64 | Kind::Fixed64 => "google.protobuf.UInt64Value",
+        Kind::Float => "google.protobuf.FloatValue",
+        Kind::Double => "google.protobuf.DoubleValue",
+        Kind::Bool => "google.protobuf.BoolValue",
+        kind => return Err(Status::failed_precondition(format!("unsupported key type {:?}", kind))),
+    };
+    Ok(DescriptorPool::global()
+        .get_message_by_name(name)
+        .expect("well known types"))
+}
+
+/// Formats the `field` of `message` the way document keys are stored.
+fn key_string(message: &DynamicMessage, field: &str) -> Result<String, Status> {
+    let value = message
+        .get_field_by_name(field)
+        .ok_or_else(|| Status::invalid_argument(format!("missing {}", field)))?;
+    db::key_string(&value).ok_or_else(|| Status::invalid_argument(format!("unsupported key {:?}", value)))
+}
+
+/// A `Codec` decoding requests as `DynamicMessage`s of the given descriptor.
+struct DynamicCodec(MessageDescriptor);
+
+impl Codec for DynamicCodec {
+    type Encode = DynamicMessage;
+    type Decode = DynamicMessage;
+    type Encoder = DynamicEncoder;
+    type Decoder = DynamicDecoder;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        DynamicEncoder
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        DynamicDecoder(self.0.clone())
+    }
+}
+
+struct DynamicEncoder;
+
+impl Encoder for DynamicEncoder {
+    type Item = DynamicMessage;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut EncodeBuf<'_>) -> Result<(), Self::Error> {
+        item.encode(dst).map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+struct DynamicDecoder(MessageDescriptor);
+
+impl Decoder for DynamicDecoder {
+    type Item = DynamicMessage;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Self::Item>, Self::Error> {
+        let message = DynamicMessage::decode(self.0.clone(), src.copy_to_bytes(src.remaining()))
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, future::poll_fn, pin::Pin, time::Duration};
+
+    use protolith_core::{
+        api::{
+            pbjson_types::Int64Value,
+            protolith::{
+                annotation::v1::Tuning,
+                test::v1::NumberedCollection,
+                types::v1::{Grant, Role},
+            },
+            FILE_DESCRIPTOR_SET,
+        },
+        memory::Memory,
+        meta_store, schema,
+    };
+    use tonic::{codegen::Body as _, Code};
+
+    use super::*;
+
+    const SERVICE: &str = "/protolith.dyn.numbered.NumberedCollectionService";
+
+    /// Calls `method` of `service` with `message` as an admin, returning the
+    /// encoded response message.
+    async fn call(service: &DynamicService, method: &str, message: impl Message) -> Result<Vec<u8>, Status> {
+        let mut frame = vec![0];
+        frame.extend((message.encoded_len() as u32).to_be_bytes());
+        message.encode(&mut frame).unwrap();
+        let mut req = http::Request::post(format!("{}/{}", SERVICE, method))
+            .header("content-type", "application/grpc")
+            .body(Body::from(frame))
+            .unwrap();
+        let admin = Grant {
+            role: Role::Admin.into(),
+            ..Default::default()
+        };
+        req.extensions_mut().insert(Principal::new("admin").with_grants(vec![admin]));
+
+        let rep = service.clone().handle(req).await;
+        let failed = |status: Option<Status>| status.filter(|status| status.code() != Code::Ok);
+        if let Some(status) = failed(Status::from_header_map(rep.headers())) {
+            return Err(status);
+        }
+        let mut body = rep.into_body();
+        let mut data = Vec::new();
+        while let Some(chunk) = poll_fn(|cx| Pin::new(&mut body).poll_data(cx)).await {
+            data.extend(chunk.unwrap());
+        }
+        let trailers = poll_fn(|cx| Pin::new(&mut body).poll_trailers(cx)).await.unwrap();
+        if let Some(status) = failed(trailers.as_ref().and_then(Status::from_header_map)) {
+            return Err(status);
+        }
+        Ok(data.get(5..).unwrap_or_default().to_vec())
+    }
+
+    #[tokio::test]
+    async fn inserts_documents_with_integer_keys() {
+        let path = std::env::temp_dir().join(format!("protolith-dynamic-{}", std::process::id()));
+        let config = db::Config {
+            db_path: path.clone(),
+            cache_size: 1024 * 1024,
+            write_buffer_budget: 0,
+            max_open_files: -1,
+            descriptor_file_name: "descriptor.bin".to_owned(),
+            encryption_key: None,
+            quota: db::Quota::default(),
+            slow_query_threshold: Duration::from_secs(60),
+            tuning: Tuning::default(),
+            database_tuning: HashMap::new(),
+        };
+        let meta_store = meta_store::Config {
+            schema_cf_name: "schema".to_owned(),
+            index_cf_name: "index".to_owned(),
+            schema_versions_cf_name: "schema_versions".to_owned(),
+            user_cf_name: "users".to_owned(),
+            session_cf_name: "sessions".to_owned(),
+            api_key_cf_name: "api_keys".to_owned(),
+            audit_cf_name: "audit".to_owned(),
+            default_db: "numbered".to_owned(),
+        };
+        let schema = schema::Config {
+            enable_versioning: false,
+            default_version: 1,
+        };
+        let memory = Memory::new(config.cache_size, config.write_buffer_budget);
+        let pool = DescriptorPool::decode(FILE_DESCRIPTOR_SET).unwrap();
+        let db = config.clone().build("numbered".to_owned(), meta_store.clone(), schema.clone(), pool, &memory).unwrap();
+        let dbs = HashMap::from([("numbered".to_owned(), db)]);
+        let service = DynamicService::new(ProtolithDbEngine::new(config, memory, meta_store, schema, dbs));
+
+        let document = NumberedCollection {
+            number: 42,
+            name: "forty-two".to_owned(),
+        };
+        let key = call(&service, "Insert", document.clone()).await.unwrap();
+        assert_eq!(Int64Value::decode(key.as_slice()).unwrap().value, 42);
+        // The key is taken once whatever its type.
+        let status = call(&service, "Insert", document.clone()).await.unwrap_err();
+        assert_eq!(status.code(), Code::AlreadyExists);
+        let got = call(&service, "Get", Int64Value { value: 42 }).await.unwrap();
+        assert_eq!(NumberedCollection::decode(got.as_slice()).unwrap(), document);
+        let status = call(&service, "Get", Int64Value { value: 7 }).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        drop(service);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
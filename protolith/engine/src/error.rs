@@ -20,8 +20,34 @@ pub enum OpError {
     CollectionNotFound(String, String),
     #[error("{0}")]
     KeyAlreadyExists(protolith_error),
+    #[error("{0}")]
+    KeyNotFound(protolith_error),
     #[error("collection {1} already exists on {0}")]
     CollectionAlreadyExists(String, String),
     #[error("user {0} not found")]
     UserNotFound(String),
+    #[error("user {0} already exists")]
+    UserAlreadyExists(String),
+    #[error("invalid password for user {0}")]
+    InvalidPassword(String),
+    #[error("user {0} is disabled")]
+    UserDisabled(String),
+    #[error("api key {0} not found")]
+    ApiKeyNotFound(String),
+    #[error("invalid data: {0}")]
+    InvalidData(String),
+    #[error("{0}")]
+    QuotaExceeded(String),
+    #[error("background work of database {0} is paused")]
+    BackgroundWorkPaused(String),
+    #[error("maintenance operation {0} not found")]
+    OperationNotFound(String),
+    #[error("{0}")]
+    PermissionDenied(String),
+    /// An import failed after writing `imported` documents, which stay.
+    #[error("{source}, after importing {imported} documents")]
+    ImportFailed {
+        imported: u64,
+        source: Box<EngineError>,
+    },
 }
\ No newline at end of file
@@ -1,14 +1,21 @@
 use std::fs;
-use std::{collections::HashMap, future::Future, sync::Arc};
+use std::{collections::HashMap, future::Future, sync::{Arc, RwLock}};
 pub mod service;
 pub mod client;
+pub mod dynamic;
+pub mod rbac;
+pub mod audit;
+pub mod limit;
+pub mod profile;
+pub mod maintenance;
 use protolith_core::api::DescriptorPool;
+use protolith_core::api::pbjson_types::Timestamp;
 use protolith_core::api::prost::bytes::Bytes;
 use protolith_core::api::prost_wkt_types::Any;
 use protolith_core::schema;
 use rocksdb::{Options, DB};
-use tokio::sync::Mutex;
-use tracing::{debug, error, info};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tracing::{debug, error, info, instrument, Span};
 mod error;
 pub use error::{EngineError, OpError};
 use protolith_core::api::protolith::core::v1::Collection;
@@ -17,11 +24,13 @@ use protolith_core::api::protolith::core::v1::Collection;
 use protolith_core::{
     api::protolith::{
             core::v1::Database,
-            services::v1::{CreateDatabaseResponse, ListDatabasesResponse, CreateCollectionResponse},
-            types::v1::{ApiOp, Op, OpStatus},
+            metastore::v1::{ApiKey, Session, User},
+            services::v1::{maintenance_operation::Kind, ColumnFamilyProperties, CreateDatabaseResponse, ListDatabasesResponse, CreateCollectionResponse, MaintenanceOperation},
+            types::v1::{ApiOp, AuditEvent, Op, OpStatus, ExportFormat, Grant},
         },
     db,
     error::{Error, Result},
+    memory::{self, Memory},
     meta_store,
 };
 
@@ -92,6 +101,39 @@ pub trait Login {
         username: String,
         passwrod: String,
     ) -> impl Future<Output = Result<String, EngineError>> + Send;
+    fn verify_password(
+        &self,
+        username: String,
+        password: String,
+    ) -> impl Future<Output = Result<bool, EngineError>> + Send;
+    fn get_session(
+        &self,
+        id: String,
+    ) -> impl Future<Output = Result<Option<Session>, EngineError>> + Send;
+    fn put_session(
+        &self,
+        session: Session,
+    ) -> impl Future<Output = Result<(), EngineError>> + Send;
+    /// Records an access to the session `id` at `at` unless it was deleted,
+    /// returns the updated session.
+    fn touch_session(
+        &self,
+        id: String,
+        at: Timestamp,
+    ) -> impl Future<Output = Result<Option<Session>, EngineError>> + Send;
+    fn delete_session(
+        &self,
+        id: String,
+    ) -> impl Future<Output = Result<bool, EngineError>> + Send;
+    fn list_sessions(&self) -> impl Future<Output = Result<Vec<Session>, EngineError>> + Send;
+    fn revoke_sessions(
+        &self,
+        username: String,
+    ) -> impl Future<Output = Result<usize, EngineError>> + Send;
+    fn verify_api_key(
+        &self,
+        key: String,
+    ) -> impl Future<Output = Result<Option<ApiKey>, EngineError>> + Send;
 }
 
 pub trait Admin {
@@ -114,7 +156,103 @@ pub trait Admin {
         &self,
         username: String,
         passwrod: String,
+        grants: Vec<Grant>,
+    ) -> impl Future<Output = Result<User, EngineError>> + Send;
+    fn get_user(
+        &self,
+        username: String,
+    ) -> impl Future<Output = Result<User, EngineError>> + Send;
+    fn list_users(&self) -> impl Future<Output = Result<Vec<User>, EngineError>> + Send;
+    fn delete_user(
+        &self,
+        username: String,
+    ) -> impl Future<Output = Result<User, EngineError>> + Send;
+    fn change_password(
+        &self,
+        username: String,
+        password: String,
+    ) -> impl Future<Output = Result<User, EngineError>> + Send;
+    fn set_user_disabled(
+        &self,
+        username: String,
+        disabled: bool,
+    ) -> impl Future<Output = Result<User, EngineError>> + Send;
+    fn grant_permission(
+        &self,
+        username: String,
+        grant: Grant,
+    ) -> impl Future<Output = Result<Vec<Grant>, EngineError>> + Send;
+    fn revoke_permission(
+        &self,
+        username: String,
+        grant: Grant,
+    ) -> impl Future<Output = Result<Vec<Grant>, EngineError>> + Send;
+    fn list_permissions(
+        &self,
+        username: String,
+    ) -> impl Future<Output = Result<Vec<Grant>, EngineError>> + Send;
+    fn create_api_key(
+        &self,
+        username: String,
+        name: String,
+        expires_at: Option<Timestamp>,
+        databases: Vec<String>,
+    ) -> impl Future<Output = Result<(ApiKey, String), EngineError>> + Send;
+    fn list_api_keys(
+        &self,
+        username: Option<String>,
+    ) -> impl Future<Output = Result<Vec<ApiKey>, EngineError>> + Send;
+    fn revoke_api_key(
+        &self,
+        id: String,
+    ) -> impl Future<Output = Result<ApiKey, EngineError>> + Send;
+    fn revoke_api_keys(
+        &self,
+        username: String,
+    ) -> impl Future<Output = Result<usize, EngineError>> + Send;
+    fn append_audit_event(
+        &self,
+        event: AuditEvent,
     ) -> impl Future<Output = Result<(), EngineError>> + Send;
+    fn list_audit_events(
+        &self,
+        since: Option<Timestamp>,
+        until: Option<Timestamp>,
+        username: Option<String>,
+        limit: usize,
+    ) -> impl Future<Output = Result<Vec<AuditEvent>, EngineError>> + Send;
+    /// Starts compacting `collection`, or the whole database, and returns
+    /// the pending operation.
+    fn compact(
+        &self,
+        database: String,
+        collection: Option<String>,
+    ) -> impl Future<Output = Result<MaintenanceOperation, EngineError>> + Send;
+    /// Starts flushing the memtables of `collection`, or of the whole
+    /// database, and returns the pending operation.
+    fn flush(
+        &self,
+        database: String,
+        collection: Option<String>,
+    ) -> impl Future<Output = Result<MaintenanceOperation, EngineError>> + Send;
+    fn get_maintenance_operation(
+        &self,
+        id: String,
+    ) -> impl Future<Output = Result<MaintenanceOperation, EngineError>> + Send;
+    fn list_maintenance_operations(
+        &self,
+    ) -> impl Future<Output = Result<Vec<MaintenanceOperation>, EngineError>> + Send;
+    fn pause_background_work(
+        &self,
+        database: String,
+        paused: bool,
+    ) -> impl Future<Output = Result<(), EngineError>> + Send;
+    fn get_properties(
+        &self,
+        database: String,
+        collection: Option<String>,
+        properties: Vec<String>,
+    ) -> impl Future<Output = Result<Vec<ColumnFamilyProperties>, EngineError>> + Send;
 }
 
 pub trait Engine: Login + Admin + Metadata + Sync + Send + 'static {
@@ -123,34 +261,94 @@ pub trait Engine: Login + Admin + Metadata + Sync + Send + 'static {
         database: String,
         message: Any,
     ) -> impl Future<Output = Result<String, EngineError>> + Send;
+    fn insert_json(
+        &self,
+        database: String,
+        collection: Option<String>,
+        document: serde_json::Value,
+    ) -> impl Future<Output = Result<String, EngineError>> + Send;
+    /// Reads return the encrypted fields of documents only when `decrypt`.
     fn get(
         &self,
         database: String,
         collection: String,
         key: &[u8],
+        decrypt: bool,
     ) -> impl Future<Output = Result<Any, EngineError>> + Send;
+    fn get_json(
+        &self,
+        database: String,
+        collection: String,
+        key: &[u8],
+        decrypt: bool,
+    ) -> impl Future<Output = Result<serde_json::Value, EngineError>> + Send;
     fn list(
         &self,
         database: String,
         collection: String,
+        decrypt: bool,
     ) -> impl Future<Output = Result<Vec<Any>, EngineError>> + Send;
+    /// Returns the documents of `collection` whose `field`, which has a HASH
+    /// index, equals `value`.
+    fn find(
+        &self,
+        database: String,
+        collection: String,
+        field: String,
+        value: serde_json::Value,
+        decrypt: bool,
+    ) -> impl Future<Output = Result<Vec<Any>, EngineError>> + Send;
+    fn export(
+        &self,
+        database: String,
+        collection: Option<String>,
+        format: ExportFormat,
+        decrypt: bool,
+        omit_encrypted: bool,
+    ) -> impl Future<Output = Result<Chunks, EngineError>> + Send;
+    /// Imports the documents of `chunks` as they are received, until the
+    /// channel is closed or a chunk is an error.
+    fn import(
+        &self,
+        database: String,
+        collection: Option<String>,
+        format: ExportFormat,
+        chunks: Chunks,
+        grants: Vec<Grant>,
+    ) -> impl Future<Output = Result<u64, EngineError>> + Send;
 }
 
 
 
 pub type DatabasesMap = HashMap<String, db::RocksDb>;
 
+/// The chunks of an export or an import, sent while they are read.
+pub type Chunks = mpsc::Receiver<Result<Vec<u8>, EngineError>>;
+
+/// How many chunks of an export may be read ahead of those sent.
+const EXPORT_CHUNKS: usize = 16;
+
+/// How often `destroy_db` checks whether a database is still in use.
+const RELEASE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// How long `destroy_db` waits for a database to be released before giving
+/// up, so that a long export does not hold the catalog.
+const RELEASE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
 #[derive(Clone)]
 pub struct ProtolithDbEngine {
     pub db_config: db::Config,
+    /// The block cache and memtable budget shared by every database.
+    pub memory: Memory,
     pub meta_store_config: meta_store::Config,
     pub schema_config: schema::Config,
-    inner: Arc<Mutex<Inner>>,
-}
-
-#[derive(Clone)]
-pub struct Inner {
-    dbs: DatabasesMap,
+    /// Only locked to look a database up or add one, never across RocksDB
+    /// calls, which run on the blocking threads of the runtime.
+    dbs: Arc<RwLock<HashMap<String, Arc<db::RocksDb>>>>,
+    /// Serializes the creation of databases and collections, which check
+    /// that they do not exist before creating them.
+    catalog: Arc<Mutex<()>>,
+    operations: Arc<maintenance::Operations>,
 }
 
 impl Metadata for ProtolithDbEngine {
@@ -166,82 +364,326 @@ impl Login for ProtolithDbEngine {
             username: String,
             password: String,
     ) -> Result<String, EngineError> {
-        let inner = self.inner.lock().await;
-        let default_db = inner.dbs.get(&self.meta_store_config.default_db).unwrap();
-        if let Some(session) = default_db.login_user(username.clone(), password) {
-            Ok(session)
-        } else {
-            Err(EngineError::OpError(OpError::UserNotFound(username)))
-        }
+        let default_db = self.default_db();
+        blocking(move || default_db.login_user(username, password).map_err(user_error)).await
+    }
+
+    async fn verify_password(&self, username: String, password: String) -> Result<bool, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || default_db.verify_password(&username, &password).map_err(user_error)).await
+    }
+
+    async fn get_session(&self, id: String) -> Result<Option<Session>, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || default_db.get_session(&id).map_err(EngineError::Internal)).await
+    }
+
+    async fn put_session(&self, session: Session) -> Result<(), EngineError> {
+        let default_db = self.default_db();
+        blocking(move || default_db.put_session(&session).map_err(EngineError::Internal)).await
+    }
+
+    async fn touch_session(&self, id: String, at: Timestamp) -> Result<Option<Session>, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || default_db.touch_session(&id, at).map_err(EngineError::Internal)).await
+    }
+
+    async fn delete_session(&self, id: String) -> Result<bool, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || default_db.delete_session(&id).map_err(EngineError::Internal)).await
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<Session>, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || default_db.list_sessions().map_err(EngineError::Internal)).await
+    }
+
+    async fn revoke_sessions(&self, username: String) -> Result<usize, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || {
+            let revoked = default_db.delete_user_sessions(&username).map_err(EngineError::Internal)?;
+            info!(username = ?username, revoked = ?revoked, "revoked sessions");
+            Ok(revoked)
+        }).await
+    }
+
+    async fn verify_api_key(&self, key: String) -> Result<Option<ApiKey>, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || default_db.verify_api_key(&key).map_err(EngineError::Internal)).await
     }
 }
 
 impl Admin for ProtolithDbEngine {
-    async fn create_user(&self, username: String, password: String) -> Result<(), EngineError> {
-        let inner = self.inner.lock().await;
-        let default_db = inner.dbs.get(&self.meta_store_config.default_db).unwrap();
-        default_db
-            .create_user(username.clone(), password.clone())
-            .map_err(|e| EngineError::Internal(e))?;
-        info!(username = ?username, "created new");
-        Ok(())
+    async fn create_user(&self, username: String, password: String, grants: Vec<Grant>) -> Result<User, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || {
+            let user = default_db
+                .create_user(username.clone(), password, grants)
+                .map_err(user_error)?;
+            info!(username = ?username, "created new");
+            Ok(user)
+        }).await
+    }
+
+    async fn get_user(&self, username: String) -> Result<User, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || {
+            default_db.get_user(&username)
+                .map_err(EngineError::Internal)?
+                .ok_or_else(|| EngineError::OpError(OpError::UserNotFound(username)))
+        }).await
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || default_db.list_users().map_err(EngineError::Internal)).await
+    }
+
+    async fn delete_user(&self, username: String) -> Result<User, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || {
+            let user = default_db.delete_user(&username).map_err(user_error)?;
+            info!(username = ?username, "deleted user");
+            Ok(user)
+        }).await
+    }
+
+    async fn change_password(&self, username: String, password: String) -> Result<User, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || {
+            let user = default_db.change_password(&username, password).map_err(user_error)?;
+            info!(username = ?username, "changed password");
+            Ok(user)
+        }).await
+    }
+
+    async fn set_user_disabled(&self, username: String, disabled: bool) -> Result<User, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || {
+            let mut user = default_db.get_user(&username)
+                .map_err(EngineError::Internal)?
+                .ok_or_else(|| EngineError::OpError(OpError::UserNotFound(username.clone())))?;
+            user.disabled = disabled;
+            default_db.update_user(&user).map_err(EngineError::Internal)?;
+            info!(username = ?username, disabled = ?disabled, "updated user");
+            Ok(user)
+        }).await
+    }
+
+    async fn grant_permission(&self, username: String, grant: Grant) -> Result<Vec<Grant>, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || {
+            let mut user = default_db.get_user(&username)
+                .map_err(EngineError::Internal)?
+                .ok_or_else(|| EngineError::OpError(OpError::UserNotFound(username.clone())))?;
+            if !user.grants.contains(&grant) {
+                user.grants.push(grant);
+                default_db.update_user(&user).map_err(EngineError::Internal)?;
+            }
+            info!(username = ?username, grants = ?user.grants, "granted permission");
+            Ok(user.grants)
+        }).await
+    }
+
+    async fn revoke_permission(&self, username: String, grant: Grant) -> Result<Vec<Grant>, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || {
+            let mut user = default_db.get_user(&username)
+                .map_err(EngineError::Internal)?
+                .ok_or_else(|| EngineError::OpError(OpError::UserNotFound(username.clone())))?;
+            user.grants.retain(|g| g != &grant);
+            default_db.update_user(&user).map_err(EngineError::Internal)?;
+            info!(username = ?username, grants = ?user.grants, "revoked permission");
+            Ok(user.grants)
+        }).await
+    }
+
+    async fn list_permissions(&self, username: String) -> Result<Vec<Grant>, EngineError> {
+        Ok(self.get_user(username).await?.grants)
+    }
+
+    async fn create_api_key(
+        &self,
+        username: String,
+        name: String,
+        expires_at: Option<Timestamp>,
+        databases: Vec<String>,
+    ) -> Result<(ApiKey, String), EngineError> {
+        let default_db = self.default_db();
+        blocking(move || {
+            let (api_key, key) = default_db
+                .create_api_key(&username, name, expires_at, databases)
+                .map_err(user_error)?;
+            info!(username = ?username, id = ?api_key.id, databases = ?api_key.databases, "created api key");
+            Ok((api_key, key))
+        }).await
     }
 
+    async fn list_api_keys(&self, username: Option<String>) -> Result<Vec<ApiKey>, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || default_db.list_api_keys(username.as_deref()).map_err(EngineError::Internal)).await
+    }
+
+    async fn revoke_api_key(&self, id: String) -> Result<ApiKey, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || {
+            let api_key = default_db
+                .delete_api_key(&id)
+                .map_err(EngineError::Internal)?
+                .ok_or_else(|| EngineError::OpError(OpError::ApiKeyNotFound(id)))?;
+            info!(username = ?api_key.username, id = ?api_key.id, "revoked api key");
+            Ok(api_key)
+        }).await
+    }
+
+    async fn revoke_api_keys(&self, username: String) -> Result<usize, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || {
+            let revoked = default_db.delete_user_api_keys(&username).map_err(EngineError::Internal)?;
+            info!(username = ?username, revoked = ?revoked, "revoked api keys");
+            Ok(revoked)
+        }).await
+    }
+
+    async fn append_audit_event(&self, event: AuditEvent) -> Result<(), EngineError> {
+        let default_db = self.default_db();
+        blocking(move || default_db.append_audit_event(&event).map_err(EngineError::Internal)).await
+    }
+
+    async fn list_audit_events(
+        &self,
+        since: Option<Timestamp>,
+        until: Option<Timestamp>,
+        username: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<AuditEvent>, EngineError> {
+        let default_db = self.default_db();
+        blocking(move || {
+            default_db
+                .list_audit_events(since.as_ref(), until.as_ref(), username.as_deref(), limit)
+                .map_err(EngineError::Internal)
+        }).await
+    }
+
+    #[instrument(name = "engine_compact", skip_all, fields(database = %database, collection = ?collection))]
+    async fn compact(&self, database: String, collection: Option<String>) -> Result<MaintenanceOperation, EngineError> {
+        let db = self.maintained_db(&database, collection.clone()).await?;
+        if db.background_work_paused() {
+            return Err(EngineError::OpError(OpError::BackgroundWorkPaused(database)));
+        }
+        Ok(self.operations.start(Kind::Compact, db, collection))
+    }
+
+    #[instrument(name = "engine_flush", skip_all, fields(database = %database, collection = ?collection))]
+    async fn flush(&self, database: String, collection: Option<String>) -> Result<MaintenanceOperation, EngineError> {
+        let db = self.maintained_db(&database, collection.clone()).await?;
+        Ok(self.operations.start(Kind::Flush, db, collection))
+    }
+
+    async fn get_maintenance_operation(&self, id: String) -> Result<MaintenanceOperation, EngineError> {
+        self.operations
+            .get(&id)
+            .ok_or_else(|| EngineError::OpError(OpError::OperationNotFound(id)))
+    }
+
+    async fn list_maintenance_operations(&self) -> Result<Vec<MaintenanceOperation>, EngineError> {
+        Ok(self.operations.list())
+    }
+
+    #[instrument(name = "engine_pause_background_work", skip_all, fields(database = %database, paused = paused))]
+    async fn pause_background_work(&self, database: String, paused: bool) -> Result<(), EngineError> {
+        let db = self.maintained_db(&database, None).await?;
+        blocking(move || db.pause_background_work(paused).map_err(EngineError::Internal)).await
+    }
+
+    #[instrument(name = "engine_get_properties", skip_all, fields(database = %database, collection = ?collection))]
+    async fn get_properties(
+        &self,
+        database: String,
+        collection: Option<String>,
+        properties: Vec<String>,
+    ) -> Result<Vec<ColumnFamilyProperties>, EngineError> {
+        let db = self.maintained_db(&database, collection.clone()).await?;
+        let values = blocking(move || {
+            db.properties(collection.as_deref(), &properties).map_err(EngineError::Internal)
+        }).await?;
+        Ok(values
+            .into_iter()
+            .map(|(column_family, properties)| ColumnFamilyProperties { column_family, properties })
+            .collect())
+    }
+
+    #[instrument(name = "engine_create_database", skip_all, fields(database = %name))]
     async fn create_database(&self, name: String, fd_descriptor: Vec<u8>) -> Result<CreateDatabaseResponse, EngineError> {
-        let mut inner = self.inner.lock().await;
-        if inner.dbs.contains_key(&name) {
+        let _catalog = self.catalog.lock().await;
+        if self.dbs.read().unwrap().contains_key(&name) {
             error!("database {name} already exists");
-            Err(EngineError::OpError(OpError::DatabaseAlreadyExists(name)))
-        } else {
+            return Err(EngineError::OpError(OpError::DatabaseAlreadyExists(name)));
+        }
+        let engine = self.clone();
+        let db_name = name.clone();
+        let db = blocking(move || {
             let buf = Bytes::from(fd_descriptor.clone());
-            let pool = DescriptorPool::decode(buf).unwrap();
-            let db: db::RocksDb = self
+            let pool = DescriptorPool::decode(buf)
+                .map_err(|e| EngineError::OpError(OpError::InvalidData(format!("invalid file descriptor set: {}", e))))?;
+            let db: db::RocksDb = engine
                 .db_config
                 .clone()
-                .build(name.clone(), self.meta_store_config.clone(), self.schema_config.clone(), pool)
-                .map_err(|e| EngineError::Internal(e))?;
-            inner.dbs.insert(name.clone(), db);
-            
-            let descriptor_path = self.db_config.db_path.join(name.clone()).join(self.db_config.descriptor_file_name.clone());
-            fs::write(descriptor_path, fd_descriptor).unwrap();
-            Ok(CreateDatabaseResponse {
-                name: name.clone(),
-                op: Some(ApiOp {
-                    r#type: Op::Create.into(),
-                    description: format!("created database {}", name),
-                    status: OpStatus::Success.into(),
-
-                })
+                .build(db_name.clone(), engine.meta_store_config.clone(), engine.schema_config.clone(), pool, &engine.memory)
+                .map_err(|err| match err.downcast::<db::CoreError>() {
+                    Ok(err) => core_error(*err),
+                    Err(err) => EngineError::Internal(err),
+                })?;
+            let descriptor_path = engine.db_config.db_path.join(db_name).join(engine.db_config.descriptor_file_name.clone());
+            fs::write(descriptor_path, fd_descriptor).map_err(|e| EngineError::Internal(e.into()))?;
+            Ok(db)
+        }).await?;
+        self.dbs.write().unwrap().insert(name.clone(), Arc::new(db));
+        Ok(CreateDatabaseResponse {
+            name: name.clone(),
+            op: Some(ApiOp {
+                r#type: Op::Create.into(),
+                description: format!("created database {}", name),
+                status: OpStatus::Success.into(),
+                ..Default::default()
             })
-        }
+        })
     }
 
+    #[instrument(name = "engine_list_databases", skip_all)]
     async fn list_databases(
         &self,
     ) -> Result<ListDatabasesResponse, EngineError> {
-        let inner = self.inner.lock().await;
-        let mut dbs = Vec::with_capacity(inner.dbs.len());
-        for (name, db) in &inner.dbs {
-            let collections = db
-                .get_collections()
-                .map_err(|e| EngineError::Internal(e))?;
-            dbs.push(Database {
-                name: name.to_string(),
-                collections: collections,
-                path: db.path.to_string_lossy().to_string()
+        let dbs = self.databases();
+        blocking(move || {
+            let mut databases = Vec::with_capacity(dbs.len());
+            for (name, db) in dbs {
+                let collections = db
+                    .get_collections()
+                    .map_err(EngineError::Internal)?;
+                databases.push(Database {
+                    name,
+                    collections,
+                    path: db.path.to_string_lossy().to_string()
+                })
+            }
+            Ok(ListDatabasesResponse {
+                databases
             })
-        }
-        Ok(ListDatabasesResponse {
-            databases: dbs
-        })
+        }).await
     }
 
+    #[instrument(name = "engine_create_collection", skip_all, fields(database = %database, collection = %collection))]
     async fn create_collection(&self, database: String, collection: String, key: String, version: u64) -> Result<CreateCollectionResponse, EngineError> {
-        let mut inner = self.inner.lock().await;
-        let db = inner.dbs.get_mut(&database).unwrap();
-        match db.get_collection(collection.clone()) {
+        let _catalog = self.catalog.lock().await;
+        let db = self.database(&database)?;
+        blocking(move || match db.get_collection(collection.clone()) {
             Err(_) => {
-                let schema = db.create_schema(collection.clone(), key, version).unwrap();
+                let schema = db.create_schema(collection.clone(), key, version)
+                    .map_err(|err| match err.downcast::<db::CoreError>() {
+                        Ok(err) => core_error(*err),
+                        Err(err) => EngineError::Internal(err),
+                    })?;
                 info!(schema = ?schema, "created new collection");
                 Ok(CreateCollectionResponse {
                     database: database.clone(),
@@ -250,96 +692,357 @@ impl Admin for ProtolithDbEngine {
                         description: format!("created collection {} on database {}", database, collection),
                         r#type: Op::Create.into(),
                         status: OpStatus::Success.into(),
+                        ..Default::default()
                     })
                 })
             },
             Ok(_) => {
                 Err(EngineError::OpError(OpError::CollectionAlreadyExists(database, collection)))
             }
-        }
+        }).await
     }
 }
 
 impl Engine for ProtolithDbEngine {
     
+    #[instrument(name = "engine_list", skip_all, fields(database = %database, collection = %collection))]
     async fn list(
         &self,
         database: String,
-        collection: String
+        collection: String,
+        decrypt: bool,
     ) -> Result<Vec<Any>, EngineError> {
-        
-        let inner = self.inner.lock().await;
-        let db = inner.dbs.get(&database);
-        match db {
-            None => Err(EngineError::OpError(OpError::DatabaseNotFound(database))),
-            Some(db) => { 
-                let rep = db.list(collection.clone())
-                    .map_err(|_e| EngineError::OpError(OpError::CollectionNotFound(database ,collection)));
-                rep
-            }
-        }
+        let db = self.database(&database)?;
+        let mut profile = profile::start();
+        let (rep, profile) = blocking(move || {
+            let rep = db.list(collection.clone(), decrypt, &mut profile)
+                .map_err(|_e| EngineError::OpError(OpError::CollectionNotFound(collection, database)));
+            Ok((rep, profile))
+        }).await?;
+        profile::record(profile);
+        rep
     }
 
+    #[instrument(name = "engine_find", skip_all, fields(database = %database, collection = %collection, field = %field))]
+    async fn find(
+        &self,
+        database: String,
+        collection: String,
+        field: String,
+        value: serde_json::Value,
+        decrypt: bool,
+    ) -> Result<Vec<Any>, EngineError> {
+        let db = self.database(&database)?;
+        let mut profile = profile::start();
+        let (rep, profile) = blocking(move || {
+            let rep = db.find(collection.clone(), &field, value, decrypt, &mut profile).map_err(|err| match err.downcast::<db::CoreError>() {
+                Ok(err) => match *err {
+                    db::CoreError::SchemaNotExists(_) => EngineError::OpError(OpError::CollectionNotFound(collection, database)),
+                    err => core_error(err),
+                },
+                Err(err) => EngineError::Internal(err),
+            });
+            Ok((rep, profile))
+        }).await?;
+        profile::record(profile);
+        rep
+    }
+
+    #[instrument(name = "engine_insert", skip_all, fields(database = %database))]
     async fn insert(
             &self,
             database: String,
             message: Any,
     ) -> Result<String, EngineError> {
-        let inner = self.inner.lock().await;
-        let db = inner.dbs.get(&database);
-        match db {
-            None => Err(EngineError::OpError(OpError::DatabaseNotFound(database))),
-            Some(db) => { 
-                let rep = db.insert(message).map_err(|err| EngineError::OpError(OpError::KeyAlreadyExists(err.into())));
-                rep
-            }
-        }
+        let db = self.database(&database)?;
+        let mut profile = profile::start();
+        let (rep, profile) = blocking(move || {
+            let rep = db.insert(message, &mut profile).map_err(core_error);
+            Ok((rep, profile))
+        }).await?;
+        profile::record(profile);
+        rep
+    }
+
+    #[instrument(name = "engine_insert", skip_all, fields(database = %database, collection = ?collection))]
+    async fn insert_json(
+        &self,
+        database: String,
+        collection: Option<String>,
+        document: serde_json::Value,
+    ) -> Result<String, EngineError> {
+        let db = self.database(&database)?;
+        let mut profile = profile::start();
+        let (rep, profile) = blocking(move || {
+            let rep = db.insert_json(collection.as_deref(), document, &mut profile).map_err(core_error);
+            Ok((rep, profile))
+        }).await?;
+        profile::record(profile);
+        rep
     }
 
+    #[instrument(name = "engine_get", skip_all, fields(database = %database, collection = %collection))]
     async fn get(
         &self,
         database: String,
         collection: String,
         key: &[u8],
+        decrypt: bool,
     ) -> Result<Any, EngineError> {
-        let inner = self.inner.lock().await;
-        if let Some(db) = inner.dbs.get(&database) {
-            let _ = db.get_schema(collection.clone())
+        let db = self.database(&database)?;
+        let key = key.to_vec();
+        let mut profile = profile::start();
+        let (rep, profile) = blocking(move || {
+            db.get_schema(collection.clone())
                 .map_err(|_e| EngineError::OpError(OpError::CollectionNotFound(collection.clone(), database)))?;
-            let value = db.get(collection.clone(), key).unwrap();
-            Ok(value)
+            let rep = db.get(collection, &key, decrypt, &mut profile).map_err(|err| match err.downcast::<db::CoreError>() {
+                Ok(err) => core_error(*err),
+                Err(err) => EngineError::Internal(err),
+            });
+            Ok((rep, profile))
+        }).await?;
+        profile::record(profile);
+        rep
+    }
 
-        } else {
-            return Err(EngineError::OpError(OpError::DatabaseNotFound(database)))
+    #[instrument(name = "engine_get", skip_all, fields(database = %database, collection = %collection))]
+    async fn get_json(
+        &self,
+        database: String,
+        collection: String,
+        key: &[u8],
+        decrypt: bool,
+    ) -> Result<serde_json::Value, EngineError> {
+        let db = self.database(&database)?;
+        let key = key.to_vec();
+        let mut profile = profile::start();
+        let (rep, profile) = blocking(move || {
+            db.get_schema(collection.clone())
+                .map_err(|_e| EngineError::OpError(OpError::CollectionNotFound(collection.clone(), database)))?;
+            let rep = db.get_json(collection, &key, decrypt, &mut profile).map_err(|err| match err.downcast::<db::CoreError>() {
+                Ok(err) => core_error(*err),
+                Err(err) => EngineError::Internal(err),
+            });
+            Ok((rep, profile))
+        }).await?;
+        profile::record(profile);
+        rep
+    }
+
+    #[instrument(name = "engine_export", skip_all, fields(database = %database, collection = ?collection))]
+    async fn export(
+        &self,
+        database: String,
+        collection: Option<String>,
+        format: ExportFormat,
+        decrypt: bool,
+        omit_encrypted: bool,
+    ) -> Result<Chunks, EngineError> {
+        let db = self.database(&database)?;
+        let (started, start) = oneshot::channel();
+        let (tx, chunks) = mpsc::channel(EXPORT_CHUNKS);
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            if let Some(collection) = &collection {
+                if db.get_schema(collection.clone()).is_err() {
+                    let _ = started.send(Err(EngineError::OpError(OpError::CollectionNotFound(collection.clone(), database))));
+                    return;
+                }
+            }
+            let export = match db.export(collection, format, decrypt, omit_encrypted) {
+                Ok(export) => export,
+                Err(e) => {
+                    let _ = started.send(Err(user_error(e)));
+                    return;
+                }
+            };
+            if started.send(Ok(())).is_err() {
+                return;
+            }
+            for chunk in export {
+                let failed = chunk.is_err();
+                if tx.blocking_send(chunk.map_err(user_error)).is_err() {
+                    debug!("export cancelled");
+                    return;
+                }
+                if failed {
+                    return;
+                }
+            }
+        });
+        start.await.map_err(|e| EngineError::Internal(e.into()))??;
+        Ok(chunks)
+    }
+    async fn import(
+        &self,
+        database: String,
+        collection: Option<String>,
+        format: ExportFormat,
+        mut chunks: Chunks,
+        grants: Vec<Grant>,
+    ) -> Result<u64, EngineError> {
+        // Archives carry their own descriptors, so they can be restored
+        // into a database which does not exist yet by its admins.
+        let mut received = Vec::new();
+        let exists = self.dbs.read().unwrap().contains_key(&database);
+        if !exists && format == ExportFormat::Archive {
+            if !rbac::permits(&grants, rbac::Permission::Admin, &database, "") {
+                return Err(EngineError::OpError(OpError::PermissionDenied(format!(
+                    "missing Admin permission to create database {}", database
+                ))));
+            }
+            let header = loop {
+                let header = db::read_archive_header(&mut received.as_slice())
+                    .map_err(|e| EngineError::OpError(OpError::InvalidData(e.to_string())))?;
+                if let Some(header) = header {
+                    break header;
+                }
+                match chunks.recv().await {
+                    Some(chunk) => received.extend(chunk?),
+                    None => return Err(EngineError::OpError(OpError::InvalidData("truncated archive header".to_string()))),
+                }
+            };
+            self.create_database(database.clone(), header.file_descriptor_set).await?;
         }
+
+        let db = self.database(&database)?;
+        blocking(move || {
+            let writable = |name: &str| rbac::permits(&grants, rbac::Permission::Write, &database, name);
+            let mut import = db.import(collection, format, &writable);
+            let mut chunk = Ok(received);
+            loop {
+                if let Err(e) = chunk.and_then(|chunk| import.write(&chunk).map_err(user_error)) {
+                    let imported = import.imported();
+                    return Err(EngineError::OpError(OpError::ImportFailed { imported, source: Box::new(e) }));
+                }
+                match chunks.blocking_recv() {
+                    Some(next) => chunk = next,
+                    None => break,
+                }
+            }
+            let imported = import.imported();
+            import.finish().map_err(|e| EngineError::OpError(OpError::ImportFailed { imported, source: Box::new(user_error(e)) }))
+        }).await
     }
 }
 
+/// Runs `f`, which calls RocksDB, on the blocking threads of the runtime
+/// within the current span, so that it does not hold up the requests served
+/// by the same worker thread.
+async fn blocking<T, F>(f: F) -> Result<T, EngineError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, EngineError> + Send + 'static,
+{
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || span.in_scope(f))
+        .await
+        .map_err(|e| EngineError::Internal(e.into()))?
+}
+
+
+/// Maps the errors of a document operation to the matching `OpError`.
+fn core_error(err: db::CoreError) -> EngineError {
+    match err {
+        db::CoreError::InvalidDocument(e) => EngineError::OpError(OpError::InvalidData(e)),
+        db::CoreError::SchemaNotExists(e) => EngineError::OpError(OpError::InvalidData(e)),
+        err @ db::CoreError::KeyAlreadyExists(..) => EngineError::OpError(OpError::KeyAlreadyExists(err.into())),
+        err @ db::CoreError::KeyNotFound(..) => EngineError::OpError(OpError::KeyNotFound(err.into())),
+        db::CoreError::UserAlreadyExists(user) => EngineError::OpError(OpError::UserAlreadyExists(user)),
+        db::CoreError::UserNotFound(user) => EngineError::OpError(OpError::UserNotFound(user)),
+        db::CoreError::InvalidPassword(user) => EngineError::OpError(OpError::InvalidPassword(user)),
+        db::CoreError::UserDisabled(user) => EngineError::OpError(OpError::UserDisabled(user)),
+        db::CoreError::QuotaExceeded(e) => EngineError::OpError(OpError::QuotaExceeded(e)),
+        db::CoreError::BackgroundWorkPaused(db) => EngineError::OpError(OpError::BackgroundWorkPaused(db)),
+        db::CoreError::PermissionDenied(e) => EngineError::OpError(OpError::PermissionDenied(e)),
+        err => EngineError::Internal(err.into()),
+    }
+}
+
+/// Maps the errors of a user operation of the metastore.
+fn user_error(err: Error) -> EngineError {
+    match err.downcast::<db::CoreError>() {
+        Ok(err) => core_error(*err),
+        Err(err) => EngineError::Internal(err),
+    }
+}
+
+
 impl ProtolithDbEngine {
     pub fn new(
         db_config: db::Config,
+        memory: Memory,
         meta_store_config: meta_store::Config,
         schema_config: schema::Config,
         dbs: DatabasesMap,
     ) -> Self {
-        let inner = Arc::new(Mutex::new(Inner { dbs }));
+        let dbs = dbs.into_iter().map(|(name, db)| (name, Arc::new(db))).collect();
 
         Self {
-            inner,
+            dbs: Arc::new(RwLock::new(dbs)),
+            catalog: Arc::default(),
+            operations: Arc::default(),
             db_config,
+            memory,
             schema_config,
             meta_store_config,
         }
     }
 
+    fn database(&self, name: &str) -> Result<Arc<db::RocksDb>, EngineError> {
+        self.dbs
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| EngineError::OpError(OpError::DatabaseNotFound(name.to_owned())))
+    }
+
+    /// Returns the database holding the users, sessions and API keys.
+    fn default_db(&self) -> Arc<db::RocksDb> {
+        self.dbs.read().unwrap().get(&self.meta_store_config.default_db).cloned().unwrap()
+    }
+
+    fn databases(&self) -> Vec<(String, Arc<db::RocksDb>)> {
+        self.dbs
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, db)| (name.clone(), db.clone()))
+            .collect()
+    }
+
     // Method to destroy a specific database
     pub async fn destroy_db(&mut self, db_name: &str) -> Result<(), String> {
-        let mut inner = self.inner.lock().await;
+        let _catalog = self.catalog.lock().await;
+        let db = self.dbs.write().unwrap().remove(db_name);
 
         // Check if the database exists
-        if let Some(db) = inner.dbs.remove(db_name) {
-            // Drop the db to close it
-            drop(db);
+        if let Some(mut db) = db {
+            // New requests no longer find the db, wait for the requests and
+            // maintenance operations still holding it so that dropping the
+            // last reference closes it before its files are removed. The db
+            // is served again when they do not finish in time.
+            let deadline = tokio::time::Instant::now() + RELEASE_TIMEOUT;
+            loop {
+                match Arc::try_unwrap(db) {
+                    Ok(db) => {
+                        drop(db);
+                        break;
+                    }
+                    Err(in_use) if tokio::time::Instant::now() >= deadline => {
+                        let references = Arc::strong_count(&in_use) - 1;
+                        self.dbs.write().unwrap().insert(db_name.to_owned(), in_use);
+                        return Err(format!("Database '{}' is still in use by {} requests", db_name, references));
+                    }
+                    Err(in_use) => {
+                        debug!(db = ?db_name, references = Arc::strong_count(&in_use), "waiting for the database to be released");
+                        db = in_use;
+                        tokio::time::sleep(RELEASE_POLL_INTERVAL).await;
+                    }
+                }
+            }
 
             let base_path = &self.db_config.db_path;
             // Construct the path to the database
@@ -366,13 +1069,52 @@ impl ProtolithDbEngine {
     }
 
     pub async fn get_databse_collections(&self, db_name: &str ) -> Result<Vec<Collection>, Error> {
-        let inner = self.inner.lock().await;
-        if let Some(db_engine) = inner.dbs.get(db_name) {
-            let collections = db_engine.get_collections().unwrap();
-            Ok(collections)
-        } else {
-            Err(Box::new(EngineError::OpError(OpError::DatabaseNotFound(db_name.to_owned()))))
-        }
-    } 
+        let db_engine = self.database(db_name).map_err(Box::new)?;
+        blocking(move || Ok(db_engine.get_collections().unwrap())).await.map_err(|e| e.into())
+    }
 
-}
\ No newline at end of file
+    /// Returns the `DescriptorPool` holding the collections of `db_name`.
+    pub async fn descriptor_pool(&self, db_name: &str) -> Option<DescriptorPool> {
+        self.database(db_name).ok().map(|db| db.descriptor_pool().clone())
+    }
+
+    /// Returns the RocksDB statistics of every database, leaving out those
+    /// which could not be read.
+    pub async fn database_stats(&self) -> Vec<(String, db::Stats)> {
+        let dbs = self.databases();
+        let stats = blocking(move || {
+            let mut stats = Vec::with_capacity(dbs.len());
+            for (name, db) in dbs {
+                match db.stats() {
+                    Ok(db_stats) => stats.push((name, db_stats)),
+                    Err(e) => error!(db = ?name, error = ?e, "failed to read database statistics"),
+                }
+            }
+            Ok(stats)
+        }).await;
+        stats.unwrap_or_else(|e| {
+            error!(error = ?e, "failed to read database statistics");
+            Vec::new()
+        })
+    }
+
+    /// Returns how much of the memory shared by the databases is in use.
+    pub fn memory_usage(&self) -> memory::Usage {
+        self.memory.usage()
+    }
+
+    /// Returns the database the maintenance operations of `collection`, or
+    /// of the whole database, run on.
+    async fn maintained_db(&self, database: &str, collection: Option<String>) -> Result<Arc<db::RocksDb>, EngineError> {
+        let db = self.database(database)?;
+        let Some(collection) = collection else {
+            return Ok(db);
+        };
+        let database = database.to_owned();
+        blocking(move || {
+            db.get_schema(collection.clone())
+                .map_err(|_e| EngineError::OpError(OpError::CollectionNotFound(collection, database)))?;
+            Ok(db)
+        }).await
+    }
+}
@@ -0,0 +1,266 @@
+//! Request rate and concurrency limits per user and per database.
+//!
+//! The limit layer runs each request within a `Scope` once it admitted the
+//! user of the request, the services admit the databases they reach while
+//! authorizing the request. Every user and database has a token bucket
+//! refilled at `rate` requests per second and holding up to `burst` requests,
+//! and may have up to `max_concurrent` requests in flight. A request holds its
+//! slots until its response is returned.
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::{metadata::MetadataMap, Code, Status};
+
+/// The metadata key of how many seconds to wait before retrying a refused
+/// request, after the HTTP `Retry-After` header.
+pub const HEADER_RETRY_AFTER: &str = "retry-after";
+
+/// How long clients are told to wait when too many requests are in flight.
+const CONCURRENCY_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+tokio::task_local! {
+    static SCOPE: Scope;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// Requests per second, unlimited when zero.
+    pub rate: f64,
+    /// How many requests may be made at once above `rate`, at least one.
+    pub burst: u32,
+    /// Requests in flight, unlimited when zero.
+    pub max_concurrent: u32,
+}
+
+impl Limits {
+    fn is_unlimited(&self) -> bool {
+        self.rate <= 0.0 && self.max_concurrent == 0
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub user: Limits,
+    pub database: Limits,
+}
+
+impl Config {
+    /// Builds the limiter, `None` when nothing is limited.
+    pub fn build(self) -> Option<Arc<Limiter>> {
+        if self.user.is_unlimited() && self.database.is_unlimited() {
+            return None;
+        }
+        Some(Arc::new(Limiter::new(self)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Subject {
+    User(String),
+    Database(String),
+}
+
+impl std::fmt::Display for Subject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Subject::User(username) => write!(f, "user {}", username),
+            Subject::Database(database) => write!(f, "database {}", database),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    tokens: f64,
+    updated: Instant,
+    in_flight: Arc<Semaphore>,
+}
+
+#[derive(Debug)]
+pub struct Limiter {
+    config: Config,
+    subjects: Mutex<HashMap<Subject, State>>,
+}
+
+impl Limiter {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            subjects: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Admits a request of `subject`, returning the slot it holds while in
+    /// flight or the status refusing it.
+    #[allow(clippy::result_large_err)]
+    fn admit(&self, subject: Subject, now: Instant) -> Result<Option<OwnedSemaphorePermit>, Status> {
+        let limits = match subject {
+            Subject::User(_) => self.config.user,
+            Subject::Database(_) => self.config.database,
+        };
+        if limits.is_unlimited() {
+            return Ok(None);
+        }
+        let burst = f64::from(limits.burst.max(1));
+        let mut subjects = self.subjects.lock().unwrap();
+        if !subjects.contains_key(&subject) {
+            // Forget the subjects back to a full bucket and without requests
+            // in flight, they are no different from new ones.
+            subjects.retain(|subject, state| {
+                let limits = match subject {
+                    Subject::User(_) => self.config.user,
+                    Subject::Database(_) => self.config.database,
+                };
+                let tokens = state.tokens + now.saturating_duration_since(state.updated).as_secs_f64() * limits.rate;
+                let idle = limits.max_concurrent == 0
+                    || state.in_flight.available_permits() == limits.max_concurrent as usize;
+                !(idle && (limits.rate <= 0.0 || tokens >= f64::from(limits.burst.max(1))))
+            });
+        }
+        let state = subjects.entry(subject.clone()).or_insert_with(|| State {
+            tokens: burst,
+            updated: now,
+            in_flight: Arc::new(Semaphore::new(limits.max_concurrent as usize)),
+        });
+
+        let permit = if limits.max_concurrent > 0 {
+            let permit = state.in_flight.clone().try_acquire_owned().map_err(|_| {
+                exhausted(
+                    format!("{} has too many requests in flight", subject),
+                    CONCURRENCY_RETRY_AFTER,
+                )
+            })?;
+            Some(permit)
+        } else {
+            None
+        };
+        if limits.rate > 0.0 {
+            let elapsed = now.saturating_duration_since(state.updated).as_secs_f64();
+            state.tokens = (state.tokens + elapsed * limits.rate).min(burst);
+            state.updated = now;
+            if state.tokens < 1.0 {
+                let retry_after = Duration::from_secs_f64((1.0 - state.tokens) / limits.rate);
+                return Err(exhausted(format!("{} exceeds its request rate", subject), retry_after));
+            }
+            state.tokens -= 1.0;
+        }
+        Ok(permit)
+    }
+}
+
+/// A `RESOURCE_EXHAUSTED` status telling to retry after `retry_after`.
+fn exhausted(message: String, retry_after: Duration) -> Status {
+    // Round up, so that retrying after the hint succeeds.
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let mut metadata = MetadataMap::new();
+    metadata.insert(HEADER_RETRY_AFTER, secs.into());
+    Status::with_metadata(
+        Code::ResourceExhausted,
+        format!("{}, retry in {}s", message, secs),
+        metadata,
+    )
+}
+
+/// The limits of a request and the slots it holds, released once the last
+/// clone of the scope is dropped.
+#[derive(Debug, Clone)]
+pub struct Scope(Arc<ScopeInner>);
+
+#[derive(Debug)]
+struct ScopeInner {
+    limiter: Arc<Limiter>,
+    admitted: Mutex<Vec<(Subject, Option<OwnedSemaphorePermit>)>>,
+}
+
+impl Scope {
+    pub fn new(limiter: Arc<Limiter>) -> Self {
+        Self(Arc::new(ScopeInner {
+            limiter,
+            admitted: Mutex::new(Vec::new()),
+        }))
+    }
+
+    /// Runs `f` within this scope.
+    pub fn run<F: Future>(&self, f: F) -> impl Future<Output = F::Output> {
+        SCOPE.scope(self.clone(), f)
+    }
+
+    /// Admits the request of `username`.
+    #[allow(clippy::result_large_err)]
+    pub fn admit_user(&self, username: &str) -> Result<(), Status> {
+        self.admit(Subject::User(username.to_owned()))
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn admit(&self, subject: Subject) -> Result<(), Status> {
+        let mut admitted = self.0.admitted.lock().unwrap();
+        // A request reaching a database several times is admitted once.
+        if admitted.iter().any(|(s, _)| *s == subject) {
+            return Ok(());
+        }
+        let permit = self.0.limiter.admit(subject.clone(), Instant::now())?;
+        admitted.push((subject, permit));
+        Ok(())
+    }
+}
+
+/// Admits the current request to `database`, requests outside a scope are
+/// not limited.
+#[allow(clippy::result_large_err)]
+pub fn admit_database(database: &str) -> Result<(), Status> {
+    if database.is_empty() {
+        return Ok(());
+    }
+    SCOPE
+        .try_with(|scope| scope.admit(Subject::Database(database.to_owned())))
+        .unwrap_or(Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_and_concurrency() {
+        let limiter = Arc::new(Limiter::new(Config {
+            user: Limits {
+                rate: 2.0,
+                burst: 2,
+                max_concurrent: 0,
+            },
+            database: Limits {
+                rate: 0.0,
+                burst: 0,
+                max_concurrent: 1,
+            },
+        }));
+        let now = Instant::now();
+        let ann = || Subject::User("ann".to_string());
+        assert!(limiter.admit(ann(), now).is_ok());
+        assert!(limiter.admit(ann(), now).is_ok());
+        let status = limiter.admit(ann(), now).unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(status.metadata().get(HEADER_RETRY_AFTER).unwrap(), "1");
+        assert!(limiter.admit(Subject::User("bob".to_string()), now).is_ok());
+        assert!(limiter.admit(ann(), now + Duration::from_millis(500)).is_ok());
+
+        let db = || Subject::Database("shop".to_string());
+        let permit = limiter.admit(db(), now).unwrap();
+        assert!(limiter.admit(db(), now).is_err());
+        drop(permit);
+        assert!(limiter.admit(db(), now).is_ok());
+
+        // A scope admits each database once and releases its slots with it.
+        let scope = Scope::new(limiter.clone());
+        assert!(scope.admit(db()).is_ok());
+        assert!(scope.admit(db()).is_ok());
+        assert!(Scope::new(limiter.clone()).admit(db()).is_err());
+        drop(scope);
+        assert!(Scope::new(limiter).admit(db()).is_ok());
+    }
+}
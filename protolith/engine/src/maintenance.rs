@@ -0,0 +1,144 @@
+//! The compactions and flushes started through the `AdminService`.
+//!
+//! They can take minutes on large databases, so they run on the blocking
+//! threads of the runtime while callers poll them by id. Operations are only
+//! kept in memory, the last `MAX_FINISHED` finished ones until the server
+//! restarts.
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use protolith_core::{
+    api::{
+        pbjson_types::Timestamp,
+        protolith::{
+            services::v1::{maintenance_operation::Kind, MaintenanceOperation},
+            types::v1::OpStatus,
+        },
+    },
+    db,
+    error::Error,
+};
+use tracing::{error, info, info_span};
+
+/// The finished operations kept for callers to poll.
+const MAX_FINISHED: usize = 100;
+
+#[derive(Debug, Default)]
+pub struct Operations {
+    operations: Mutex<VecDeque<MaintenanceOperation>>,
+}
+
+impl Operations {
+    /// Runs `kind` over the column families of `collection`, or of the whole
+    /// `db`, in the background and returns the pending operation.
+    pub fn start(self: &Arc<Self>, kind: Kind, db: Arc<db::RocksDb>, collection: Option<String>) -> MaintenanceOperation {
+        let operation = MaintenanceOperation {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            kind: kind.into(),
+            database: db.name.clone(),
+            collection: collection.clone().unwrap_or_default(),
+            status: OpStatus::Pending.into(),
+            started_at: Some(now()),
+            ..Default::default()
+        };
+        self.operations.lock().unwrap().push_back(operation.clone());
+
+        let operations = self.clone();
+        let id = operation.id.clone();
+        let span = info_span!("maintenance", id = %id, kind = ?kind, db = %db.name, collection = ?collection);
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            info!("started maintenance operation");
+            let result = match kind {
+                Kind::Compact => db.compact(collection.as_deref()),
+                Kind::Flush => db.flush(collection.as_deref()),
+            };
+            operations.finish(&id, result);
+        });
+        operation
+    }
+
+    pub fn get(&self, id: &str) -> Option<MaintenanceOperation> {
+        self.operations
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|operation| operation.id == id)
+            .cloned()
+    }
+
+    /// Returns the operations in the order they were started.
+    pub fn list(&self) -> Vec<MaintenanceOperation> {
+        self.operations.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn finish(&self, id: &str, result: Result<(), Error>) {
+        let mut operations = self.operations.lock().unwrap();
+        let Some(operation) = operations.iter_mut().find(|operation| operation.id == id) else {
+            return;
+        };
+        operation.finished_at = Some(now());
+        match result {
+            Ok(()) => {
+                info!("finished maintenance operation");
+                operation.status = OpStatus::Success.into();
+            }
+            Err(e) => {
+                error!(error = ?e, "maintenance operation failed");
+                operation.status = OpStatus::Failure.into();
+                operation.error = e.to_string();
+            }
+        }
+
+        let finished = operations
+            .iter()
+            .filter(|operation| operation.status() != OpStatus::Pending)
+            .count();
+        if finished > MAX_FINISHED {
+            if let Some(oldest) = operations.iter().position(|operation| operation.status() != OpStatus::Pending) {
+                operations.remove(oldest);
+            }
+        }
+    }
+}
+
+fn now() -> Timestamp {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Timestamp {
+        seconds: since_epoch.as_secs() as i64,
+        nanos: since_epoch.subsec_nanos() as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_last_finished_operations() {
+        let operations = Operations::default();
+        for i in 0..=MAX_FINISHED + 1 {
+            operations.operations.lock().unwrap().push_back(MaintenanceOperation {
+                id: i.to_string(),
+                status: OpStatus::Pending.into(),
+                ..Default::default()
+            });
+        }
+        for i in 1..=MAX_FINISHED + 1 {
+            operations.finish(&i.to_string(), Ok(()));
+        }
+        operations.finish("missing", Ok(()));
+
+        let kept = operations.list();
+        assert_eq!(kept.len(), MAX_FINISHED + 1);
+        // The pending operation is kept whatever its age.
+        assert_eq!(kept[0].status(), OpStatus::Pending);
+        assert_eq!(kept[1].id, "2");
+        assert!(kept[1..].iter().all(|operation| operation.status() == OpStatus::Success && operation.finished_at.is_some()));
+    }
+}
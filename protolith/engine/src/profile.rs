@@ -0,0 +1,38 @@
+//! The profiles returned to the requests asking for them.
+//!
+//! The services run such requests within a scope, the engine profiles the
+//! storage operations it runs in it with RocksDB timers and records their
+//! `Profile` for the response.
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use protolith_core::profile::Profile;
+
+tokio::task_local! {
+    static SCOPE: Arc<Mutex<Option<Profile>>>;
+}
+
+/// Runs `f`, returning the profile of the last storage operation it ran
+/// when `enabled`.
+pub async fn run<F: Future>(enabled: bool, f: F) -> (F::Output, Option<Profile>) {
+    if !enabled {
+        return (f.await, None);
+    }
+    let scope = Arc::default();
+    let output = SCOPE.scope(Arc::clone(&scope), f).await;
+    let profile = scope.lock().unwrap().take();
+    (output, profile)
+}
+
+/// Returns the profile to fill in by the next storage operation, timed only
+/// when the current request asked for it.
+pub fn start() -> Profile {
+    Profile::new(SCOPE.try_with(|_| ()).is_ok())
+}
+
+/// Records the profile of the storage operation of the current request.
+pub fn record(profile: Profile) {
+    let _ = SCOPE.try_with(|scope| *scope.lock().unwrap() = Some(profile));
+}
@@ -0,0 +1,198 @@
+//! Role based access control over the users grants.
+use protolith_core::api::protolith::types::v1::{Grant, Role};
+use tonic::Status;
+
+use crate::{audit, limit, Admin, EngineError, OpError};
+
+/// The authenticated user of a request, set in the request extensions by the
+/// session layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    pub username: String,
+    /// Grants carried by the credential itself, such as the roles claim of a
+    /// JWT, on top of the grants stored for the user.
+    pub grants: Vec<Grant>,
+    /// The databases the credential is restricted to, such as the scope of
+    /// an API key, every database when empty.
+    pub databases: Vec<String>,
+}
+
+impl Principal {
+    pub fn new(username: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            grants: Vec::new(),
+            databases: Vec::new(),
+        }
+    }
+
+    pub fn with_grants(mut self, grants: Vec<Grant>) -> Self {
+        self.grants = grants;
+        self
+    }
+
+    pub fn with_databases(mut self, databases: Vec<String>) -> Self {
+        self.databases = databases;
+        self
+    }
+}
+
+/// The permission an operation requires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    Read,
+    Write,
+    Admin,
+    /// Read the encrypted fields of documents.
+    Decrypt,
+}
+
+impl Permission {
+    fn granted_by(self, grant: &Grant) -> bool {
+        if self == Permission::Decrypt {
+            return grant.decrypt || grant.role() == Role::Admin;
+        }
+        match grant.role() {
+            Role::Admin => true,
+            Role::ReadWrite => self != Permission::Admin,
+            Role::ReadOnly => self == Permission::Read,
+        }
+    }
+}
+
+/// Whether `grants` allow `permission` on `collection` of `database`.
+///
+/// An empty `database` or `collection` asks for the permission on every
+/// database or collection, which only grants of the same scope allow.
+pub fn permits(grants: &[Grant], permission: Permission, database: &str, collection: &str) -> bool {
+    grants.iter().any(|grant| {
+        permission.granted_by(grant)
+            && (grant.database.is_empty() || grant.database == database)
+            && (grant.collection.is_empty() || grant.collection == collection)
+    })
+}
+
+/// Whether `grants` allow anything on `database`.
+pub fn can_access(grants: &[Grant], database: &str) -> bool {
+    grants
+        .iter()
+        .any(|grant| grant.database.is_empty() || grant.database == database)
+}
+
+/// Narrows `grants` to `databases`, grants on every database become grants
+/// on each of them.
+pub fn restrict(grants: Vec<Grant>, databases: &[String]) -> Vec<Grant> {
+    if databases.is_empty() {
+        return grants;
+    }
+    let mut restricted = Vec::new();
+    for grant in grants {
+        for database in databases {
+            if grant.database.is_empty() || &grant.database == database {
+                restricted.push(Grant {
+                    database: database.clone(),
+                    ..grant.clone()
+                });
+            }
+        }
+    }
+    restricted
+}
+
+/// Returns the grants of `principal`, failing when it is disabled.
+pub async fn grants<E: Admin>(engine: &E, principal: Option<&Principal>) -> Result<Vec<Grant>, Status> {
+    let principal = principal.ok_or_else(|| Status::unauthenticated("request is not authenticated"))?;
+    let grants = match engine.get_user(principal.username.clone()).await {
+        Ok(user) if user.disabled => {
+            return Err(Status::permission_denied(format!("user {} is disabled", principal.username)))
+        }
+        Ok(user) => [user.grants, principal.grants.clone()].concat(),
+        Err(EngineError::OpError(OpError::UserNotFound(_))) => principal.grants.clone(),
+        Err(e) => return Err(Status::internal(e.to_string())),
+    };
+    Ok(restrict(grants, &principal.databases))
+}
+
+/// Checks that `principal` holds `permission` on `collection` of `database`,
+/// which the audit log records as the target of the request, and admits the
+/// request to the limits of `database`, returning its grants.
+pub async fn authorize<E: Admin>(
+    engine: &E,
+    principal: Option<&Principal>,
+    permission: Permission,
+    database: &str,
+    collection: &str,
+) -> Result<Vec<Grant>, Status> {
+    audit::record_collection(database, collection);
+    let grants = grants(engine, principal).await?;
+    if permits(&grants, permission, database, collection) {
+        limit::admit_database(database)?;
+        Ok(grants)
+    } else {
+        Err(Status::permission_denied(format!(
+            "user {} is missing {:?} permission on {}",
+            principal.map(|p| p.username.as_str()).unwrap_or_default(),
+            permission,
+            scope(database, collection),
+        )))
+    }
+}
+
+fn scope(database: &str, collection: &str) -> String {
+    match (database, collection) {
+        ("", _) => "all databases".to_string(),
+        (database, "") => format!("database {}", database),
+        (database, collection) => format!("collection {} of {}", collection, database),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant(role: Role, database: &str, collection: &str) -> Grant {
+        Grant {
+            role: role.into(),
+            database: database.to_string(),
+            collection: collection.to_string(),
+            decrypt: false,
+        }
+    }
+
+    #[test]
+    fn roles_and_scopes() {
+        let grants = vec![
+            grant(Role::ReadOnly, "", ""),
+            grant(Role::ReadWrite, "app", "app.v1.Item"),
+        ];
+        assert!(permits(&grants, Permission::Read, "other", "x.Y"));
+        assert!(permits(&grants, Permission::Write, "app", "app.v1.Item"));
+        assert!(!permits(&grants, Permission::Write, "app", "app.v1.Other"));
+        assert!(!permits(&grants, Permission::Write, "app", ""));
+        assert!(!permits(&grants, Permission::Admin, "app", "app.v1.Item"));
+
+        let admin = vec![grant(Role::Admin, "app", "")];
+        assert!(permits(&admin, Permission::Admin, "app", ""));
+        assert!(permits(&admin, Permission::Write, "app", "app.v1.Item"));
+        assert!(!permits(&admin, Permission::Admin, "", ""));
+        assert!(can_access(&admin, "app"));
+        assert!(!can_access(&admin, "other"));
+
+        let scoped = restrict(grants, &["app".to_string()]);
+        assert!(permits(&scoped, Permission::Read, "app", "x.Y"));
+        assert!(!permits(&scoped, Permission::Read, "other", "x.Y"));
+        assert!(permits(&scoped, Permission::Write, "app", "app.v1.Item"));
+        assert!(!can_access(&scoped, "other"));
+    }
+
+    #[test]
+    fn decrypt() {
+        let reader = vec![grant(Role::ReadOnly, "app", "")];
+        assert!(!permits(&reader, Permission::Decrypt, "app", "app.v1.Item"));
+        let decrypting = vec![Grant { decrypt: true, ..grant(Role::ReadOnly, "app", "") }];
+        assert!(permits(&decrypting, Permission::Decrypt, "app", "app.v1.Item"));
+        assert!(!permits(&decrypting, Permission::Write, "app", "app.v1.Item"));
+        let admin = vec![grant(Role::Admin, "app", "")];
+        assert!(permits(&admin, Permission::Decrypt, "app", "app.v1.Item"));
+    }
+}
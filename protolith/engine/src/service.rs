@@ -3,22 +3,35 @@ use protolith_core::api::{
     protolith::{
         services::v1::{
             engine_service_server::{self, EngineService},
-            GetRequest, GetResponse, InsertRequest, InsertResponse, ListRequest, ListResponse,
+            ExportRequest, ExportResponse, GetRequest, GetResponse, ImportRequest, ImportResponse,
+            InsertRequest, InsertResponse, ListRequest, ListResponse,
         },
-        types::v1::{ApiOp, Op, OpStatus},
+        types::v1::{ApiOp, DocumentEncoding, ExportFormat, Op, OpStatus},
     },
 };
+use protolith_core::{db, profile::Profile};
+use std::pin::Pin;
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 use tracing::debug;
 
-use crate::Engine;
-use tonic::{Request, Response, Status};
-pub struct ProtolithEngineService<E: Engine> {
+use crate::{
+    audit, profile,
+    rbac::{self, Permission, Principal},
+    Admin, Engine,
+};
+use tonic::{Request, Response, Status, Streaming};
+pub struct ProtolithEngineService<E: Engine + Admin> {
     engine: E,
 }
 
 type EngineServiceType<E> = engine_service_server::EngineServiceServer<ProtolithEngineService<E>>;
+type ExportStream = Pin<Box<dyn Stream<Item = Result<ExportResponse, Status>> + Send>>;
+
+/// How many chunks of an import may be received before it writes them.
+const IMPORT_CHUNKS: usize = 16;
 
-impl<E: Engine> ProtolithEngineService<E> {
+impl<E: Engine + Admin> ProtolithEngineService<E> {
     pub fn new(e: E) -> Self {
         Self { engine: e }
     }
@@ -29,24 +42,27 @@ impl<E: Engine> ProtolithEngineService<E> {
 }
 
 #[tonic::async_trait]
-impl<E: Engine> EngineService for ProtolithEngineService<E> {
+impl<E: Engine + Admin> EngineService for ProtolithEngineService<E> {
     async fn list(&self, request: Request<ListRequest>) -> Result<Response<ListResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
         let req = request.into_inner();
         let database = req.database;
         let collection = req.collection;
+        let grants = rbac::authorize(&self.engine, principal.as_ref(), Permission::Read, &database, &collection).await?;
+        let decrypt = rbac::permits(&grants, Permission::Decrypt, &database, &collection);
 
-        let data = self
-            .engine
-            .list(database, collection.clone())
+        let (data, profile) = if req.index_field.is_empty() {
+            profile::run(req.profile, self.engine.list(database, collection.clone(), decrypt)).await
+        } else {
+            let value = serde_json::to_value(req.index_value.unwrap_or_default())
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            profile::run(
+                req.profile,
+                self.engine.find(database, collection.clone(), req.index_field, value, decrypt),
+            )
             .await
-            .map_err(|err| match err {
-                crate::EngineError::Internal(e) => Status::internal(e.to_string()),
-                crate::EngineError::OpError(op) => match op {
-                    crate::OpError::DatabaseNotFound(e) => Status::not_found(e),
-                    crate::OpError::KeyAlreadyExists(e) => Status::already_exists(e.to_string()),
-                    _ => unreachable!(),
-                },
-            })?;
+        };
+        let data = data.map_err(status)?;
         let length = data.len();
         Ok(Response::new(ListResponse {
             collection: collection.clone(),
@@ -58,6 +74,7 @@ impl<E: Engine> EngineService for ProtolithEngineService<E> {
                 ),
                 status: OpStatus::Success.into(),
                 r#type: Op::List.into(),
+                profile: profile.map(Into::into),
             }),
         }))
     }
@@ -66,35 +83,43 @@ impl<E: Engine> EngineService for ProtolithEngineService<E> {
         &self,
         request: Request<InsertRequest>,
     ) -> Result<Response<InsertResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
         let req = request.into_inner();
-        if let Some(any) = req.data {
-            let collection =
-                self.engine
-                    .insert(req.database, any)
-                    .await
-                    .map_err(|err| match err {
-                        crate::EngineError::Internal(e) => Status::internal(e.to_string()),
-                        crate::EngineError::OpError(op) => match op {
-                            crate::OpError::DatabaseNotFound(e) => Status::not_found(e),
-                            crate::OpError::KeyAlreadyExists(e) => {
-                                Status::already_exists(e.to_string())
-                            }
-                            _ => unreachable!(),
-                        },
-                    })?;
-            Ok(Response::new(InsertResponse {
-                collection,
-                ..Default::default()
-            }))
+        let collection = Some(req.collection).filter(|c| !c.is_empty());
+        let (inserted, profile) = if let Some(any) = req.data {
+            let target = db::collection_of(&any.type_url)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            rbac::authorize(&self.engine, principal.as_ref(), Permission::Write, &req.database, target).await?;
+            profile::run(req.profile, self.engine.insert(req.database, any)).await
+        } else if let Some(document) = req.struct_data {
+            let document = serde_json::to_value(document)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            authorize_json(&self.engine, principal.as_ref(), &req.database, collection.as_deref(), &document).await?;
+            profile::run(req.profile, self.engine.insert_json(req.database, collection, document)).await
+        } else if !req.json_data.is_empty() {
+            let document = serde_json::from_str(&req.json_data)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            authorize_json(&self.engine, principal.as_ref(), &req.database, collection.as_deref(), &document).await?;
+            profile::run(req.profile, self.engine.insert_json(req.database, collection, document)).await
         } else {
-            Err(Status::invalid_argument(
-                "Must pass a valid Any type message",
-            ))
-        }
+            return Err(Status::invalid_argument(
+                "Must pass a valid Any type message, Struct or JSON document",
+            ));
+        };
+        let collection = inserted.map_err(status)?;
+        Ok(Response::new(InsertResponse {
+            op: profile.map(|profile| {
+                profiled_op(Op::Create, format!("inserted into {} successfully.", collection), profile)
+            }),
+            collection,
+        }))
     }
 
     async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
         let req = request.into_inner();
+        let grants = rbac::authorize(&self.engine, principal.as_ref(), Permission::Read, &req.database, &req.collection).await?;
+        let decrypt = rbac::permits(&grants, Permission::Decrypt, &req.database, &req.collection);
         if let Some(key) = &req.key {
             let key = match &key.kind {
                 Some(Kind::NumberValue(n)) => format!("{}:{}", req.collection, n),
@@ -103,19 +128,46 @@ impl<E: Engine> EngineService for ProtolithEngineService<E> {
                 }
                 _ => todo!(),
             };
-            let value = self
-                .engine
-                .get(
+            audit::record_key(&key);
+            let encoding = req.encoding();
+            if encoding != DocumentEncoding::Any {
+                let (document, profile) = profile::run(
+                    req.profile,
+                    self.engine.get_json(req.database, req.collection.clone(), key.as_bytes(), decrypt),
+                )
+                .await;
+                let document = document.map_err(status)?;
+                debug!(collection = ?req.collection.clone(), key = ?key, encoding = ?encoding, "get");
+                let mut rep = GetResponse {
+                    collection: req.collection,
+                    op: profile.map(|profile| profiled_op(Op::Fetch, format!("fetched {} successfully.", key), profile)),
+                    ..Default::default()
+                };
+                if encoding == DocumentEncoding::Struct {
+                    let document = serde_json::from_value(document)
+                        .map_err(|e| Status::internal(e.to_string()))?;
+                    rep.struct_data = Some(document);
+                } else {
+                    rep.json_data = document.to_string();
+                }
+                return Ok(Response::new(rep));
+            }
+            let (value, profile) = profile::run(
+                req.profile,
+                self.engine.get(
                     req.database,
                     req.collection.clone(),
                     &key.clone().into_bytes(),
-                )
-                .await
-                .map_err(|e| Status::internal(e.to_string()))?;
+                    decrypt,
+                ),
+            )
+            .await;
+            let value = value.map_err(status)?;
             debug!(collection = ?req.collection.clone(), key = ?key, bytes = ?value.value.len(), "get");
             return Ok(Response::new(GetResponse {
                 collection: req.collection,
                 data: Some(value),
+                op: profile.map(|profile| profiled_op(Op::Fetch, format!("fetched {} successfully.", key), profile)),
                 ..Default::default()
             }));
         } else {
@@ -124,4 +176,129 @@ impl<E: Engine> EngineService for ProtolithEngineService<E> {
             ));
         };
     }
+
+    type ExportStream = ExportStream;
+
+    async fn export(
+        &self,
+        request: Request<ExportRequest>,
+    ) -> Result<Response<Self::ExportStream>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        let grants = rbac::authorize(&self.engine, principal.as_ref(), Permission::Read, &req.database, &req.collection).await?;
+        let decrypt = rbac::permits(&grants, Permission::Decrypt, &req.database, &req.collection);
+        let format = req.format();
+        let collection = Some(req.collection).filter(|c| !c.is_empty());
+        let chunks = self
+            .engine
+            .export(req.database, collection, format, decrypt, req.omit_encrypted_fields)
+            .await
+            .map_err(status)?;
+        #[allow(clippy::result_large_err)]
+        let stream = ReceiverStream::new(chunks).map(|chunk| chunk.map(|chunk| ExportResponse { chunk }).map_err(status));
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn import(
+        &self,
+        request: Request<Streaming<ImportRequest>>,
+    ) -> Result<Response<ImportResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let mut stream = request.into_inner();
+        let first = match stream.message().await? {
+            Some(first) => first,
+            None => return Err(Status::invalid_argument("import stream must not be empty")),
+        };
+        let format = first.format();
+        let ImportRequest {
+            database,
+            collection,
+            chunk,
+            ..
+        } = first;
+        // Archives restore the schemas of their collections, which takes
+        // a grant on the whole database.
+        let scope = if format == ExportFormat::Archive { "" } else { collection.as_str() };
+        let grants = rbac::authorize(&self.engine, principal.as_ref(), Permission::Write, &database, scope).await?;
+        let (tx, chunks) = mpsc::channel(IMPORT_CHUNKS);
+        let collection = Some(collection).filter(|c| !c.is_empty());
+        let import = self.engine.import(database.clone(), collection, format, chunks, grants);
+        // The chunks are received while the import writes those before them.
+        let receive = async move {
+            let mut chunk = Ok(chunk);
+            loop {
+                let failed = chunk.is_err();
+                if tx.send(chunk).await.is_err() || failed {
+                    break;
+                }
+                chunk = match stream.message().await {
+                    Ok(Some(req)) => Ok(req.chunk),
+                    Ok(None) => break,
+                    Err(e) => Err(crate::EngineError::Internal(e.into())),
+                };
+            }
+        };
+        let (imported, ()) = tokio::join!(import, receive);
+        let imported = imported.map_err(status)?;
+        Ok(Response::new(ImportResponse {
+            database: database.clone(),
+            imported,
+            op: Some(ApiOp {
+                description: format!("imported {} documents into {}", imported, database),
+                status: OpStatus::Success.into(),
+                r#type: Op::Import.into(),
+                ..Default::default()
+            }),
+        }))
+    }
+}
+
+/// Authorizes writing the JSON `document` into the collection its `@type`
+/// names, or `collection` when it has none. A mismatching `@type` is rejected
+/// by the insert itself.
+async fn authorize_json<E: Admin>(
+    engine: &E,
+    principal: Option<&Principal>,
+    database: &str,
+    collection: Option<&str>,
+    document: &serde_json::Value,
+) -> Result<(), Status> {
+    let target = match (collection, document.get("@type").and_then(|url| url.as_str())) {
+        (Some(collection), _) => collection,
+        (None, Some(url)) => db::collection_of(url).map_err(|e| Status::invalid_argument(e.to_string()))?,
+        (None, None) => return Err(Status::invalid_argument("missing @type")),
+    };
+    rbac::authorize(engine, principal, Permission::Write, database, target).await?;
+    Ok(())
+}
+
+/// The `ApiOp` of a request which asked for the profile of its operation.
+fn profiled_op(r#type: Op, description: String, profile: Profile) -> ApiOp {
+    ApiOp {
+        description,
+        r#type: r#type.into(),
+        status: OpStatus::Success.into(),
+        profile: Some(profile.into()),
+    }
+}
+
+/// Maps an `EngineError` to the matching gRPC status.
+pub(crate) fn status(err: crate::EngineError) -> Status {
+    match err {
+        crate::EngineError::Internal(e) => Status::internal(e.to_string()),
+        crate::EngineError::OpError(op) => match op {
+            crate::OpError::DatabaseNotFound(e) => Status::not_found(e),
+            e @ crate::OpError::CollectionNotFound(..) => Status::not_found(e.to_string()),
+            crate::OpError::KeyNotFound(e) => Status::not_found(e.to_string()),
+            crate::OpError::KeyAlreadyExists(e) => Status::already_exists(e.to_string()),
+            crate::OpError::InvalidData(e) => Status::invalid_argument(e),
+            crate::OpError::QuotaExceeded(e) => Status::resource_exhausted(e),
+            crate::OpError::PermissionDenied(e) => Status::permission_denied(e),
+            crate::OpError::ImportFailed { imported, source } => {
+                let status = status(*source);
+                Status::new(status.code(), format!("{}, after importing {} documents", status.message(), imported))
+            }
+            e => Status::internal(e.to_string()),
+        },
+    }
 }
@@ -1,10 +1,10 @@
-use std::fmt;
+use std::{fmt, io::{self, Write as _}};
 use tracing::{field, span, Id, Level, Metadata, Subscriber};
 use tracing_subscriber::{
     field::RecordFields,
     filter::{FilterFn, Filtered},
-    fmt::{format, FormatFields, FormattedFields},
-    layer::{Context, Layer},
+    fmt::{format, FormatFields, FormattedFields, MakeWriter},
+    layer::{Context, Layer, SubscriberExt},
     registry::LookupSpan,
 };
 
@@ -13,9 +13,9 @@ pub const TRACE_TARGET: &str = "_access_log";
 pub(super) type AccessLogLayer<S> =
     Filtered<Box<dyn Layer<S> + Send + Sync + 'static>, FilterFn, S>;
 
-#[derive(Default)]
-pub(super) struct Writer<F = ApacheCommon> {
+pub(super) struct Writer<F = ApacheCommon, W = fn() -> io::Stderr> {
     formatter: F,
+    make_writer: W,
 }
 
 #[derive(Default)]
@@ -24,7 +24,7 @@ pub(super) struct ApacheCommon {
 }
 
 #[derive(Copy, Clone, Debug)]
-pub(super) enum Format {
+pub enum Format {
     Apache,
     Json,
 }
@@ -37,10 +37,33 @@ struct ApacheCommonVisitor<'writer> {
 pub(super) fn build<S>(format: Format) -> AccessLogLayer<S>
 where
     S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    with_writer(format, io::stderr as fn() -> io::Stderr)
+}
+
+/// A subscriber only writing the access log to `make_writer`, for tests of
+/// the lines requests are logged with.
+pub fn subscriber<W>(format: Format, make_writer: W) -> impl Subscriber + Send + Sync
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    tracing_subscriber::registry().with(with_writer(format, make_writer))
+}
+
+fn with_writer<S, W>(format: Format, make_writer: W) -> AccessLogLayer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
 {
     let writer: Box<dyn Layer<S> + Send + Sync + 'static> = match format {
-        Format::Apache => Box::<Writer<ApacheCommon>>::default(),
-        Format::Json => Box::<Writer<format::JsonFields>>::default(),
+        Format::Apache => Box::new(Writer {
+            formatter: ApacheCommon::default(),
+            make_writer,
+        }),
+        Format::Json => Box::new(Writer {
+            formatter: format::JsonFields::default(),
+            make_writer,
+        }),
     };
 
     writer.with_filter(
@@ -54,10 +77,11 @@ where
 
 // === impl Writer ===
 
-impl<S, F> Layer<S> for Writer<F>
+impl<S, F, W> Layer<S> for Writer<F, W>
 where
     S: Subscriber + for<'span> LookupSpan<'span>,
     F: for<'writer> FormatFields<'writer> + 'static,
+    W: for<'writer> MakeWriter<'writer> + 'static,
 {
     fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
         let span = ctx.span(id).expect("Span not found, this is a bug");
@@ -96,7 +120,7 @@ where
     fn on_close(&self, id: Id, ctx: Context<'_, S>) {
         if let Some(span) = ctx.span(&id) {
             if let Some(fields) = span.extensions().get::<FormattedFields<F>>() {
-                eprintln!("{}", fields.fields);
+                let _ = writeln!(self.make_writer.make_writer(), "{}", fields.fields);
             }
         }
     }
@@ -105,10 +129,10 @@ where
 impl ApacheCommon {
     const SKIPPED_FIELDS: &'static [&'static str] = &[
         "trace_id",
+        "grpc_status",
         "request_bytes",
         "total_ns",
         "processing_ns",
-        "response_bytes",
         "user_agent",
         "host",
     ];
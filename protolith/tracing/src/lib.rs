@@ -3,13 +3,14 @@
 
 pub mod access_log;
 pub mod level;
+pub mod otel;
 #[cfg(feature = "stream")]
 pub mod stream;
-mod uptime;
+pub mod uptime;
 
 use self::uptime::Uptime;
 use protolith_error::Error;
-use std::str;
+use std::{str, time::Duration};
 use tokio::time;
 use tracing::Dispatch;
 use tracing_subscriber::{
@@ -24,9 +25,12 @@ pub use tracing_subscriber::{registry, EnvFilter};
 const ENV_LOG_LEVEL: &str = "PROTOLITH_LOG";
 const ENV_LOG_FORMAT: &str = "PROTOLITH_LOG_FORMAT";
 const ENV_ACCESS_LOG: &str = "PROTOLITH_ACCESS_LOG";
+const ENV_OTLP_ENDPOINT: &str = "PROTOLITH_OTLP_ENDPOINT";
+const ENV_OTLP_FILTER: &str = "PROTOLITH_OTLP_FILTER";
 
 const DEFAULT_LOG_LEVEL: &str = "info,protolith_app::layer=trace";
 const DEFAULT_LOG_FORMAT: &str = "PLAIN";
+const DEFAULT_OTLP_FILTER: &str = "info";
 
 #[derive(Debug, Default)]
 #[must_use]
@@ -35,12 +39,21 @@ pub struct Settings {
     format: String,
     start_time: Option<time::Instant>,
     access_log: Option<access_log::Format>,
+    otlp: Option<Otlp>,
     is_test: bool,
 }
 
+/// Where spans are exported to over OTLP/gRPC, and which ones.
+#[derive(Debug, Clone)]
+struct Otlp {
+    endpoint: String,
+    filter: String,
+}
+
 #[derive(Clone)]
 pub struct Handle {
     level: Option<level::Handle>,
+    uptime: Uptime,
     #[cfg(feature = "stream")]
     stream: stream::StreamHandle<LogStack>,
 }
@@ -71,6 +84,7 @@ impl Settings {
                 .ok()
                 .unwrap_or_else(|| DEFAULT_LOG_FORMAT.to_string()),
             access_log: Self::access_log_format(),
+            otlp: Self::otlp(),
             start_time: Some(now),
             is_test: false,
         }
@@ -83,6 +97,7 @@ impl Settings {
             format,
             start_time: None,
             access_log: Self::access_log_format(),
+            otlp: None,
             is_test: true,
         }
     }
@@ -98,6 +113,20 @@ impl Settings {
         }
     }
 
+    fn otlp() -> Option<Otlp> {
+        let endpoint = std::env::var(ENV_OTLP_ENDPOINT).ok()?;
+        if cfg!(not(feature = "otel")) {
+            eprintln!("{} is set, but spans can not be exported without the `otel` feature", ENV_OTLP_ENDPOINT);
+            return None;
+        }
+        Some(Otlp {
+            endpoint,
+            filter: std::env::var(ENV_OTLP_FILTER)
+                .ok()
+                .unwrap_or_else(|| DEFAULT_OTLP_FILTER.to_string()),
+        })
+    }
+
     fn timer(&self) -> Uptime {
         self.start_time
             .map(Uptime::starting_at)
@@ -156,6 +185,7 @@ impl Settings {
         if self.filter.trim().eq_ignore_ascii_case("off") {
             return Ok(Handle {
                 level: None,
+                uptime: self.timer(),
 
                 // logging is disabled, but log streaming might still be enabled later
                 #[cfg(feature = "stream")]
@@ -186,10 +216,12 @@ impl Settings {
     ///
     /// - process diagnostic logging to stdout;
     /// - optional access logging to stderr;
+    /// - if the `otel` feature is enabled, optional span export over OTLP;
     /// - if the `stream` feature is enabled, on-demand log streaming via the
     ///   returned `Handle`
     pub fn build(self) -> (Dispatch, Handle) {
         let registry = tracing_subscriber::registry();
+        let uptime = self.timer();
 
         // Build the default stdout logger.
         let (registry, level) = {
@@ -223,9 +255,23 @@ impl Settings {
         // Access logging is optionally enabled process-wide.
         let registry = registry.with(self.access_log.map(access_log::build));
 
+        // Spans are exported when a collector is configured, with their own
+        // filter so that changing the log level does not affect them.
+        #[cfg(feature = "otel")]
+        let registry = registry.with(self.otlp.and_then(|otlp| {
+            match otel::build(&otlp.endpoint, &otlp.filter) {
+                Ok(layer) => Some(layer),
+                Err(err) => {
+                    eprintln!("Failed to export spans to {}: {}", otlp.endpoint, err);
+                    None
+                }
+            }
+        }));
+
         // The handle controls the logging system at runtime.
         let handle = Handle {
             level: Some(level::Handle::new(level)),
+            uptime,
             #[cfg(feature = "stream")]
             stream,
         };
@@ -244,6 +290,7 @@ impl Handle {
     pub fn disabled() -> Self {
         Self {
             level: None,
+            uptime: Uptime::starting_now(),
             #[cfg(feature = "stream")]
             stream: stream::StreamHandle::new().0,
         }
@@ -253,6 +300,23 @@ impl Handle {
         self.level.as_ref()
     }
 
+    /// Returns how long the process has been running.
+    pub fn uptime(&self) -> Duration {
+        self.uptime.elapsed()
+    }
+
+    /// Returns the handle starting new log streams.
+    #[cfg(feature = "stream")]
+    pub fn stream(&self) -> &stream::StreamHandle<LogStack> {
+        &self.stream
+    }
+
+    /// Exports the spans still buffered, once requests were drained.
+    pub fn shutdown(&self) {
+        #[cfg(feature = "otel")]
+        otel::shutdown();
+    }
+
     #[cfg(feature = "stream")]
     pub fn into_stream(self) -> stream::StreamHandle<LogStack> {
         self.stream
@@ -0,0 +1,149 @@
+//! Exports spans over OTLP/gRPC and propagates their context across
+//! processes with the W3C `traceparent` header.
+//!
+//! Without the `otel` feature, or until an exporter or `install_propagator`
+//! installed the propagator, the propagation functions do nothing.
+use tracing::Span;
+
+#[cfg(feature = "otel")]
+pub(super) use self::export::{build, shutdown};
+
+/// Installs the W3C TraceContext propagator used by `inject` and
+/// `set_parent`, replacing the propagator installed before.
+#[cfg(feature = "otel")]
+pub fn install_propagator() {
+    opentelemetry::global::set_text_map_propagator(opentelemetry_sdk::propagation::TraceContextPropagator::new());
+}
+
+/// Continues the trace of a remote caller from the `traceparent` of its
+/// request `headers` in `span`.
+pub fn set_parent(span: &Span, headers: &http::HeaderMap) {
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::global;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let cx = global::get_text_map_propagator(|propagator| propagator.extract(&Extractor(headers)));
+        span.set_parent(cx);
+    }
+    #[cfg(not(feature = "otel"))]
+    let _ = (span, headers);
+}
+
+/// Sets the `traceparent` of an outgoing request to the current span.
+pub fn inject(headers: &mut http::HeaderMap) {
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::global;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let cx = Span::current().context();
+        global::get_text_map_propagator(|propagator| propagator.inject_context(&cx, &mut Injector(headers)));
+    }
+    #[cfg(not(feature = "otel"))]
+    let _ = headers;
+}
+
+#[cfg(feature = "otel")]
+struct Extractor<'a>(&'a http::HeaderMap);
+
+#[cfg(feature = "otel")]
+impl opentelemetry::propagation::Extractor for Extractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(feature = "otel")]
+struct Injector<'a>(&'a mut http::HeaderMap);
+
+#[cfg(feature = "otel")]
+impl opentelemetry::propagation::Injector for Injector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        let name = http::header::HeaderName::from_bytes(key.as_bytes());
+        let value = http::HeaderValue::from_str(&value);
+        if let (Ok(name), Ok(value)) = (name, value) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+#[cfg(feature = "otel")]
+mod export {
+    use opentelemetry::{global, KeyValue};
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{runtime, trace, Resource};
+    use protolith_error::Error;
+    use tracing::Subscriber;
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::{filter::Filtered, registry::LookupSpan, EnvFilter, Layer};
+
+    /// The `service.name` of the exported spans.
+    const SERVICE_NAME: &str = "protolith-db";
+
+    pub(crate) type OtelLayer<S> = Filtered<OpenTelemetryLayer<S, trace::Tracer>, EnvFilter, S>;
+
+    /// Builds a layer exporting the spans enabled by `filter` in batches to
+    /// the OTLP/gRPC collector at `endpoint`, the spawned exporter needs a
+    /// Tokio runtime.
+    pub(crate) fn build<S>(endpoint: &str, filter: &str) -> Result<OtelLayer<S>, Error>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        super::install_propagator();
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+            .with_trace_config(
+                trace::config().with_resource(Resource::new([KeyValue::new("service.name", SERVICE_NAME)])),
+            )
+            .install_batch(runtime::Tokio)?;
+        let filter = crate::level::filter_builder()
+            .parse_lossy(filter)
+            .add_directive(format!("{}=off", crate::access_log::TRACE_TARGET).parse().unwrap());
+        Ok(tracing_opentelemetry::layer().with_tracer(tracer).with_filter(filter))
+    }
+
+    /// Exports the spans still buffered, blocking until they are sent.
+    pub(crate) fn shutdown() {
+        global::shutdown_tracer_provider();
+    }
+}
+
+#[cfg(all(test, feature = "otel"))]
+mod tests {
+    use super::*;
+    use opentelemetry::{global, trace::TracerProvider as _};
+    use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::TracerProvider};
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn propagates_traceparent() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        // Tracers stop recording once their provider is dropped.
+        let provider = TracerProvider::builder().build();
+        let tracer = provider.tracer("test");
+        let subscriber = tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+        let mut incoming = http::HeaderMap::new();
+        incoming.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap(),
+        );
+        let mut outgoing = http::HeaderMap::new();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request");
+            set_parent(&span, &incoming);
+            let _enter = span.enter();
+            inject(&mut outgoing);
+        });
+        let traceparent = outgoing["traceparent"].to_str().unwrap();
+        // The same trace, from the span of the request.
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+    }
+}
@@ -2,7 +2,10 @@ use std::fmt;
 use tokio::time::{Duration, Instant};
 use tracing_subscriber::fmt::{format, time::FormatTime};
 
-pub(crate) struct Uptime {
+/// The time elapsed since the process started, which log lines are stamped
+/// with.
+#[derive(Clone, Copy, Debug)]
+pub struct Uptime {
     start_time: Instant,
 }
 
@@ -17,6 +20,10 @@ impl Uptime {
         Self { start_time }
     }
 
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().saturating_duration_since(self.start_time)
+    }
+
     fn format(d: Duration, w: &mut impl fmt::Write) -> fmt::Result {
         let micros = d.subsec_micros();
         write!(w, "[{:>6}.{:06}s]", d.as_secs(), micros)
@@ -25,7 +32,7 @@ impl Uptime {
 
 impl FormatTime for Uptime {
     fn format_time(&self, w: &mut format::Writer<'_>) -> fmt::Result {
-        Self::format(Instant::now().saturating_duration_since(self.start_time), w)
+        Self::format(self.elapsed(), w)
     }
 }
 